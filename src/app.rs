@@ -1,21 +1,28 @@
 use anyhow::Result;
 use crossterm::{
-    event::{self, DisableMouseCapture, EnableMouseCapture, Event, KeyCode, KeyModifiers},
+    event::{
+        self, DisableMouseCapture, EnableMouseCapture, Event, KeyCode, KeyModifiers,
+        KeyboardEnhancementFlags, MouseButton, MouseEvent, MouseEventKind, PopKeyboardEnhancementFlags, PushKeyboardEnhancementFlags,
+    },
     execute,
     terminal::{disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen},
 };
 use ratatui::{backend::CrosstermBackend, Terminal};
-use rand;
+use std::collections::HashMap;
 use std::io;
 use std::path::PathBuf;
 use std::time::{Duration, Instant};
 
 use crate::{
-    audio::{AudioEngine, Recording, RecordingEventType},
+    audio::{AudioEngine, Recording},
+    audio_effects::EffectParam,
+    metronome::{MetronomeParam, TapTempo},
     config::Config,
+    drums::DRUM_CHANNEL,
     effects::VisualEffects,
     file_dialog::FileDialog,
-    midi::{MidiPlayer, MidiRecorder},
+    midi::{MidiEvent, MidiPlayer, MidiRecorder},
+    mixer::Mixer,
     piano::Piano,
     ui::UI,
 };
@@ -31,17 +38,68 @@ pub struct App {
     pub debug_mode: bool,
     pub should_quit: bool,
     pub last_update: Instant,
+    // Notes held from the computer keyboard and when they were last pressed
+    pub held_keys: HashMap<u8, Instant>,
+    pub key_release_events: bool,
+    tap_tempo: TapTempo,
+    // The MIDI player's tempo and time signature the metronome is following
+    metronome_lock: Option<(f32, u8, u8)>,
+    // Whether the metronome is clicking a count-in rather than by choice
+    counting_in: bool,
+    // Mute, solo and volume for the loaded MIDI file's parts
+    mixer: Mixer,
 }
 
+// Without key release events a note is let go this long after its last
+// press (auto-repeat keeps refreshing it while the key is held down)
+const KEY_HOLD_TIMEOUT: Duration = Duration::from_millis(300);
+// Computer keys carry no velocity, so they always play at full strength
+const KEYBOARD_VELOCITY: u8 = 127;
+// Presses of a held key closer together than this are auto-repeat
+const KEY_REPEAT_WINDOW: Duration = Duration::from_millis(100);
+// How far ahead of the playback position MIDI file events go to the synth.
+// Longer than the slowest pass through the update loop.
+const SCHEDULE_AHEAD: Duration = Duration::from_millis(250);
+// How far the arrow keys jump through a MIDI file
+const SEEK_STEP_SECONDS: f64 = 5.0;
+// How much `<` and `>` change the playback rate
+const RATE_STEP: f64 = 0.05;
+
 impl App {
-    pub async fn new(debug_mode: bool) -> Result<Self> {
+    pub async fn new(debug_mode: bool, no_audio: bool) -> Result<Self> {
         let config = Config::load()?;
-        let audio_engine = AudioEngine::new()?;
+        let mut audio_engine = if no_audio {
+            AudioEngine::null(&config.audio)
+        } else {
+            match AudioEngine::new(&config.audio) {
+                Ok(engine) => engine,
+                Err(e) => {
+                    eprintln!("Failed to initialize audio: {}", e);
+                    AudioEngine::null(&config.audio)
+                }
+            }
+        };
         let piano = Piano::new();
-        let midi_player = MidiPlayer::new();
+        let mut midi_player = MidiPlayer::new();
+        midi_player.count_in = config.midi.count_in;
+        midi_player.loop_speed_up = (config.midi.loop_speed_up.max(0.0) / 100.0) as f64;
         let midi_recorder = MidiRecorder::new();
         let visual_effects = VisualEffects::new();
-        let ui = UI::new();
+        let mut ui = UI::new();
+        
+        if !audio_engine.is_audio_enabled() {
+            eprintln!("Audio is off: running without sound.");
+            ui.set_status_message("Audio off - running without sound".to_string());
+        } else if let Some(path) = &config.audio.sound_font {
+            match audio_engine.load_sound_font(path, config.audio.sound_font_bank, config.audio.sound_font_preset) {
+                Ok(name) => ui.set_status_message(format!("SoundFont: {}", name)),
+                Err(e) => ui.set_status_message(format!("SoundFont unavailable ({}), using built-in synth", e)),
+            }
+        }
+        audio_engine.set_metronome(config.metronome);
+        if let Err(e) = audio_engine.set_tuning(&config.audio.tuning) {
+            ui.set_status_message(format!("Tuning unavailable ({}), using equal temperament", e));
+        }
 
         Ok(Self {
             piano,
@@ -54,6 +112,12 @@ impl App {
             debug_mode,
             should_quit: false,
             last_update: Instant::now(),
+            held_keys: HashMap::new(),
+            key_release_events: false,
+            tap_tempo: TapTempo::new(),
+            metronome_lock: None,
+            counting_in: false,
+            mixer: Mixer::default(),
         })
     }
 
@@ -61,11 +125,21 @@ impl App {
         enable_raw_mode()?;
         let mut stdout = io::stdout();
         execute!(stdout, EnterAlternateScreen, EnableMouseCapture)?;
+        
+        // Ask for key release events so held keys sustain until let go
+        self.key_release_events = crossterm::terminal::supports_keyboard_enhancement().unwrap_or(false);
+        if self.key_release_events {
+            execute!(stdout, PushKeyboardEnhancementFlags(KeyboardEnhancementFlags::REPORT_EVENT_TYPES))?;
+        }
+        
         let backend = CrosstermBackend::new(stdout);
         let mut terminal = Terminal::new(backend)?;
 
         let result = self.run_app(&mut terminal).await;
 
+        if self.key_release_events {
+            execute!(terminal.backend_mut(), PopKeyboardEnhancementFlags)?;
+        }
         disable_raw_mode()?;
         execute!(
             terminal.backend_mut(),
@@ -91,8 +165,8 @@ impl App {
                 .unwrap_or_else(|| Duration::from_secs(0));
 
             if event::poll(timeout)? {
-                if let Event::Key(key) = event::read()? {
-                    match key.kind {
+                match event::read()? {
+                    Event::Key(key) => match key.kind {
                         crossterm::event::KeyEventKind::Press => {
                             self.handle_key_event(key).await?;
                         }
@@ -100,7 +174,9 @@ impl App {
                             self.handle_key_release(key).await?;
                         }
                         _ => {}
-                    }
+                    },
+                    Event::Mouse(mouse) => self.handle_mouse_event(mouse)?,
+                    _ => {}
                 }
             }
 
@@ -123,6 +199,18 @@ impl App {
             self.ui.toggle_help();
             return Ok(());
         }
+        
+        // The effects panel takes the arrow keys and Enter; everything else
+        // still reaches the piano
+        if self.ui.show_effects && self.handle_effects_key(key.code) {
+            return Ok(());
+        }
+        if self.ui.show_metronome && self.handle_metronome_key(key.code) {
+            return Ok(());
+        }
+        if self.ui.show_mixer && self.handle_mixer_key(key.code) {
+            return Ok(());
+        }
 
         // Debug ALL key presses
         use std::io::Write;
@@ -143,6 +231,65 @@ impl App {
             (KeyCode::F(1), KeyModifiers::NONE) => {
                 self.ui.toggle_help();
             }
+            (KeyCode::F(2), KeyModifiers::NONE) => {
+                self.ui.show_metronome = false;
+                self.ui.show_mixer = false;
+                self.ui.toggle_effects();
+            }
+            (KeyCode::F(3), KeyModifiers::NONE) => {
+                self.ui.show_effects = false;
+                self.ui.show_mixer = false;
+                self.ui.toggle_metronome_panel();
+            }
+            (KeyCode::F(5), KeyModifiers::NONE) => {
+                self.ui.show_effects = false;
+                self.ui.show_metronome = false;
+                self.ui.show_mixer = !self.ui.show_mixer;
+            }
+            (KeyCode::F(4), KeyModifiers::NONE) => {
+                match self.tap_tempo.tap(Instant::now()) {
+                    Some(bpm) => {
+                        self.config.metronome.bpm = bpm.round();
+                        self.audio_engine.set_metronome(self.config.metronome);
+                        self.ui.set_status_message(format!("Tap tempo: {:.0} BPM", bpm));
+                    }
+                    None => self.ui.set_status_message("Tap tempo: keep tapping F4".to_string()),
+                }
+            }
+            (KeyCode::Left, KeyModifiers::NONE) => {
+                let state = self.midi_player.seek_by(-SEEK_STEP_SECONDS);
+                self.apply_seek(state)?;
+            }
+            (KeyCode::Right, KeyModifiers::NONE) => {
+                let state = self.midi_player.seek_by(SEEK_STEP_SECONDS);
+                self.apply_seek(state)?;
+            }
+            (KeyCode::Left, KeyModifiers::SHIFT) => {
+                let state = self.midi_player.seek_bars(-1);
+                self.apply_seek(state)?;
+            }
+            (KeyCode::Right, KeyModifiers::SHIFT) => {
+                let state = self.midi_player.seek_bars(1);
+                self.apply_seek(state)?;
+            }
+            (KeyCode::Char('<'), _) => self.step_playback_rate(-1),
+            (KeyCode::Char('>'), _) => self.step_playback_rate(1),
+            (KeyCode::Char('{'), _) => {
+                self.midi_player.set_loop_start(self.midi_player.current_position);
+                self.loop_changed()?;
+            }
+            (KeyCode::Char('}'), _) => {
+                self.midi_player.set_loop_end(self.midi_player.current_position);
+                self.loop_changed()?;
+            }
+            (KeyCode::Char('|'), _) => {
+                self.midi_player.set_loop(!self.midi_player.is_loop_enabled());
+                self.loop_changed()?;
+            }
+            (KeyCode::Char('\\'), _) => {
+                self.midi_player.clear_loop_markers();
+                self.loop_changed()?;
+            }
             (KeyCode::Char('['), KeyModifiers::NONE) => {
                 self.piano.adjust_volume(-0.1);
                 self.audio_engine.set_volume(self.piano.volume);
@@ -155,7 +302,7 @@ impl App {
             }
             (KeyCode::Char('-'), KeyModifiers::NONE) => {
                 if let Some(midi_note) = self.piano.get_midi_note_from_key('-') {
-                    self.play_note(midi_note).await?;
+                    self.play_note(midi_note, KEYBOARD_VELOCITY).await?;
                 }
             }
             (KeyCode::Char('+'), KeyModifiers::NONE) => {
@@ -164,7 +311,7 @@ impl App {
             }
             (KeyCode::Char('='), KeyModifiers::NONE) => {
                 if let Some(midi_note) = self.piano.get_midi_note_from_key('=') {
-                    self.play_note(midi_note).await?;
+                    self.play_note(midi_note, KEYBOARD_VELOCITY).await?;
                 }
             }
             (KeyCode::Char('_'), KeyModifiers::NONE) => {
@@ -172,7 +319,7 @@ impl App {
                 self.ui.set_status_message(format!("Octave: {}", self.piano.current_octave));
             }
             (KeyCode::Char(' '), KeyModifiers::NONE) => {
-                self.piano.toggle_sustain();
+                self.set_sustain_pedal(!self.piano.sustain_pedal);
                 self.midi_recorder.record_sustain_pedal(self.piano.sustain_pedal);
                 self.ui.set_status_message(format!("Sustain: {}", if self.piano.sustain_pedal { "ON" } else { "OFF" }));
             }
@@ -215,6 +362,12 @@ impl App {
                     let was_playing = self.midi_player.is_playing;
                     
                     self.midi_player.toggle_playback();
+                    if self.midi_player.is_playing {
+                        self.audio_engine.sync_clock();
+                        self.resume_held_notes()?;
+                    } else {
+                        self.silence_midi_playback();
+                    }
                     
                     let status = if self.midi_player.is_playing { "Playing" } else { "Paused" };
                     
@@ -233,12 +386,18 @@ impl App {
                 }
             }
             (KeyCode::Char('m'), KeyModifiers::NONE) => {
-                self.ui.metronome = !self.ui.metronome;
-                self.ui.set_status_message(format!("Metronome: {}", if self.ui.metronome { "ON" } else { "OFF" }));
+                self.toggle_metronome();
             }
             (KeyCode::Char('M'), KeyModifiers::NONE) => {
-                self.ui.metronome = !self.ui.metronome;
-                self.ui.set_status_message(format!("Metronome: {}", if self.ui.metronome { "ON" } else { "OFF" }));
+                self.toggle_metronome();
+            }
+            (KeyCode::Tab, _) => {
+                let name = self.audio_engine.cycle_instrument(1).to_string();
+                self.ui.set_status_message(format!("Instrument: {}", name));
+            }
+            (KeyCode::BackTab, _) => {
+                let name = self.audio_engine.cycle_instrument(-1).to_string();
+                self.ui.set_status_message(format!("Instrument: {}", name));
             }
             (KeyCode::Char('l'), KeyModifiers::NONE) => {
                 self.load_midi_file_dialog().await?;
@@ -248,12 +407,12 @@ impl App {
             }
             (KeyCode::Char(c), KeyModifiers::NONE) => {
                 if let Some(midi_note) = self.piano.get_midi_note_from_key(c) {
-                    self.play_note(midi_note).await?;
+                    self.play_note(midi_note, KEYBOARD_VELOCITY).await?;
                 }
             }
             (KeyCode::Char(c), KeyModifiers::SHIFT) => {
                 if let Some(midi_note) = self.piano.get_midi_note_from_key(c) {
-                    self.play_note(midi_note).await?;
+                    self.play_note(midi_note, KEYBOARD_VELOCITY).await?;
                 }
             }
             _ => {}
@@ -262,23 +421,132 @@ impl App {
         Ok(())
     }
 
+    fn handle_effects_key(&mut self, code: KeyCode) -> bool {
+        let params = EffectParam::ALL;
+        let selected = params[self.ui.effects_selection.min(params.len() - 1)];
+        let mut effects = self.audio_engine.effects().clone();
+        match code {
+            KeyCode::Up => {
+                self.ui.effects_selection = (self.ui.effects_selection + params.len() - 1) % params.len();
+                return true;
+            }
+            KeyCode::Down => {
+                self.ui.effects_selection = (self.ui.effects_selection + 1) % params.len();
+                return true;
+            }
+            KeyCode::Left => selected.adjust(&mut effects, -1),
+            KeyCode::Right => selected.adjust(&mut effects, 1),
+            KeyCode::Enter => selected.toggle_effect(&mut effects),
+            KeyCode::Esc | KeyCode::F(2) => {
+                self.ui.show_effects = false;
+                self.save_effects();
+                return true;
+            }
+            _ => return false,
+        }
+        self.audio_engine.set_effects(effects);
+        true
+    }
+
+    // Keeps effect changes made in the panel for the next session
+    fn save_effects(&mut self) {
+        if self.config.audio.effects == *self.audio_engine.effects() {
+            return;
+        }
+        self.config.audio.effects = self.audio_engine.effects().clone();
+        match self.config.save() {
+            Ok(()) => self.ui.set_status_message("Effects saved".to_string()),
+            Err(e) => self.ui.set_status_message(format!("Could not save effects: {}", e)),
+        }
+    }
+
+    fn handle_metronome_key(&mut self, code: KeyCode) -> bool {
+        let params = MetronomeParam::ALL;
+        let selected = params[self.ui.metronome_selection.min(params.len() - 1)];
+        match code {
+            KeyCode::Up => {
+                self.ui.metronome_selection = (self.ui.metronome_selection + params.len() - 1) % params.len();
+            }
+            KeyCode::Down => {
+                self.ui.metronome_selection = (self.ui.metronome_selection + 1) % params.len();
+            }
+            KeyCode::Left => selected.adjust(&mut self.config.metronome, -1),
+            KeyCode::Right => selected.adjust(&mut self.config.metronome, 1),
+            KeyCode::Enter => self.toggle_metronome(),
+            KeyCode::Esc | KeyCode::F(3) => {
+                self.ui.show_metronome = false;
+                match self.config.save() {
+                    Ok(()) => self.ui.set_status_message("Metronome settings saved".to_string()),
+                    Err(e) => self.ui.set_status_message(format!("Could not save metronome settings: {}", e)),
+                }
+            }
+            _ => return false,
+        }
+        self.audio_engine.set_metronome(self.config.metronome);
+        true
+    }
+
+    // Mute (m or Enter), solo (s) and volume (Left/Right) for the selected part
+    fn handle_mixer_key(&mut self, code: KeyCode) -> bool {
+        let count = self.mixer.strips.len().max(1);
+        let selection = self.ui.mixer_selection.min(count - 1);
+        match code {
+            KeyCode::Up => self.ui.mixer_selection = (selection + count - 1) % count,
+            KeyCode::Down => self.ui.mixer_selection = (selection + 1) % count,
+            KeyCode::Esc | KeyCode::F(5) => self.ui.show_mixer = false,
+            KeyCode::Left | KeyCode::Right | KeyCode::Enter | KeyCode::Char('m') | KeyCode::Char('s') => {
+                let Some(strip) = self.mixer.strips.get_mut(selection) else {
+                    return true;
+                };
+                match code {
+                    KeyCode::Left => strip.adjust_volume(-1),
+                    KeyCode::Right => strip.adjust_volume(1),
+                    KeyCode::Char('s') => strip.solo = !strip.solo,
+                    _ => strip.muted = !strip.muted,
+                }
+                self.save_mixer();
+            }
+            _ => return false,
+        }
+        true
+    }
+
+    fn save_mixer(&mut self) {
+        let Some(song) = self.midi_player.current_file.clone() else {
+            return;
+        };
+        if let Err(e) = Config::mixer_path().and_then(|settings| self.mixer.store(&settings, &song)) {
+            self.ui.set_status_message(format!("Could not save mixer settings: {}", e));
+        }
+    }
+
+    fn toggle_metronome(&mut self) {
+        self.ui.metronome = !self.ui.metronome;
+        self.audio_engine.set_metronome_enabled(self.ui.metronome || self.counting_in);
+        self.ui.set_status_message(format!("Metronome: {}", if self.ui.metronome { "ON" } else { "OFF" }));
+    }
+
     async fn handle_key_release(&mut self, key: crossterm::event::KeyEvent) -> Result<()> {
         // Only handle piano key releases, not control keys
-        match key.code {
-            KeyCode::Char(c) => {
-                if let Some(midi_note) = self.piano.get_midi_note_from_key(c) {
-                    self.release_note(midi_note).await?;
-                }
+        if let KeyCode::Char(c) = key.code {
+            if let Some(midi_note) = self.piano.get_midi_note_from_key(c) {
+                self.release_note(midi_note).await?;
             }
-            _ => {}
         }
         Ok(())
     }
 
-    async fn play_note(&mut self, midi_note: u8) -> Result<()> {
+    async fn play_note(&mut self, midi_note: u8, velocity: u8) -> Result<()> {
+        let now = Instant::now();
+        if let Some(last_press) = self.held_keys.insert(midi_note, now) {
+            if now.duration_since(last_press) < KEY_REPEAT_WINDOW {
+                return Ok(());
+            }
+        }
+        
         self.piano.press_key(midi_note);
-        self.audio_engine.play_note(midi_note)?;
-        self.midi_recorder.record_note_on(midi_note, 127);
+        self.audio_engine.play_note(midi_note, velocity)?;
+        self.midi_recorder.record_note_on(midi_note, velocity);
         
         let (x, y) = self.get_key_position(midi_note);
         self.visual_effects.add_key_press(midi_note, x, y);
@@ -286,9 +554,9 @@ impl App {
         Ok(())
     }
 
+    // Visuals for a note from the MIDI player; the engine plays the sound
     async fn play_midi_note(&mut self, midi_note: u8, velocity: u8) -> Result<()> {
         self.piano.press_key(midi_note);
-        self.audio_engine.play_note(midi_note)?;
         
         let (x, y) = self.get_key_position(midi_note);
         
@@ -299,7 +567,7 @@ impl App {
         // More particles for louder notes (higher velocity)
         let particle_count = 3 + (velocity / 32) as usize; // 3-6 particles based on velocity
         
-        for i in 0..particle_count {
+        for _ in 0..particle_count {
             let offset_x = x + (rand::random::<u16>() % 8).saturating_sub(4); // Spread around key
             let offset_y = y.saturating_sub(rand::random::<u16>() % 3); // Slightly above key
             self.visual_effects.add_key_press(midi_note, offset_x, offset_y);
@@ -318,12 +586,19 @@ impl App {
     }
 
     async fn release_note(&mut self, midi_note: u8) -> Result<()> {
+        self.held_keys.remove(&midi_note);
         self.piano.release_key(midi_note);
         self.audio_engine.stop_note(midi_note);
         self.midi_recorder.record_note_off(midi_note);
         Ok(())
     }
 
+    // Shared by the space bar and MIDI CC64
+    fn set_sustain_pedal(&mut self, pressed: bool) {
+        self.piano.set_sustain(pressed);
+        self.audio_engine.set_sustain_pedal(pressed);
+    }
+
     fn get_key_position(&self, midi_note: u8) -> (u16, u16) {
         // Calculate key position based on MIDI note for better visual effects
         let note_in_octave = midi_note % 12;
@@ -356,24 +631,75 @@ impl App {
         self.last_update = now;
 
         self.visual_effects.update();
-        self.audio_engine.cleanup_finished_notes();
         self.piano.update(); // Auto-release keys after timeout
+        
+        if !self.key_release_events {
+            let expired: Vec<u8> = self.held_keys
+                .iter()
+                .filter(|(_, &pressed)| now.duration_since(pressed) >= KEY_HOLD_TIMEOUT)
+                .map(|(&midi_note, _)| midi_note)
+                .collect();
+            for midi_note in expired {
+                self.release_note(midi_note).await?;
+            }
+        }
 
+        let was_playing = self.midi_player.is_playing;
         let pending_midi_events = self.midi_player.get_pending_events();
+        if was_playing && !self.midi_player.is_playing {
+            // Reached the end; anything scheduled past it must not sound
+            self.silence_midi_playback();
+        }
+        // The synth hears about events ahead of time, stamped with the frame
+        // they play on; the display below catches up as they come due
+        for (when, event) in self.midi_player.schedule_ahead(SCHEDULE_AHEAD) {
+            // Muted parts still light up the keys, to play along with
+            if let Some(message) = self.mixer.apply(event.track, event.channel, &event.event) {
+                self.audio_engine.schedule_midi_message(when, event.channel, &message)?;
+            }
+        }
+        
+        // While a file plays the metronome follows its tempo and beats
+        // and picks up tempo changes as they pass
+        let (beats_per_bar, beat_unit) = self.midi_player.time_signature();
+        let lock = self.midi_player.is_playing.then(|| (self.midi_player.bpm(), beats_per_bar, beat_unit));
+        if lock != self.metronome_lock {
+            self.metronome_lock = lock;
+            self.audio_engine.lock_metronome(lock);
+        }
+        // A count-in clicks whether or not the metronome is on
+        let counting_in = self.midi_player.count_in_remaining().is_some();
+        if counting_in != self.counting_in {
+            self.counting_in = counting_in;
+            self.audio_engine.set_metronome_enabled(self.ui.metronome || counting_in);
+        }
+        if self.metronome_lock.is_some() && (self.ui.metronome || counting_in) {
+            self.audio_engine.sync_metronome(self.midi_player.beat_position());
+        }
         if !pending_midi_events.is_empty() && self.debug_mode {
             self.ui.set_status_message(format!("Processing {} MIDI events", pending_midi_events.len()));
         }
         for event in pending_midi_events {
-            match event {
-                midly::MidiMessage::NoteOn { key, vel } => {
+            if event.channel == DRUM_CHANNEL {
+                if let midly::MidiMessage::NoteOn { key, vel } = event.event {
                     if vel.as_int() > 0 {
-                        self.play_midi_note(key.as_int(), vel.as_int()).await?;
-                    } else {
-                        self.release_note(key.as_int()).await?;
+                        self.visual_effects.add_drum_hit(key.as_int(), vel.as_int());
                     }
                 }
-                midly::MidiMessage::NoteOff { key, vel: _ } => {
-                    self.release_note(key.as_int()).await?;
+                continue;
+            }
+            match event.event {
+                midly::MidiMessage::NoteOn { key, vel } if vel.as_int() > 0 => {
+                    self.play_midi_note(key.as_int(), vel.as_int()).await?;
+                }
+                midly::MidiMessage::NoteOn { key, .. } | midly::MidiMessage::NoteOff { key, .. } => {
+                    self.piano.release_key(key.as_int());
+                }
+                midly::MidiMessage::Controller { controller, value } if controller.as_int() == 64 => {
+                    self.piano.set_sustain(value.as_int() >= 64);
+                }
+                midly::MidiMessage::Controller { controller, value } if controller.as_int() == 67 => {
+                    self.piano.soft_pedal = value.as_int() >= 64;
                 }
                 _ => {}
             }
@@ -387,11 +713,139 @@ impl App {
     }
 
     fn render(&mut self, f: &mut ratatui::Frame) {
-        self.ui.render(f, &self.piano, &self.visual_effects, &self.midi_player, &self.audio_engine);
+        self.ui.render(f, &self.piano, &self.visual_effects, &self.midi_player, &self.audio_engine, &self.mixer);
+    }
+
+    fn step_playback_rate(&mut self, steps: i32) {
+        let rate = ((self.midi_player.rate() / RATE_STEP).round() + steps as f64) * RATE_STEP;
+        self.midi_player.set_rate(rate);
+        self.ui.set_status_message(format!("Playback speed: {:.0}%", self.midi_player.rate() * 100.0));
+    }
+
+    // Clicking the progress bar jumps to that point in the song; right
+    // clicks place the A and B loop markers
+    fn handle_mouse_event(&mut self, mouse: MouseEvent) -> Result<()> {
+        let Some(position) = self.ui.progress_position(mouse.column, mouse.row) else {
+            return Ok(());
+        };
+        match mouse.kind {
+            MouseEventKind::Down(MouseButton::Left) => {
+                let state = self.midi_player.seek_to_position(position);
+                self.apply_seek(state)?;
+            }
+            MouseEventKind::Down(MouseButton::Right) => {
+                let tick = (self.midi_player.total_ticks as f32 * position.clamp(0.0, 1.0)) as u64;
+                self.midi_player.mark_loop_point(tick);
+                self.loop_changed()?;
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    // After the loop markers or loop switch change: playback carries on from
+    // where it is, with what was scheduled under the old loop dropped
+    fn loop_changed(&mut self) -> Result<()> {
+        if self.midi_player.current_file.is_none() {
+            self.ui.set_status_message("No MIDI file loaded. Press 'L' to load a file.".to_string());
+            return Ok(());
+        }
+        if self.midi_player.is_playing {
+            // From past the end of a new loop, go round to its start
+            let tick = match self.midi_player.loop_markers {
+                (Some(a), Some(b)) if self.midi_player.loops_between_markers() && self.midi_player.current_position >= b => a,
+                _ => self.midi_player.current_position,
+            };
+            let state = self.midi_player.seek_to_tick(tick);
+            self.apply_seek(state)?;
+        }
+
+        let marker = |tick: Option<u64>| tick.map_or("--:--".to_string(), |tick| {
+            let time = self.midi_player.ticks_to_time(tick);
+            format!("{:02}:{:02}", time.as_secs() / 60, time.as_secs() % 60)
+        });
+        let (a, b) = self.midi_player.loop_markers;
+        let status = if !self.midi_player.is_loop_enabled() {
+            format!("Loop: OFF (A {} B {})", marker(a), marker(b))
+        } else if self.midi_player.loops_between_markers() {
+            format!("Loop: A {} - B {}", marker(a), marker(b))
+        } else {
+            format!("Loop: whole song (A {} B {})", marker(a), marker(b))
+        };
+        self.ui.set_status_message(status);
+        Ok(())
+    }
+
+    // After the MIDI player has jumped, puts the synth and the keyboard in
+    // the state the song is in at the new position
+    fn apply_seek(&mut self, state: Vec<MidiEvent>) -> Result<()> {
+        if self.midi_player.current_file.is_none() {
+            return Ok(());
+        }
+        self.silence_midi_playback();
+        self.audio_engine.reset_channels();
+        self.piano.set_sustain(false);
+        self.piano.soft_pedal = false;
+        let playing = self.midi_player.is_playing;
+        for event in &state {
+            // Stopped or paused, held notes only show on the keyboard; they
+            // sound once playback resumes
+            let is_note = matches!(event.event, midly::MidiMessage::NoteOn { .. });
+            if playing || !is_note {
+                if let Some(message) = self.mixer.apply(event.track, event.channel, &event.event) {
+                    self.audio_engine.handle_midi_message(event.channel, &message)?;
+                }
+            }
+            match event.event {
+                midly::MidiMessage::NoteOn { key, .. } if event.channel != DRUM_CHANNEL => {
+                    self.piano.press_key(key.as_int());
+                }
+                midly::MidiMessage::Controller { controller, value } if controller.as_int() == 64 => {
+                    self.piano.set_sustain(value.as_int() >= 64);
+                }
+                midly::MidiMessage::Controller { controller, value } if controller.as_int() == 67 => {
+                    self.piano.soft_pedal = value.as_int() >= 64;
+                }
+                _ => {}
+            }
+        }
+        if self.midi_player.is_playing {
+            self.audio_engine.sync_clock();
+        }
+
+        let (current, _) = self.midi_player.get_time_info();
+        self.ui.set_status_message(format!("Position: {:02}:{:02}", current.as_secs() / 60, current.as_secs() % 60));
+        Ok(())
+    }
+
+    // Strikes the notes held at the playback position as playback starts,
+    // after any count-in
+    fn resume_held_notes(&mut self) -> Result<()> {
+        let start = Instant::now() + self.midi_player.count_in_remaining().unwrap_or_default();
+        for event in self.midi_player.held_notes() {
+            if let Some(message) = self.mixer.apply(event.track, event.channel, &event.event) {
+                self.audio_engine.schedule_midi_message(start, event.channel, &message)?;
+            }
+        }
+        Ok(())
+    }
+
+    // Stops what a MIDI file has sent the synth, scheduled or sounding
+    fn silence_midi_playback(&mut self) {
+        self.audio_engine.cancel_scheduled();
+        self.audio_engine.stop_all_notes();
     }
 
     pub async fn load_midi_file(&mut self, path: PathBuf) -> Result<()> {
+        self.silence_midi_playback();
         self.midi_player.load_file(&path)?;
+        // Programs and pan set by the previous file's messages no longer apply
+        self.audio_engine.reset_channels();
+        self.mixer = Mixer::new(self.midi_player.mixer_strips());
+        if let Ok(settings) = Config::mixer_path() {
+            self.mixer.restore(&settings, &path);
+        }
+        self.ui.mixer_selection = 0;
         self.ui.set_status_message(format!("Loaded: {}", path.file_name().unwrap_or_default().to_string_lossy()));
         Ok(())
     }
@@ -421,7 +875,7 @@ impl App {
             let mut recordings: Vec<_> = entries
                 .filter_map(|entry| entry.ok())
                 .filter(|entry| {
-                    entry.path().extension().map_or(false, |ext| ext == "json")
+                    entry.path().extension().is_some_and(|ext| ext == "json")
                 })
                 .collect();
             
@@ -429,9 +883,12 @@ impl App {
                 entry.metadata().unwrap().modified().unwrap_or(std::time::SystemTime::UNIX_EPOCH)
             });
             
+            // Recordings load into the MIDI player like any file, so they
+            // get the same transport, progress bar and effects
             if let Some(last_recording) = recordings.last() {
-                let recording = Recording::load_from_file(&last_recording.path())?;
-                self.playback_recording(recording).await?;
+                self.load_midi_file(last_recording.path()).await?;
+                self.midi_player.play();
+                self.audio_engine.sync_clock();
                 self.ui.set_status_message("Playing back last recording".to_string());
             } else {
                 self.ui.set_status_message("No recordings found".to_string());
@@ -443,42 +900,53 @@ impl App {
         Ok(())
     }
 
-    async fn playback_recording(&mut self, recording: Recording) -> Result<()> {
-        let start_time = Instant::now();
-        
-        for event in recording.events {
-            let target_time = start_time + event.timestamp;
-            let now = Instant::now();
-            
-            if target_time > now {
-                tokio::time::sleep(target_time - now).await;
-            }
-            
-            match event.event_type {
-                RecordingEventType::NoteOn { midi_note, velocity: _ } => {
-                    self.play_note(midi_note).await?;
-                }
-                RecordingEventType::NoteOff { midi_note } => {
-                    self.release_note(midi_note).await?;
-                }
-                RecordingEventType::SustainPedal { pressed } => {
-                    if pressed != self.piano.sustain_pedal {
-                        self.piano.toggle_sustain();
-                    }
-                }
-            }
-        }
-        
-        Ok(())
-    }
-
     pub fn show_config(&self) -> Result<()> {
         println!("Terminal Piano Configuration:");
         println!("Audio:");
         println!("  Sample Rate: {} Hz", self.config.audio.sample_rate);
         println!("  Buffer Size: {}", self.config.audio.buffer_size);
+        println!("  Render Bit Depth: {}", self.config.audio.bit_depth);
         println!("  Volume: {:.0}%", self.config.audio.volume * 100.0);
         println!("  Sound Font: {:?}", self.config.audio.sound_font);
+        if self.config.audio.sound_font.is_some() {
+            println!("  Sound Font Preset: bank {} program {}", self.config.audio.sound_font_bank, self.config.audio.sound_font_preset);
+        }
+        println!("  Instrument: {}", self.config.audio.instrument);
+        if let Some(instrument) = crate::instruments::builtin_instrument(&self.config.audio.instrument) {
+            let envelope = self.config.audio.envelope_for(instrument.as_ref());
+            println!("  Envelope: A {:.2}s  D {:.2}s  S {:.0}%  R {:.2}s",
+                envelope.attack, envelope.decay, envelope.sustain * 100.0, envelope.release);
+        }
+        println!("  Velocity Curve: {}", self.config.audio.velocity_curve.name());
+        let tuning = &self.config.audio.tuning;
+        println!("  Tuning: {} (A4 = {} Hz, root {})", tuning.system.name(), tuning.reference, tuning.root);
+        if let Some(path) = &tuning.scala_file {
+            println!("  Scala File: {}", path);
+        }
+        if let Some(path) = &tuning.keyboard_map {
+            println!("  Keyboard Map: {}", path);
+        }
+        println!("  Stereo Width: {:.0}%", self.config.audio.stereo_width * 100.0);
+        println!("  Pitch Bend Range: {} semitones", self.config.audio.pitch_bend_range);
+        println!("  Polyphony: {} voices, stealing {}", self.config.audio.max_polyphony, self.config.audio.voice_stealing.name());
+        let effects = &self.config.audio.effects;
+        let mut current_effect = "";
+        for param in EffectParam::ALL {
+            if param.effect_name() != current_effect {
+                current_effect = param.effect_name();
+                let state = if param.is_effect_enabled(effects) { "on" } else { "off" };
+                println!("  {}: {}", current_effect, state);
+            }
+            println!("    {}: {}", param.label(), param.value(effects));
+        }
+        
+        let metronome = &self.config.metronome;
+        println!("Metronome:");
+        println!("  Tempo: {:.0} BPM", metronome.bpm);
+        println!("  Time Signature: {}/{}", metronome.beats_per_bar, metronome.beat_unit);
+        println!("  Subdivision: {}", metronome.subdivision);
+        println!("  Accent: {}", metronome.accent);
+        println!("  Volume: {:.0}%", metronome.volume * 100.0);
         
         println!("UI:");
         println!("  Color Scheme: {}", self.config.ui.color_scheme);
@@ -489,6 +957,14 @@ impl App {
         println!("MIDI:");
         println!("  Input Device: {}", self.config.midi.input_device);
         println!("  Output Device: {}", self.config.midi.output_device);
+        println!("  Count-in: {}", if self.config.midi.count_in { "ON" } else { "OFF" });
+        println!("  Loop Speed-up: {:.0}% per pass", self.config.midi.loop_speed_up);
+        
+        let export = &self.config.export;
+        println!("MIDI Export:");
+        println!("  Format: type {}", export.format);
+        println!("  Tempo: {:.0} BPM", export.tempo);
+        println!("  PPQ: {}", export.ppq);
         
         Ok(())
     }
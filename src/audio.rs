@@ -1,22 +1,73 @@
 use anyhow::Result;
-use rodio::{OutputStream, OutputStreamHandle, Sink, Source};
-use serde_json;
+use midly::MidiMessage;
+use rodio::{OutputStream, OutputStreamHandle};
 use std::collections::HashMap;
-use std::sync::{Arc, Mutex};
-use std::time::Duration;
-
-use crate::piano::Note;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::Sender;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use crate::config::{AudioConfig, EffectsConfig, MetronomeConfig, TuningConfig};
+use crate::drums::{DrumKit, DRUM_CHANNEL, PERCUSSION_BANK};
+use crate::instruments::{builtin_instruments, general_midi_instrument, Instrument};
+use crate::soundfont::SoundFont;
+use crate::synth::{ChannelPatch, Synth, SynthCommand};
+use crate::tuning::Tuning;
+
+// The channel notes played on the computer keyboard go to
+pub const LIVE_CHANNEL: u8 = 0;
+// The registered parameter number selecting pitch bend range
+const PITCH_BEND_RANGE_PARAMETER: (u8, u8) = (0, 0);
+// Deselects the registered parameter, so stray data entry is ignored
+const NULL_PARAMETER: (u8, u8) = (127, 127);
+// How far behind the wall clock scheduled messages sound, so the first ones
+// after a clock sync still reach the synth before their frame is rendered
+const SCHEDULE_LATENCY: Duration = Duration::from_millis(50);
+
+// Where the synth's output goes
+enum Backend {
+    // Real-time playback on the default sound device
+    Device {
+        _stream: OutputStream,
+        _stream_handle: OutputStreamHandle,
+    },
+    // Rendered on demand through `AudioEngine::render`, for bouncing to WAV
+    Offline(Box<Synth>),
+    // No sound at all, for machines without an audio device
+    Null,
+}
 
 pub struct AudioEngine {
-    _stream: OutputStream,
-    stream_handle: OutputStreamHandle,
-    sinks: Arc<Mutex<HashMap<u8, Sink>>>,
-    samples: HashMap<u8, Vec<u8>>,
+    backend: Backend,
+    commands: Sender<SynthCommand>,
+    // Frames the synth has rendered
+    clock: Arc<AtomicU64>,
+    // A wall-clock instant and the synth frame it maps to, set by `sync_clock`
+    clock_anchor: Option<(Instant, u64)>,
+    // The frame commands sent right now are scheduled for, if not immediate
+    scheduled_frame: Option<u64>,
     volume: f32,
+    config: AudioConfig,
+    builtin_instruments: Vec<Arc<dyn Instrument>>,
+    sound_font: Option<Arc<dyn Instrument>>,
+    // The whole loaded SoundFont, for MIDI program changes
+    sound_font_presets: Option<SoundFont>,
+    // Bank select (CC0) per MIDI channel, applied at the next program change
+    channel_banks: [u16; 16],
+    // Registered parameter (CC101/CC100) each channel's data entry goes to
+    channel_parameters: [(u8, u8); 16],
+    // Bend range set through RPN 0 per channel, in semitones
+    channel_bend_ranges: [f32; 16],
+    // Instruments already picked for a bank and program
+    program_patches: HashMap<(u16, u8), Arc<dyn Instrument>>,
+    current_instrument: Arc<dyn Instrument>,
+    tuning: Arc<Tuning>,
+    metronome: MetronomeConfig,
 }
 
 impl AudioEngine {
-    pub fn new() -> Result<Self> {
+    pub fn new(config: &AudioConfig) -> Result<Self> {
         let (stream, stream_handle) = match OutputStream::try_default() {
             Ok((s, h)) => (s, h),
             Err(e) => {
@@ -34,152 +85,396 @@ impl AudioEngine {
             }
         };
         
-        let mut engine = Self {
+        // One long-lived source mixes every voice; notes are sent to it as commands
+        let (synth, commands) = Synth::new(config.sample_rate);
+        let clock = synth.clock();
+        stream_handle.play_raw(synth)?;
+        
+        let backend = Backend::Device {
             _stream: stream,
-            stream_handle,
-            sinks: Arc::new(Mutex::new(HashMap::new())),
-            samples: HashMap::new(),
+            _stream_handle: stream_handle,
+        };
+        Ok(Self::with_backend(config, backend, commands, clock))
+    }
+    
+    // An engine that produces audio only when `render` is called, as fast as
+    // the machine allows, through the same synth as live playback
+    pub fn offline(config: &AudioConfig) -> Self {
+        let (synth, commands) = Synth::new(config.sample_rate);
+        let clock = synth.clock();
+        Self::with_backend(config, Backend::Offline(Box::new(synth)), commands, clock)
+    }
+    
+    // An engine that accepts every call and plays nothing. The synth is
+    // dropped straight away, so commands sent to it are simply discarded.
+    pub fn null(config: &AudioConfig) -> Self {
+        let (synth, commands) = Synth::new(config.sample_rate);
+        Self::with_backend(config, Backend::Null, commands, synth.clock())
+    }
+    
+    pub fn is_audio_enabled(&self) -> bool {
+        !matches!(self.backend, Backend::Null)
+    }
+    
+    fn with_backend(config: &AudioConfig, backend: Backend, commands: Sender<SynthCommand>, clock: Arc<AtomicU64>) -> Self {
+        let builtin_instruments = builtin_instruments();
+        let current_instrument = builtin_instruments[0].clone();
+        let mut engine = Self {
+            backend,
+            commands,
+            clock,
+            clock_anchor: None,
+            scheduled_frame: None,
             volume: 0.7,
+            config: config.clone(),
+            builtin_instruments,
+            sound_font: None,
+            sound_font_presets: None,
+            channel_banks: [0; 16],
+            channel_parameters: [NULL_PARAMETER; 16],
+            channel_bend_ranges: [config.pitch_bend_range; 16],
+            program_patches: HashMap::new(),
+            current_instrument,
+            tuning: Arc::new(Tuning::default()),
+            metronome: MetronomeConfig::default(),
         };
         
-        engine.load_samples()?;
-        Ok(engine)
+        if !engine.select_instrument(&config.instrument) {
+            engine.set_instrument(engine.current_instrument.clone());
+        }
+        engine.reset_channels();
+        engine.send(SynthCommand::SetVolume(engine.volume));
+        engine.send(SynthCommand::SetPolyphony {
+            max_voices: config.max_polyphony,
+            stealing: config.voice_stealing,
+        });
+        engine.send(SynthCommand::SetVelocityCurve {
+            curve: config.velocity_curve,
+            fixed_velocity: config.fixed_velocity,
+        });
+        engine.send(SynthCommand::SetStereoWidth(config.stereo_width));
+        engine.send(SynthCommand::SetPitchBendRange(config.pitch_bend_range));
+        engine.send(SynthCommand::SetEffects(config.effects.clone()));
+        engine
+    }
+    
+    pub fn sample_rate(&self) -> u32 {
+        self.config.sample_rate
     }
     
-    fn load_samples(&mut self) -> Result<()> {
-        for midi_note in 21..109 {
-            let sample = self.generate_sine_wave(Note::new(midi_note).frequency(), 1.0);
-            self.samples.insert(midi_note, sample);
+    // Fills `buffer` with interleaved stereo frames from an offline engine. Returns false (leaving the
+    // buffer untouched) when the engine is playing to a device instead.
+    pub fn render(&mut self, buffer: &mut [f32]) -> bool {
+        match &mut self.backend {
+            Backend::Offline(synth) => {
+                synth.render(buffer);
+                true
+            }
+            Backend::Device { .. } | Backend::Null => false,
         }
-        Ok(())
     }
     
-    fn generate_sine_wave(&self, frequency: f32, duration: f32) -> Vec<u8> {
-        let sample_rate = 44100;
-        let samples = (sample_rate as f32 * duration) as usize;
-        let mut data = Vec::with_capacity(samples * 2);
-        
-        for i in 0..samples {
-            let t = i as f32 / sample_rate as f32;
-            
-            let envelope = if t < 0.1 {
-                t / 0.1
-            } else if t > duration - 0.3 {
-                (duration - t) / 0.3
-            } else {
-                1.0
-            };
-            
-            let value = (2.0 * std::f32::consts::PI * frequency * t).sin() * envelope * 0.3;
-            let sample = (value * i16::MAX as f32) as i16;
-            
-            data.push((sample & 0xFF) as u8);
-            data.push(((sample >> 8) & 0xFF) as u8);
+    pub fn active_voices(&self) -> Option<usize> {
+        match &self.backend {
+            Backend::Offline(synth) => Some(synth.active_voices()),
+            Backend::Device { .. } | Backend::Null => None,
         }
-        
-        data
     }
     
-    pub fn play_note(&self, midi_note: u8) -> Result<()> {
-        // Stop any existing note on this key first
-        self.stop_note(midi_note);
-        
-        if let Some(sample_data) = self.samples.get(&midi_note) {
-            let cursor = std::io::Cursor::new(sample_data.clone());
-            let source = PcmSource::new(cursor, 44100, 1)?;
-            
-            let sink = Sink::try_new(&self.stream_handle)?;
-            sink.set_volume(self.volume);
-            sink.append(source);
-            sink.play();
-            
-            {
-                let mut sinks = self.sinks.lock().unwrap();
-                sinks.insert(midi_note, sink);
+    // Applies the sound-producing part of a MIDI message on `channel` (0-15)
+    pub fn handle_midi_message(&mut self, channel: u8, message: &MidiMessage) -> Result<()> {
+        match *message {
+            MidiMessage::NoteOn { key, vel } if vel.as_int() > 0 => {
+                // Closed and pedal hi-hats cut off a ringing open one
+                if channel == DRUM_CHANNEL && matches!(key.as_int(), 42 | 44) {
+                    self.send(SynthCommand::NoteOff { channel, midi_note: 46 });
+                }
+                self.send(SynthCommand::NoteOn { channel, midi_note: key.as_int(), velocity: vel.as_int() });
+            }
+            // Drum hits ring out by themselves, whenever their note-off comes
+            MidiMessage::NoteOn { .. } | MidiMessage::NoteOff { .. } if channel == DRUM_CHANNEL => {}
+            MidiMessage::NoteOn { key, .. } | MidiMessage::NoteOff { key, .. } => {
+                self.send(SynthCommand::NoteOff { channel, midi_note: key.as_int() });
+            }
+            MidiMessage::Controller { controller, value } => {
+                self.handle_controller(channel, controller.as_int(), value.as_int());
+            }
+            MidiMessage::PitchBend { bend } => {
+                self.send(SynthCommand::PitchBend { channel, bend: bend.as_f32() });
             }
+            MidiMessage::ProgramChange { program } => {
+                self.set_program(channel, program.as_int());
+            }
+            _ => {}
         }
-        
         Ok(())
     }
     
-    pub fn stop_note(&self, midi_note: u8) {
-        let mut sinks = self.sinks.lock().unwrap();
-        if let Some(sink) = sinks.remove(&midi_note) {
-            sink.stop();
+    fn handle_controller(&mut self, channel: u8, controller: u8, value: u8) {
+        let index = (channel & 0x0f) as usize;
+        match controller {
+            0 => self.channel_banks[index] = value as u16,
+            7 => self.send(SynthCommand::SetChannelVolume { channel, volume: value }),
+            10 => self.set_channel_pan(channel, Some((value as f32 - 64.0) / 63.0)),
+            11 => self.send(SynthCommand::SetExpression { channel, expression: value }),
+            64 => self.send(SynthCommand::SustainPedal { channel, pressed: value >= 64 }),
+            67 => self.send(SynthCommand::SoftPedal { channel, pressed: value >= 64 }),
+            101 => self.channel_parameters[index].0 = value,
+            100 => self.channel_parameters[index].1 = value,
+            // Data entry: whole semitones, then cents
+            6 | 38 if self.channel_parameters[index] == PITCH_BEND_RANGE_PARAMETER => {
+                let semitones = if controller == 6 {
+                    value as f32
+                } else {
+                    self.channel_bend_ranges[index].trunc() + value.min(99) as f32 / 100.0
+                };
+                self.channel_bend_ranges[index] = semitones;
+                self.send(SynthCommand::SetChannelBendRange { channel, semitones });
+            }
+            _ => {}
         }
     }
     
+    // Like `handle_midi_message`, but the message takes effect on the frame
+    // the synth plays at `when` (as of the last `sync_clock`), however long
+    // before then it was sent
+    pub fn schedule_midi_message(&mut self, when: Instant, channel: u8, message: &MidiMessage) -> Result<()> {
+        self.scheduled_frame = self.frame_at(when);
+        let result = self.handle_midi_message(channel, message);
+        self.scheduled_frame = None;
+        result
+    }
+    
+    // Lines the synth's frames up with the wall clock, for scheduling.
+    // Called whenever playback starts, so the two never drift far apart.
+    pub fn sync_clock(&mut self) {
+        let latency = (SCHEDULE_LATENCY.as_secs_f64() * self.sample_rate() as f64) as u64;
+        self.clock_anchor = Some((Instant::now(), self.clock.load(Ordering::Relaxed) + latency));
+    }
+    
+    fn frame_at(&self, when: Instant) -> Option<u64> {
+        let (anchor, frame) = self.clock_anchor?;
+        let offset = when.saturating_duration_since(anchor).as_secs_f64() * self.sample_rate() as f64;
+        Some(frame + offset.round() as u64)
+    }
+    
+    // Drops scheduled messages that haven't sounded yet, e.g. on pause
+    pub fn cancel_scheduled(&self) {
+        let _ = self.commands.send(SynthCommand::CancelScheduled);
+    }
+    
+    fn send(&self, command: SynthCommand) {
+        let command = match self.scheduled_frame {
+            Some(frame) => SynthCommand::Scheduled { frame, command: Box::new(command) },
+            None => command,
+        };
+        // The synth only goes away together with the output stream, so a
+        // failed send just means there is nothing left to play to
+        let _ = self.commands.send(command);
+    }
+    
+    pub fn play_note(&self, midi_note: u8, velocity: u8) -> Result<()> {
+        self.send(SynthCommand::NoteOn { channel: LIVE_CHANNEL, midi_note, velocity });
+        Ok(())
+    }
+    
+    pub fn stop_note(&self, midi_note: u8) {
+        self.send(SynthCommand::NoteOff { channel: LIVE_CHANNEL, midi_note });
+    }
+    
+    // While the pedal is down note-offs are deferred; lifting it releases
+    // every note whose key is already up
+    pub fn set_sustain_pedal(&self, pressed: bool) {
+        self.send(SynthCommand::SustainPedal { channel: LIVE_CHANNEL, pressed });
+    }
+    
+    // Places notes started on `channel` from now on at a fixed pan position
+    // instead of by key; `None` returns to key-position panning
+    pub fn set_channel_pan(&self, channel: u8, pan: Option<f32>) {
+        self.send(SynthCommand::SetPan { channel, pan });
+    }
+    
+    // Switches a MIDI channel to the instrument for a General MIDI program:
+    // the SoundFont preset when one is loaded and has it, otherwise the
+    // closest built-in instrument. The percussion channel always gets a kit.
+    pub fn set_program(&mut self, channel: u8, program: u8) {
+        let bank = if channel == DRUM_CHANNEL {
+            PERCUSSION_BANK
+        } else {
+            self.channel_banks[(channel & 0x0f) as usize]
+        };
+        let instrument = match self.program_patches.get(&(bank, program)) {
+            Some(instrument) => instrument.clone(),
+            None => {
+                let (fallback_bank, fallback_program) = if bank == PERCUSSION_BANK { (bank, 0) } else { (0, program) };
+                let preset = self.sound_font_presets.as_ref().and_then(|font| {
+                    font.preset(bank, program as u16)
+                        .or_else(|| font.preset(fallback_bank, fallback_program as u16))
+                });
+                let instrument: Arc<dyn Instrument> = match preset {
+                    Some(patch) => Arc::new(patch),
+                    None if bank == PERCUSSION_BANK => Arc::new(DrumKit),
+                    None => general_midi_instrument(program),
+                };
+                self.program_patches.insert((bank, program), instrument.clone());
+                instrument
+            }
+        };
+        let patch = ChannelPatch {
+            envelope: self.config.envelope_for(instrument.as_ref()),
+            retrigger: self.config.retrigger_for(instrument.as_ref()),
+            instrument,
+        };
+        self.send(SynthCommand::SetChannelPatch { channel, patch: Some(patch) });
+    }
+    
+    // Forgets every channel's program, bank and pan, so the next file (or
+    // the keyboard) starts from the selected instrument and the standard kit
+    pub fn reset_channels(&mut self) {
+        self.channel_banks = [0; 16];
+        self.channel_parameters = [NULL_PARAMETER; 16];
+        self.channel_bend_ranges = [self.config.pitch_bend_range; 16];
+        self.send(SynthCommand::ResetChannels);
+        self.set_program(DRUM_CHANNEL, 0);
+    }
+    
     pub fn stop_all_notes(&self) {
-        let mut sinks = self.sinks.lock().unwrap();
-        for (_, sink) in sinks.drain() {
-            sink.stop();
+        self.send(SynthCommand::AllNotesOff);
+    }
+    
+    // Switches the engine to a SoundFont preset and returns the font and preset
+    // names. On error the engine keeps (or returns to) a built-in instrument.
+    pub fn load_sound_font(&mut self, path: &str, bank: u16, preset: u16) -> Result<String> {
+        match SoundFont::load(expand_home(path)) {
+            Ok(font) => {
+                let patch: Arc<dyn Instrument> = Arc::new(font.patch(bank, preset));
+                let name = if font.name.is_empty() {
+                    patch.name().to_string()
+                } else {
+                    format!("{} - {}", font.name, patch.name())
+                };
+                self.sound_font = Some(patch.clone());
+                self.sound_font_presets = Some(font);
+                self.program_patches.clear();
+                self.set_instrument(patch);
+                // Picks up the SoundFont's drum kit, if it has one
+                self.set_program(DRUM_CHANNEL, 0);
+                Ok(name)
+            }
+            Err(e) => {
+                self.sound_font_presets = None;
+                self.program_patches.clear();
+                self.set_program(DRUM_CHANNEL, 0);
+                if let Some(sound_font) = self.sound_font.take() {
+                    if Arc::ptr_eq(&sound_font, &self.current_instrument) {
+                        self.set_instrument(self.builtin_instruments[0].clone());
+                    }
+                }
+                Err(e)
+            }
         }
     }
     
-    pub fn set_volume(&mut self, volume: f32) {
-        self.volume = volume.clamp(0.0, 1.0);
-        
-        let sinks = self.sinks.lock().unwrap();
-        for (_, sink) in sinks.iter() {
-            sink.set_volume(self.volume);
+    // Retunes every note from now on and returns the tuning's name. On error
+    // the current tuning stays in place.
+    pub fn set_tuning(&mut self, config: &TuningConfig) -> Result<String> {
+        let tuning = Arc::new(Tuning::from_config(config)?);
+        self.send(SynthCommand::SetTuning(tuning.clone()));
+        self.tuning = tuning;
+        self.config.tuning = config.clone();
+        Ok(self.tuning.name().to_string())
+    }
+    
+    // Built-in instruments followed by the loaded SoundFont preset, if any
+    pub fn instruments(&self) -> Vec<Arc<dyn Instrument>> {
+        let mut instruments = self.builtin_instruments.clone();
+        instruments.extend(self.sound_font.clone());
+        instruments
+    }
+    
+    pub fn instrument_name(&self) -> &str {
+        self.current_instrument.name()
+    }
+    
+    pub fn select_instrument(&mut self, name: &str) -> bool {
+        match self.instruments().into_iter().find(|i| i.name() == name) {
+            Some(instrument) => {
+                self.set_instrument(instrument);
+                true
+            }
+            None => false,
         }
     }
     
-    pub fn get_volume(&self) -> f32 {
-        self.volume
+    // Steps forwards or backwards through `instruments()`, wrapping around
+    pub fn cycle_instrument(&mut self, step: i32) -> &str {
+        let instruments = self.instruments();
+        let current = instruments
+            .iter()
+            .position(|i| Arc::ptr_eq(i, &self.current_instrument))
+            .unwrap_or(0) as i32;
+        let next = (current + step).rem_euclid(instruments.len() as i32) as usize;
+        self.set_instrument(instruments[next].clone());
+        self.instrument_name()
     }
     
-    pub fn cleanup_finished_notes(&self) {
-        let mut sinks = self.sinks.lock().unwrap();
-        sinks.retain(|_, sink| !sink.empty());
+    fn set_instrument(&mut self, instrument: Arc<dyn Instrument>) {
+        self.send(SynthCommand::SetEnvelope(self.config.envelope_for(instrument.as_ref())));
+        self.send(SynthCommand::SetRetrigger(self.config.retrigger_for(instrument.as_ref())));
+        self.send(SynthCommand::SetInstrument(instrument.clone()));
+        self.current_instrument = instrument;
     }
-}
-
-struct PcmSource {
-    data: std::io::Cursor<Vec<u8>>,
-    sample_rate: u32,
-    channels: u16,
-}
-
-impl PcmSource {
-    fn new(data: std::io::Cursor<Vec<u8>>, sample_rate: u32, channels: u16) -> Result<Self> {
-        Ok(Self {
-            data,
-            sample_rate,
-            channels,
-        })
+    
+    pub fn effects(&self) -> &EffectsConfig {
+        &self.config.effects
     }
-}
-
-impl Source for PcmSource {
-    fn current_frame_len(&self) -> Option<usize> {
-        None
+    
+    pub fn set_effects(&mut self, effects: EffectsConfig) {
+        self.send(SynthCommand::SetEffects(effects.clone()));
+        self.config.effects = effects;
     }
-
-    fn channels(&self) -> u16 {
-        self.channels
+    
+    pub fn metronome(&self) -> &MetronomeConfig {
+        &self.metronome
     }
-
-    fn sample_rate(&self) -> u32 {
-        self.sample_rate
+    
+    pub fn set_metronome(&mut self, config: MetronomeConfig) {
+        self.send(SynthCommand::SetMetronome(config));
+        self.metronome = config;
     }
-
-    fn total_duration(&self) -> Option<Duration> {
-        let total_samples = self.data.get_ref().len() / 2 / self.channels as usize;
-        Some(Duration::from_secs_f32(total_samples as f32 / self.sample_rate as f32))
+    
+    pub fn set_metronome_enabled(&self, enabled: bool) {
+        self.send(SynthCommand::MetronomeEnabled(enabled));
+    }
+    
+    // Follows a MIDI file's tempo (quarter notes per minute) and time
+    // signature; `None` returns to the metronome's own settings
+    pub fn lock_metronome(&self, locked: Option<(f32, u8, u8)>) {
+        self.send(SynthCommand::LockMetronome(locked));
+    }
+    
+    // Scheduled for now like file messages are, so the clicks line up with
+    // the notes rather than with the display
+    pub fn sync_metronome(&mut self, beat: f64) {
+        self.scheduled_frame = self.frame_at(Instant::now());
+        self.send(SynthCommand::SyncMetronome(beat));
+        self.scheduled_frame = None;
+    }
+    
+    pub fn set_volume(&mut self, volume: f32) {
+        self.volume = volume.clamp(0.0, 1.0);
+        self.send(SynthCommand::SetVolume(self.volume));
+    }
+    
+    pub fn get_volume(&self) -> f32 {
+        self.volume
     }
 }
 
-impl Iterator for PcmSource {
-    type Item = i16;
-
-    fn next(&mut self) -> Option<Self::Item> {
-        let mut bytes = [0u8; 2];
-        if std::io::Read::read_exact(&mut self.data, &mut bytes).is_ok() {
-            Some(i16::from_le_bytes(bytes))
-        } else {
-            None
-        }
+pub fn expand_home(path: &str) -> PathBuf {
+    match (path.strip_prefix("~/"), dirs::home_dir()) {
+        (Some(rest), Some(home)) => home.join(rest),
+        _ => PathBuf::from(path),
     }
 }
 
@@ -225,6 +520,31 @@ impl Recording {
         self.duration = self.start_time.elapsed();
     }
     
+    // The recording as MIDI messages on the live channel, for playing it
+    // through the same paths as SMF files
+    pub fn timed_messages(&self) -> Vec<(Duration, u8, MidiMessage)> {
+        self.events
+            .iter()
+            .map(|event| {
+                let message = match event.event_type {
+                    RecordingEventType::NoteOn { midi_note, velocity } => MidiMessage::NoteOn {
+                        key: midi_note.min(127).into(),
+                        vel: velocity.min(127).into(),
+                    },
+                    RecordingEventType::NoteOff { midi_note } => MidiMessage::NoteOff {
+                        key: midi_note.min(127).into(),
+                        vel: 0.into(),
+                    },
+                    RecordingEventType::SustainPedal { pressed } => MidiMessage::Controller {
+                        controller: 64.into(),
+                        value: if pressed { 127 } else { 0 }.into(),
+                    },
+                };
+                (event.timestamp, LIVE_CHANNEL, message)
+            })
+            .collect()
+    }
+    
     pub fn save_to_file(&self, path: &std::path::Path) -> Result<()> {
         let data = serde_json::to_string_pretty(self)?;
         std::fs::write(path, data)?;
@@ -256,7 +576,7 @@ impl<'de> serde::Deserialize<'de> for Recording {
     where
         D: serde::Deserializer<'de>,
     {
-        use serde::de::{self, Deserializer, MapAccess, Visitor};
+        use serde::de::{self, MapAccess, Visitor};
         use std::fmt;
 
         struct RecordingVisitor;
@@ -325,7 +645,7 @@ impl<'de> serde::Deserialize<'de> for RecordingEvent {
     where
         D: serde::Deserializer<'de>,
     {
-        use serde::de::{self, Deserializer, MapAccess, Visitor};
+        use serde::de::{self, MapAccess, Visitor};
         use std::fmt;
 
         struct RecordingEventVisitor;
@@ -410,7 +730,7 @@ impl<'de> serde::Deserialize<'de> for RecordingEventType {
     where
         D: serde::Deserializer<'de>,
     {
-        use serde::de::{self, Deserializer, MapAccess, Visitor};
+        use serde::de::{self, MapAccess, Visitor};
         use std::fmt;
 
         struct RecordingEventTypeVisitor;
@@ -0,0 +1,739 @@
+use std::f32::consts::PI;
+
+use crate::config::{ChorusConfig, DelayConfig, EffectsConfig, EqConfig, NoteDivision, ReverbConfig};
+
+// The limiter holds peaks at this level; it is transparent below it
+const LIMITER_THRESHOLD: f32 = 0.9;
+const LIMITER_RELEASE_SECONDS: f32 = 0.1;
+const MAX_DELAY_SECONDS: f32 = 2.0;
+const CHORUS_BASE_DELAY_SECONDS: f32 = 0.015;
+const CHORUS_MAX_DEPTH_SECONDS: f32 = 0.008;
+
+// Freeverb tunings, in samples at 44.1 kHz
+const COMB_TUNINGS: [usize; 8] = [1116, 1188, 1277, 1356, 1422, 1491, 1557, 1617];
+const ALLPASS_TUNINGS: [usize; 4] = [556, 441, 341, 225];
+const REVERB_INPUT_GAIN: f32 = 0.015;
+const REVERB_WET_GAIN: f32 = 3.0;
+// Extra delay on the right channel's reverb, so the two sides decorrelate
+const REVERB_STEREO_SPREAD: usize = 23;
+
+// The master bus: EQ, chorus, delay and reverb in series on each channel,
+// then a limiter that keeps the summed voices from clipping
+pub struct EffectsChain {
+    sample_rate: u32,
+    config: EffectsConfig,
+    // Quarter notes per minute from the metronome, once it has said
+    tempo: Option<f32>,
+    channels: [ChannelEffects; 2],
+    limiter: Limiter,
+}
+
+struct ChannelEffects {
+    eq: Equalizer,
+    chorus: Chorus,
+    delay: Delay,
+    reverb: Reverb,
+}
+
+impl ChannelEffects {
+    fn new(sample_rate: u32, right: bool) -> Self {
+        let mut chorus = Chorus::new(sample_rate);
+        let mut spread = 0;
+        if right {
+            // A quarter cycle apart, so the chorus sweeps across the field
+            chorus.phase = 0.25;
+            spread = REVERB_STEREO_SPREAD;
+        }
+        Self {
+            eq: Equalizer::new(),
+            chorus,
+            delay: Delay::new(sample_rate),
+            reverb: Reverb::new(sample_rate, spread),
+        }
+    }
+
+    fn process(&mut self, input: f32, config: &EffectsConfig) -> f32 {
+        let mut sample = input;
+        if config.eq.enabled {
+            sample = self.eq.process(sample);
+        }
+        if config.chorus.enabled {
+            sample = self.chorus.process(sample);
+        }
+        if config.delay.enabled {
+            sample = self.delay.process(sample);
+        }
+        if config.reverb.enabled {
+            sample = self.reverb.process(sample);
+        }
+        sample
+    }
+}
+
+impl EffectsChain {
+    pub fn new(config: &EffectsConfig, sample_rate: u32) -> Self {
+        let mut chain = Self {
+            sample_rate,
+            config: config.clone(),
+            tempo: None,
+            channels: [ChannelEffects::new(sample_rate, false), ChannelEffects::new(sample_rate, true)],
+            limiter: Limiter::new(sample_rate),
+        };
+        chain.configure(config);
+        chain
+    }
+
+    // Takes new parameters without interrupting the sound. An effect that is
+    // switched back on starts from silence rather than old buffer contents.
+    pub fn configure(&mut self, config: &EffectsConfig) {
+        for channel in &mut self.channels {
+            if config.chorus.enabled && !self.config.chorus.enabled {
+                channel.chorus.clear();
+            }
+            if config.delay.enabled && !self.config.delay.enabled {
+                channel.delay.clear();
+            }
+            if config.reverb.enabled && !self.config.reverb.enabled {
+                channel.reverb.clear();
+            }
+
+            channel.eq.configure(&config.eq, self.sample_rate);
+            channel.chorus.configure(&config.chorus);
+            channel.delay.configure(&config.delay, Self::delay_tempo(config, self.tempo));
+            channel.reverb.configure(&config.reverb);
+        }
+        self.config = config.clone();
+    }
+
+    // Retimes a synced delay to the metronome's tempo
+    pub fn set_tempo(&mut self, tempo: f32) {
+        if self.tempo == Some(tempo) {
+            return;
+        }
+        self.tempo = Some(tempo);
+        let delay_tempo = Self::delay_tempo(&self.config, self.tempo);
+        for channel in &mut self.channels {
+            channel.delay.configure(&self.config.delay, delay_tempo);
+        }
+    }
+
+    fn delay_tempo(config: &EffectsConfig, tempo: Option<f32>) -> f32 {
+        tempo.filter(|_| config.delay.sync).unwrap_or(config.delay.tempo)
+    }
+
+    // Processes one left/right frame. `dry` skips the effects but still goes
+    // through the limiter, for sounds like the metronome click.
+    pub fn process(&mut self, input: [f32; 2], dry: f32) -> [f32; 2] {
+        let left = self.channels[0].process(input[0], &self.config);
+        let right = self.channels[1].process(input[1], &self.config);
+        self.limiter.process([left + dry, right + dry])
+    }
+}
+
+// The parameters the effects panel can step through and adjust
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EffectParam {
+    EqLow,
+    EqMid,
+    EqHigh,
+    ChorusRate,
+    ChorusDepth,
+    ChorusMix,
+    DelaySync,
+    DelayTempo,
+    DelayDivision,
+    DelayFeedback,
+    DelayMix,
+    ReverbRoomSize,
+    ReverbDamping,
+    ReverbMix,
+}
+
+impl EffectParam {
+    pub const ALL: [EffectParam; 14] = [
+        EffectParam::EqLow,
+        EffectParam::EqMid,
+        EffectParam::EqHigh,
+        EffectParam::ChorusRate,
+        EffectParam::ChorusDepth,
+        EffectParam::ChorusMix,
+        EffectParam::DelaySync,
+        EffectParam::DelayTempo,
+        EffectParam::DelayDivision,
+        EffectParam::DelayFeedback,
+        EffectParam::DelayMix,
+        EffectParam::ReverbRoomSize,
+        EffectParam::ReverbDamping,
+        EffectParam::ReverbMix,
+    ];
+
+    pub fn effect_name(self) -> &'static str {
+        match self {
+            EffectParam::EqLow | EffectParam::EqMid | EffectParam::EqHigh => "EQ",
+            EffectParam::ChorusRate | EffectParam::ChorusDepth | EffectParam::ChorusMix => "Chorus",
+            EffectParam::DelaySync | EffectParam::DelayTempo | EffectParam::DelayDivision | EffectParam::DelayFeedback | EffectParam::DelayMix => "Delay",
+            EffectParam::ReverbRoomSize | EffectParam::ReverbDamping | EffectParam::ReverbMix => "Reverb",
+        }
+    }
+
+    pub fn label(self) -> &'static str {
+        match self {
+            EffectParam::EqLow => "Low",
+            EffectParam::EqMid => "Mid",
+            EffectParam::EqHigh => "High",
+            EffectParam::ChorusRate => "Rate",
+            EffectParam::ChorusDepth => "Depth",
+            EffectParam::DelaySync => "Tempo Sync",
+            EffectParam::DelayTempo => "Tempo",
+            EffectParam::DelayDivision => "Time",
+            EffectParam::DelayFeedback => "Feedback",
+            EffectParam::ReverbRoomSize => "Room Size",
+            EffectParam::ReverbDamping => "Damping",
+            EffectParam::ChorusMix | EffectParam::DelayMix | EffectParam::ReverbMix => "Mix",
+        }
+    }
+
+    pub fn value(self, config: &EffectsConfig) -> String {
+        let percent = |value: f32| format!("{:.0}%", value * 100.0);
+        match self {
+            EffectParam::EqLow => format!("{:+.1} dB", config.eq.low),
+            EffectParam::EqMid => format!("{:+.1} dB", config.eq.mid),
+            EffectParam::EqHigh => format!("{:+.1} dB", config.eq.high),
+            EffectParam::ChorusRate => format!("{:.1} Hz", config.chorus.rate),
+            EffectParam::ChorusDepth => percent(config.chorus.depth),
+            EffectParam::ChorusMix => percent(config.chorus.mix),
+            EffectParam::DelaySync => if config.delay.sync { "Metronome" } else { "Off" }.to_string(),
+            EffectParam::DelayTempo => format!("{:.0} BPM", config.delay.tempo),
+            EffectParam::DelayDivision => config.delay.division.name().to_string(),
+            EffectParam::DelayFeedback => percent(config.delay.feedback),
+            EffectParam::DelayMix => percent(config.delay.mix),
+            EffectParam::ReverbRoomSize => percent(config.reverb.room_size),
+            EffectParam::ReverbDamping => percent(config.reverb.damping),
+            EffectParam::ReverbMix => percent(config.reverb.mix),
+        }
+    }
+
+    // Moves the parameter `steps` increments up or down, within its range
+    pub fn adjust(self, config: &mut EffectsConfig, steps: i32) {
+        let steps = steps as f32;
+        let nudge = |value: &mut f32, step: f32, min: f32, max: f32| {
+            *value = ((*value + step * steps) / step).round() * step;
+            *value = value.clamp(min, max);
+        };
+        match self {
+            EffectParam::EqLow => nudge(&mut config.eq.low, 1.0, -12.0, 12.0),
+            EffectParam::EqMid => nudge(&mut config.eq.mid, 1.0, -12.0, 12.0),
+            EffectParam::EqHigh => nudge(&mut config.eq.high, 1.0, -12.0, 12.0),
+            EffectParam::ChorusRate => nudge(&mut config.chorus.rate, 0.1, 0.1, 5.0),
+            EffectParam::ChorusDepth => nudge(&mut config.chorus.depth, 0.05, 0.0, 1.0),
+            EffectParam::ChorusMix => nudge(&mut config.chorus.mix, 0.05, 0.0, 1.0),
+            EffectParam::DelaySync => config.delay.sync = steps > 0.0,
+            EffectParam::DelayTempo => nudge(&mut config.delay.tempo, 1.0, 30.0, 300.0),
+            EffectParam::DelayDivision => {
+                let divisions = NoteDivision::ALL;
+                let current = divisions.iter().position(|&d| d == config.delay.division).unwrap_or(0) as i32;
+                let next = (current + steps as i32).clamp(0, divisions.len() as i32 - 1);
+                config.delay.division = divisions[next as usize];
+            }
+            EffectParam::DelayFeedback => nudge(&mut config.delay.feedback, 0.05, 0.0, 0.95),
+            EffectParam::DelayMix => nudge(&mut config.delay.mix, 0.05, 0.0, 1.0),
+            EffectParam::ReverbRoomSize => nudge(&mut config.reverb.room_size, 0.05, 0.0, 1.0),
+            EffectParam::ReverbDamping => nudge(&mut config.reverb.damping, 0.05, 0.0, 1.0),
+            EffectParam::ReverbMix => nudge(&mut config.reverb.mix, 0.05, 0.0, 1.0),
+        }
+    }
+
+    fn enabled_flag(self, config: &mut EffectsConfig) -> &mut bool {
+        match self {
+            EffectParam::EqLow | EffectParam::EqMid | EffectParam::EqHigh => &mut config.eq.enabled,
+            EffectParam::ChorusRate | EffectParam::ChorusDepth | EffectParam::ChorusMix => &mut config.chorus.enabled,
+            EffectParam::DelaySync | EffectParam::DelayTempo | EffectParam::DelayDivision | EffectParam::DelayFeedback | EffectParam::DelayMix => &mut config.delay.enabled,
+            EffectParam::ReverbRoomSize | EffectParam::ReverbDamping | EffectParam::ReverbMix => &mut config.reverb.enabled,
+        }
+    }
+
+    pub fn is_effect_enabled(self, config: &EffectsConfig) -> bool {
+        match self {
+            EffectParam::EqLow | EffectParam::EqMid | EffectParam::EqHigh => config.eq.enabled,
+            EffectParam::ChorusRate | EffectParam::ChorusDepth | EffectParam::ChorusMix => config.chorus.enabled,
+            EffectParam::DelaySync | EffectParam::DelayTempo | EffectParam::DelayDivision | EffectParam::DelayFeedback | EffectParam::DelayMix => config.delay.enabled,
+            EffectParam::ReverbRoomSize | EffectParam::ReverbDamping | EffectParam::ReverbMix => config.reverb.enabled,
+        }
+    }
+
+    // Switches the effect this parameter belongs to on or off
+    pub fn toggle_effect(self, config: &mut EffectsConfig) {
+        let enabled = self.enabled_flag(config);
+        *enabled = !*enabled;
+    }
+}
+
+// RBJ cookbook biquad in direct form I
+#[derive(Clone, Copy)]
+struct Biquad {
+    b0: f32,
+    b1: f32,
+    b2: f32,
+    a1: f32,
+    a2: f32,
+    x1: f32,
+    x2: f32,
+    y1: f32,
+    y2: f32,
+}
+
+impl Biquad {
+    fn identity() -> Self {
+        Self { b0: 1.0, b1: 0.0, b2: 0.0, a1: 0.0, a2: 0.0, x1: 0.0, x2: 0.0, y1: 0.0, y2: 0.0 }
+    }
+
+    fn set(&mut self, b0: f32, b1: f32, b2: f32, a0: f32, a1: f32, a2: f32) {
+        self.b0 = b0 / a0;
+        self.b1 = b1 / a0;
+        self.b2 = b2 / a0;
+        self.a1 = a1 / a0;
+        self.a2 = a2 / a0;
+    }
+
+    fn low_shelf(&mut self, frequency: f32, gain_db: f32, sample_rate: u32) {
+        let a = 10f32.powf(gain_db / 40.0);
+        let w = 2.0 * PI * frequency / sample_rate as f32;
+        let alpha = w.sin() / 2.0 * 2f32.sqrt();
+        let cos = w.cos();
+        let sqrt_a = 2.0 * a.sqrt() * alpha;
+        self.set(
+            a * ((a + 1.0) - (a - 1.0) * cos + sqrt_a),
+            2.0 * a * ((a - 1.0) - (a + 1.0) * cos),
+            a * ((a + 1.0) - (a - 1.0) * cos - sqrt_a),
+            (a + 1.0) + (a - 1.0) * cos + sqrt_a,
+            -2.0 * ((a - 1.0) + (a + 1.0) * cos),
+            (a + 1.0) + (a - 1.0) * cos - sqrt_a,
+        );
+    }
+
+    fn high_shelf(&mut self, frequency: f32, gain_db: f32, sample_rate: u32) {
+        let a = 10f32.powf(gain_db / 40.0);
+        let w = 2.0 * PI * frequency / sample_rate as f32;
+        let alpha = w.sin() / 2.0 * 2f32.sqrt();
+        let cos = w.cos();
+        let sqrt_a = 2.0 * a.sqrt() * alpha;
+        self.set(
+            a * ((a + 1.0) + (a - 1.0) * cos + sqrt_a),
+            -2.0 * a * ((a - 1.0) + (a + 1.0) * cos),
+            a * ((a + 1.0) + (a - 1.0) * cos - sqrt_a),
+            (a + 1.0) - (a - 1.0) * cos + sqrt_a,
+            2.0 * ((a - 1.0) - (a + 1.0) * cos),
+            (a + 1.0) - (a - 1.0) * cos - sqrt_a,
+        );
+    }
+
+    fn peaking(&mut self, frequency: f32, q: f32, gain_db: f32, sample_rate: u32) {
+        let a = 10f32.powf(gain_db / 40.0);
+        let w = 2.0 * PI * frequency / sample_rate as f32;
+        let alpha = w.sin() / (2.0 * q);
+        let cos = w.cos();
+        self.set(
+            1.0 + alpha * a,
+            -2.0 * cos,
+            1.0 - alpha * a,
+            1.0 + alpha / a,
+            -2.0 * cos,
+            1.0 - alpha / a,
+        );
+    }
+
+    fn process(&mut self, x: f32) -> f32 {
+        let y = self.b0 * x + self.b1 * self.x1 + self.b2 * self.x2 - self.a1 * self.y1 - self.a2 * self.y2;
+        self.x2 = self.x1;
+        self.x1 = x;
+        self.y2 = self.y1;
+        self.y1 = y;
+        y
+    }
+}
+
+// Low shelf at 250 Hz, mid peak at 1 kHz and high shelf at 4 kHz
+struct Equalizer {
+    bands: [Biquad; 3],
+}
+
+impl Equalizer {
+    fn new() -> Self {
+        Self { bands: [Biquad::identity(); 3] }
+    }
+
+    fn configure(&mut self, config: &EqConfig, sample_rate: u32) {
+        self.bands[0].low_shelf(250.0, config.low, sample_rate);
+        self.bands[1].peaking(1000.0, 0.7, config.mid, sample_rate);
+        self.bands[2].high_shelf(4000.0, config.high, sample_rate);
+    }
+
+    fn process(&mut self, input: f32) -> f32 {
+        self.bands.iter_mut().fold(input, |sample, band| band.process(sample))
+    }
+}
+
+// A circular buffer read at a fractional number of samples before the
+// most recently written one
+struct DelayLine {
+    buffer: Vec<f32>,
+    position: usize,
+}
+
+impl DelayLine {
+    fn new(len: usize) -> Self {
+        Self { buffer: vec![0.0; len.max(2)], position: 0 }
+    }
+
+    fn read(&self, delay: f32) -> f32 {
+        let len = self.buffer.len();
+        let delay = delay.clamp(1.0, (len - 1) as f32);
+        let whole = delay.floor() as usize;
+        let frac = delay - whole as f32;
+        let a = self.buffer[(self.position + len - whole) % len];
+        let b = self.buffer[(self.position + len - whole - 1) % len];
+        a + (b - a) * frac
+    }
+
+    fn write(&mut self, sample: f32) {
+        self.position = (self.position + 1) % self.buffer.len();
+        self.buffer[self.position] = sample;
+    }
+
+    fn clear(&mut self) {
+        self.buffer.fill(0.0);
+    }
+}
+
+// A single voice whose delay time is swept by a sine LFO
+struct Chorus {
+    line: DelayLine,
+    sample_rate: f32,
+    phase: f32,
+    rate: f32,
+    depth: f32,
+    mix: f32,
+}
+
+impl Chorus {
+    fn new(sample_rate: u32) -> Self {
+        let len = ((CHORUS_BASE_DELAY_SECONDS + CHORUS_MAX_DEPTH_SECONDS) * sample_rate as f32) as usize + 2;
+        Self {
+            line: DelayLine::new(len),
+            sample_rate: sample_rate as f32,
+            phase: 0.0,
+            rate: 0.0,
+            depth: 0.0,
+            mix: 0.0,
+        }
+    }
+
+    fn configure(&mut self, config: &ChorusConfig) {
+        self.rate = config.rate.max(0.0);
+        self.depth = config.depth.clamp(0.0, 1.0);
+        self.mix = config.mix.clamp(0.0, 1.0);
+    }
+
+    fn clear(&mut self) {
+        self.line.clear();
+    }
+
+    fn process(&mut self, input: f32) -> f32 {
+        let sweep = (self.phase * 2.0 * PI).sin() * 0.5 + 0.5;
+        let delay = (CHORUS_BASE_DELAY_SECONDS + sweep * self.depth * CHORUS_MAX_DEPTH_SECONDS) * self.sample_rate;
+        self.phase = (self.phase + self.rate / self.sample_rate).fract();
+
+        self.line.write(input);
+        let wet = self.line.read(delay);
+        input * (1.0 - self.mix) + wet * self.mix
+    }
+}
+
+// Feedback echo timed to a note length at a tempo
+struct Delay {
+    line: DelayLine,
+    sample_rate: f32,
+    delay: f32,
+    feedback: f32,
+    mix: f32,
+}
+
+impl Delay {
+    fn new(sample_rate: u32) -> Self {
+        Self {
+            line: DelayLine::new((MAX_DELAY_SECONDS * sample_rate as f32) as usize + 1),
+            sample_rate: sample_rate as f32,
+            delay: 1.0,
+            feedback: 0.0,
+            mix: 0.0,
+        }
+    }
+
+    fn configure(&mut self, config: &DelayConfig, tempo: f32) {
+        self.delay = delay_seconds(tempo, config.division) * self.sample_rate;
+        self.feedback = config.feedback.clamp(0.0, 0.95);
+        self.mix = config.mix.clamp(0.0, 1.0);
+    }
+
+    fn clear(&mut self) {
+        self.line.clear();
+    }
+
+    fn process(&mut self, input: f32) -> f32 {
+        // Read before writing, so one sample less reaches back `delay` samples
+        let echo = self.line.read(self.delay - 1.0);
+        self.line.write(input + echo * self.feedback);
+        input * (1.0 - self.mix) + echo * self.mix
+    }
+}
+
+pub fn delay_seconds(tempo: f32, division: NoteDivision) -> f32 {
+    (60.0 / tempo.max(1.0) * division.beats()).min(MAX_DELAY_SECONDS)
+}
+
+struct Comb {
+    buffer: Vec<f32>,
+    position: usize,
+    filter_state: f32,
+}
+
+impl Comb {
+    fn new(len: usize) -> Self {
+        Self { buffer: vec![0.0; len.max(1)], position: 0, filter_state: 0.0 }
+    }
+
+    fn process(&mut self, input: f32, feedback: f32, damping: f32) -> f32 {
+        let output = self.buffer[self.position];
+        self.filter_state = output * (1.0 - damping) + self.filter_state * damping;
+        self.buffer[self.position] = input + self.filter_state * feedback;
+        self.position = (self.position + 1) % self.buffer.len();
+        output
+    }
+}
+
+struct Allpass {
+    buffer: Vec<f32>,
+    position: usize,
+}
+
+impl Allpass {
+    fn new(len: usize) -> Self {
+        Self { buffer: vec![0.0; len.max(1)], position: 0 }
+    }
+
+    fn process(&mut self, input: f32) -> f32 {
+        let delayed = self.buffer[self.position];
+        self.buffer[self.position] = input + delayed * 0.5;
+        self.position = (self.position + 1) % self.buffer.len();
+        delayed - input
+    }
+}
+
+// Freeverb: parallel damped combs into series allpasses
+struct Reverb {
+    combs: Vec<Comb>,
+    allpasses: Vec<Allpass>,
+    feedback: f32,
+    damping: f32,
+    mix: f32,
+}
+
+impl Reverb {
+    fn new(sample_rate: u32, spread: usize) -> Self {
+        let scale = |len: usize| ((len + spread) as f32 * sample_rate as f32 / 44100.0) as usize;
+        Self {
+            combs: COMB_TUNINGS.iter().map(|&len| Comb::new(scale(len))).collect(),
+            allpasses: ALLPASS_TUNINGS.iter().map(|&len| Allpass::new(scale(len))).collect(),
+            feedback: 0.0,
+            damping: 0.0,
+            mix: 0.0,
+        }
+    }
+
+    fn configure(&mut self, config: &ReverbConfig) {
+        self.feedback = 0.7 + config.room_size.clamp(0.0, 1.0) * 0.28;
+        self.damping = config.damping.clamp(0.0, 1.0) * 0.4;
+        self.mix = config.mix.clamp(0.0, 1.0);
+    }
+
+    fn clear(&mut self) {
+        for comb in &mut self.combs {
+            comb.buffer.fill(0.0);
+            comb.filter_state = 0.0;
+        }
+        for allpass in &mut self.allpasses {
+            allpass.buffer.fill(0.0);
+        }
+    }
+
+    fn process(&mut self, input: f32) -> f32 {
+        let (feedback, damping) = (self.feedback, self.damping);
+        let mut wet: f32 = self
+            .combs
+            .iter_mut()
+            .map(|comb| comb.process(input * REVERB_INPUT_GAIN, feedback, damping))
+            .sum();
+        for allpass in &mut self.allpasses {
+            wet = allpass.process(wet);
+        }
+        input * (1.0 - self.mix) + wet * REVERB_WET_GAIN * self.mix
+    }
+}
+
+// Stereo-linked peak limiter with instant attack, so no sample leaves above
+// the threshold, and a smooth release back to unity gain
+struct Limiter {
+    gain: f32,
+    release: f32,
+}
+
+impl Limiter {
+    fn new(sample_rate: u32) -> Self {
+        Self {
+            gain: 1.0,
+            release: 1.0 / (LIMITER_RELEASE_SECONDS * sample_rate as f32),
+        }
+    }
+
+    fn process(&mut self, input: [f32; 2]) -> [f32; 2] {
+        let level = input[0].abs().max(input[1].abs());
+        if level * self.gain > LIMITER_THRESHOLD {
+            self.gain = LIMITER_THRESHOLD / level;
+        } else {
+            self.gain = (self.gain + self.release).min(1.0);
+        }
+        input.map(|sample| (sample * self.gain).clamp(-LIMITER_THRESHOLD, LIMITER_THRESHOLD))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SAMPLE_RATE: u32 = 44100;
+
+    fn chain(configure: impl FnOnce(&mut EffectsConfig)) -> EffectsChain {
+        let mut config = EffectsConfig::default().bypassed();
+        configure(&mut config);
+        EffectsChain::new(&config, SAMPLE_RATE)
+    }
+
+    // Feeds the same signal to both channels and returns the left one
+    fn process_mono(chain: &mut EffectsChain, input: f32) -> f32 {
+        chain.process([input, input], 0.0)[0]
+    }
+
+    fn impulse_response(chain: &mut EffectsChain, len: usize) -> Vec<f32> {
+        (0..len).map(|i| process_mono(chain, if i == 0 { 0.5 } else { 0.0 })).collect()
+    }
+
+    #[test]
+    fn test_bypassed_chain_is_transparent_below_threshold() {
+        let mut chain = chain(|_| {});
+        for i in 0..1000 {
+            let input = (i as f32 * 0.01).sin() * 0.5;
+            assert_eq!(chain.process([input, -input], 0.0), [input, -input]);
+        }
+    }
+
+    #[test]
+    fn test_limiter_keeps_loud_input_from_clipping() {
+        let mut chain = chain(|_| {});
+        for i in 0..SAMPLE_RATE {
+            let input = (i as f32 * 0.05).sin() * 4.0;
+            assert!(chain.process([input, input * 0.5], input).iter().all(|s| s.abs() <= LIMITER_THRESHOLD));
+        }
+        // Gain recovers once the input gets quiet again
+        for _ in 0..SAMPLE_RATE {
+            process_mono(&mut chain, 0.0);
+        }
+        assert_eq!(process_mono(&mut chain, 0.5), 0.5);
+    }
+
+    #[test]
+    fn test_delay_is_tempo_synced() {
+        let mut chain = chain(|config| {
+            config.delay.enabled = true;
+            config.delay.tempo = 120.0;
+            config.delay.division = NoteDivision::Eighth;
+            config.delay.mix = 0.5;
+            config.delay.feedback = 0.0;
+        });
+        let response = impulse_response(&mut chain, SAMPLE_RATE as usize);
+
+        // An eighth note at 120 BPM is a quarter of a second
+        let echo = SAMPLE_RATE as usize / 4;
+        assert!(response[echo].abs() > 0.2);
+        assert!(response[1..echo].iter().all(|s| s.abs() < 1e-6));
+        assert!(response[echo + 2..].iter().all(|s| s.abs() < 1e-6));
+    }
+
+    #[test]
+    fn test_delay_follows_the_metronome_tempo() {
+        let mut chain = chain(|config| {
+            config.delay.enabled = true;
+            config.delay.division = NoteDivision::Eighth;
+            config.delay.mix = 0.5;
+            config.delay.feedback = 0.0;
+        });
+        chain.set_tempo(60.0);
+        let response = impulse_response(&mut chain, SAMPLE_RATE as usize);
+        // An eighth note at 60 BPM, not the configured 120
+        assert!(response[SAMPLE_RATE as usize / 2].abs() > 0.2);
+        assert!(response[SAMPLE_RATE as usize / 4].abs() < 1e-6);
+
+        // Unsynced, it keeps to its own tempo
+        let mut config = chain.config.clone();
+        config.delay.sync = false;
+        chain.configure(&config);
+        chain.channels.iter_mut().for_each(|channel| channel.delay.clear());
+        let response = impulse_response(&mut chain, SAMPLE_RATE as usize);
+        assert!(response[SAMPLE_RATE as usize / 4].abs() > 0.2);
+    }
+
+    #[test]
+    fn test_reverb_adds_a_decaying_tail() {
+        let mut chain = chain(|config| config.reverb.enabled = true);
+        let response = impulse_response(&mut chain, SAMPLE_RATE as usize * 3);
+
+        let energy = |range: std::ops::Range<usize>| response[range].iter().map(|s| s * s).sum::<f32>();
+        let early = energy(2000..12000);
+        let late = energy(100000..110000);
+        assert!(early > 0.0);
+        assert!(late < early);
+    }
+
+    #[test]
+    fn test_effect_params_stay_in_range() {
+        let mut config = EffectsConfig::default();
+        EffectParam::ReverbMix.adjust(&mut config, 100);
+        assert_eq!(config.reverb.mix, 1.0);
+        EffectParam::EqLow.adjust(&mut config, -3);
+        assert_eq!(config.eq.low, -3.0);
+        EffectParam::DelayDivision.adjust(&mut config, -10);
+        assert_eq!(config.delay.division, NoteDivision::Quarter);
+
+        assert!(!EffectParam::DelayMix.is_effect_enabled(&config));
+        EffectParam::DelayFeedback.toggle_effect(&mut config);
+        assert!(config.delay.enabled);
+    }
+
+    #[test]
+    fn test_eq_low_shelf_boosts_bass() {
+        let mut chain = chain(|config| {
+            config.eq.enabled = true;
+            config.eq.low = 6.0;
+        });
+        let frequency = 60.0;
+        let mut peak = 0.0f32;
+        for i in 0..SAMPLE_RATE {
+            let input = (2.0 * PI * frequency * i as f32 / SAMPLE_RATE as f32).sin() * 0.2;
+            let output = process_mono(&mut chain, input);
+            if i > SAMPLE_RATE / 2 {
+                peak = peak.max(output.abs());
+            }
+        }
+        // +6 dB is about double
+        assert!(peak > 0.35 && peak < 0.45, "peak {}", peak);
+    }
+}
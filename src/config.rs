@@ -1,22 +1,415 @@
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 use std::fs;
 use std::path::PathBuf;
 use anyhow::Result;
 
+use crate::instruments::{builtin_instruments, Instrument};
+
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct Config {
     pub audio: AudioConfig,
     pub ui: UiConfig,
     pub midi: MidiConfig,
     pub keybindings: KeyBindings,
+    #[serde(default)]
+    pub metronome: MetronomeConfig,
+    #[serde(default)]
+    pub export: ExportConfig,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct AudioConfig {
     pub sample_rate: u32,
     pub buffer_size: u32,
+    // Sample format of rendered WAV files: 16 or 24 bit integer, or 32 bit float
+    #[serde(default = "default_bit_depth")]
+    pub bit_depth: u16,
     pub sound_font: Option<String>,
+    // Bank and program of the SoundFont preset to play
+    #[serde(default)]
+    pub sound_font_bank: u16,
+    #[serde(default)]
+    pub sound_font_preset: u16,
     pub volume: f32,
+    #[serde(default = "default_instrument")]
+    pub instrument: String,
+    #[serde(default = "default_instruments")]
+    pub instruments: BTreeMap<String, InstrumentConfig>,
+    #[serde(default)]
+    pub velocity_curve: VelocityCurve,
+    // Velocity used for every note when `velocity_curve` is "fixed"
+    #[serde(default = "default_fixed_velocity")]
+    pub fixed_velocity: u8,
+    #[serde(default = "default_max_polyphony")]
+    pub max_polyphony: usize,
+    #[serde(default)]
+    pub voice_stealing: VoiceStealing,
+    // How far apart low and high notes sit in the stereo field, from 0.0
+    // (mono) to 1.0 (lowest key hard left, highest hard right)
+    #[serde(default = "default_stereo_width")]
+    pub stereo_width: f32,
+    // How far a full pitch bend moves notes, in semitones, until a MIDI file
+    // sets its own range
+    #[serde(default = "default_pitch_bend_range")]
+    pub pitch_bend_range: f32,
+    #[serde(default)]
+    pub effects: EffectsConfig,
+    #[serde(default)]
+    pub tuning: TuningConfig,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum VelocityCurve {
+    #[default]
+    Linear,
+    Soft,
+    Hard,
+    Fixed,
+}
+
+impl VelocityCurve {
+    // Maps a MIDI velocity to a 0.0-1.0 strength
+    pub fn apply(self, velocity: u8, fixed_velocity: u8) -> f32 {
+        let v = velocity.min(127) as f32 / 127.0;
+        match self {
+            VelocityCurve::Linear => v,
+            // Soft players reach full strength sooner
+            VelocityCurve::Soft => v.sqrt(),
+            // Hard players need to dig in for full strength
+            VelocityCurve::Hard => v * v,
+            VelocityCurve::Fixed => fixed_velocity.min(127) as f32 / 127.0,
+        }
+    }
+    
+    pub fn name(self) -> &'static str {
+        match self {
+            VelocityCurve::Linear => "linear",
+            VelocityCurve::Soft => "soft",
+            VelocityCurve::Hard => "hard",
+            VelocityCurve::Fixed => "fixed",
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct InstrumentConfig {
+    #[serde(default)]
+    pub envelope: EnvelopeConfig,
+    // What happens to a note that is still sounding when its key is struck again
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub retrigger: Option<RetriggerMode>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum RetriggerMode {
+    // The old note rings out through its normal release under the new one
+    #[default]
+    Overlap,
+    // The old note fades out quickly while the new one starts
+    Crossfade,
+}
+
+// Which voice gives way when a new note needs one and the limit is reached
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum VoiceStealing {
+    Oldest,
+    Quietest,
+    // Releasing voices first, then pedal-sustained ones, then held keys,
+    // oldest first within each group
+    #[default]
+    Released,
+}
+
+impl VoiceStealing {
+    pub fn name(self) -> &'static str {
+        match self {
+            VoiceStealing::Oldest => "oldest",
+            VoiceStealing::Quietest => "quietest",
+            VoiceStealing::Released => "released",
+        }
+    }
+}
+
+// Attack, decay and release are in seconds; sustain is a level from 0.0 to 1.0
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+pub struct EnvelopeConfig {
+    pub attack: f32,
+    pub decay: f32,
+    pub sustain: f32,
+    pub release: f32,
+}
+
+impl Default for EnvelopeConfig {
+    fn default() -> Self {
+        Self {
+            attack: 0.02,
+            decay: 0.2,
+            sustain: 0.7,
+            release: 0.3,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum TuningSystem {
+    #[default]
+    Equal,
+    Just,
+    Pythagorean,
+    Meantone,
+    // A Scala .scl file, optionally with a .kbm keyboard mapping
+    Scala,
+}
+
+impl TuningSystem {
+    pub fn name(self) -> &'static str {
+        match self {
+            TuningSystem::Equal => "equal",
+            TuningSystem::Just => "just",
+            TuningSystem::Pythagorean => "pythagorean",
+            TuningSystem::Meantone => "meantone",
+            TuningSystem::Scala => "scala",
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+#[serde(default)]
+pub struct TuningConfig {
+    pub system: TuningSystem,
+    // Frequency of A4 in Hz
+    pub reference: f32,
+    // Pitch class of the tonic (0 = C, 11 = B) for just, Pythagorean,
+    // meantone, and Scala scales without a keyboard mapping
+    pub root: u8,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub scala_file: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub keyboard_map: Option<String>,
+}
+
+impl Default for TuningConfig {
+    fn default() -> Self {
+        Self {
+            system: TuningSystem::Equal,
+            reference: 440.0,
+            root: 0,
+            scala_file: None,
+            keyboard_map: None,
+        }
+    }
+}
+
+// Master bus effects, applied in this order after the voices are mixed
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Default)]
+#[serde(default)]
+pub struct EffectsConfig {
+    pub eq: EqConfig,
+    pub chorus: ChorusConfig,
+    pub delay: DelayConfig,
+    pub reverb: ReverbConfig,
+}
+
+impl EffectsConfig {
+    // Every effect switched off, keeping the other settings
+    pub fn bypassed(&self) -> Self {
+        let mut effects = self.clone();
+        effects.eq.enabled = false;
+        effects.chorus.enabled = false;
+        effects.delay.enabled = false;
+        effects.reverb.enabled = false;
+        effects
+    }
+}
+
+// Gains are in dB for the low shelf, mid peak and high shelf bands
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+#[serde(default)]
+pub struct EqConfig {
+    pub enabled: bool,
+    pub low: f32,
+    pub mid: f32,
+    pub high: f32,
+}
+
+impl Default for EqConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            low: 0.0,
+            mid: 0.0,
+            high: 0.0,
+        }
+    }
+}
+
+// Rate is in Hz; depth and mix go from 0.0 to 1.0
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+#[serde(default)]
+pub struct ChorusConfig {
+    pub enabled: bool,
+    pub rate: f32,
+    pub depth: f32,
+    pub mix: f32,
+}
+
+impl Default for ChorusConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            rate: 0.8,
+            depth: 0.5,
+            mix: 0.35,
+        }
+    }
+}
+
+// The delay time is `division` at the metronome's tempo, which follows tap
+// tempo and a playing MIDI file. With `sync` off, or before the metronome
+// has a tempo, it is `division` at `tempo` beats per minute instead.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+#[serde(default)]
+pub struct DelayConfig {
+    pub enabled: bool,
+    pub sync: bool,
+    pub tempo: f32,
+    pub division: NoteDivision,
+    pub feedback: f32,
+    pub mix: f32,
+}
+
+impl Default for DelayConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            sync: true,
+            tempo: 120.0,
+            division: NoteDivision::DottedEighth,
+            feedback: 0.35,
+            mix: 0.25,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum NoteDivision {
+    Quarter,
+    DottedEighth,
+    Eighth,
+    Triplet,
+    Sixteenth,
+}
+
+impl NoteDivision {
+    pub const ALL: [NoteDivision; 5] = [
+        NoteDivision::Quarter,
+        NoteDivision::DottedEighth,
+        NoteDivision::Eighth,
+        NoteDivision::Triplet,
+        NoteDivision::Sixteenth,
+    ];
+
+    // Length in quarter-note beats
+    pub fn beats(self) -> f32 {
+        match self {
+            NoteDivision::Quarter => 1.0,
+            NoteDivision::DottedEighth => 0.75,
+            NoteDivision::Eighth => 0.5,
+            NoteDivision::Triplet => 1.0 / 3.0,
+            NoteDivision::Sixteenth => 0.25,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            NoteDivision::Quarter => "1/4",
+            NoteDivision::DottedEighth => "1/8 dotted",
+            NoteDivision::Eighth => "1/8",
+            NoteDivision::Triplet => "1/8 triplet",
+            NoteDivision::Sixteenth => "1/16",
+        }
+    }
+}
+
+// Room size, damping and mix go from 0.0 to 1.0
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+#[serde(default)]
+pub struct ReverbConfig {
+    pub enabled: bool,
+    pub room_size: f32,
+    pub damping: f32,
+    pub mix: f32,
+}
+
+impl Default for ReverbConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            room_size: 0.6,
+            damping: 0.5,
+            mix: 0.2,
+        }
+    }
+}
+
+fn default_stereo_width() -> f32 {
+    0.6
+}
+
+fn default_pitch_bend_range() -> f32 {
+    2.0
+}
+
+fn default_bit_depth() -> u16 {
+    16
+}
+
+fn default_fixed_velocity() -> u8 {
+    100
+}
+
+fn default_max_polyphony() -> usize {
+    64
+}
+
+fn default_instrument() -> String {
+    "piano".to_string()
+}
+
+fn default_instruments() -> BTreeMap<String, InstrumentConfig> {
+    builtin_instruments()
+        .iter()
+        .map(|instrument| {
+            let config = InstrumentConfig {
+                envelope: instrument.default_envelope(),
+                retrigger: Some(instrument.default_retrigger()),
+            };
+            (instrument.name().to_string(), config)
+        })
+        .collect()
+}
+
+impl AudioConfig {
+    // Instruments missing from the config table use their own defaults
+    pub fn envelope_for(&self, instrument: &dyn Instrument) -> EnvelopeConfig {
+        self.instruments
+            .get(instrument.name())
+            .map(|config| config.envelope)
+            .unwrap_or_else(|| instrument.default_envelope())
+    }
+    
+    pub fn retrigger_for(&self, instrument: &dyn Instrument) -> RetriggerMode {
+        self.instruments
+            .get(instrument.name())
+            .and_then(|config| config.retrigger)
+            .unwrap_or_else(|| instrument.default_retrigger())
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -27,10 +420,85 @@ pub struct UiConfig {
     pub animation_speed: f32,
 }
 
+// `bpm` counts beats of `beat_unit` (4 = quarter notes, 8 = eighths)
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+#[serde(default)]
+pub struct MetronomeConfig {
+    pub bpm: f32,
+    pub beats_per_bar: u8,
+    pub beat_unit: u8,
+    // Clicks per beat; 1 clicks on the beat only
+    pub subdivision: u8,
+    // Sound the first beat of each bar higher and louder
+    pub accent: bool,
+    pub volume: f32,
+}
+
+impl Default for MetronomeConfig {
+    fn default() -> Self {
+        Self {
+            bpm: 120.0,
+            beats_per_bar: 4,
+            beat_unit: 4,
+            subdivision: 1,
+            accent: true,
+            volume: 0.5,
+        }
+    }
+}
+
+// How recordings are written as Standard MIDI Files
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+#[serde(default)]
+pub struct ExportConfig {
+    // SMF type: 0 for a single track, 1 for a tempo track plus the notes
+    pub format: u8,
+    // Quarter notes per minute the bars and beats are laid out at. Notes
+    // keep their recorded timing whatever it is.
+    pub tempo: f32,
+    // Ticks per quarter note
+    pub ppq: u16,
+}
+
+// Largest tick count a 15-bit SMF division can hold
+const MAX_PPQ: u16 = 0x7fff;
+
+impl ExportConfig {
+    pub fn validate(&self) -> Result<()> {
+        if self.format > 1 {
+            return Err(anyhow::anyhow!("Unsupported SMF format: {} (use 0 or 1)", self.format));
+        }
+        if self.ppq == 0 || self.ppq > MAX_PPQ {
+            return Err(anyhow::anyhow!("PPQ must be between 1 and {}", MAX_PPQ));
+        }
+        if !(self.tempo.is_finite() && self.tempo > 0.0) {
+            return Err(anyhow::anyhow!("Tempo must be above 0 BPM"));
+        }
+        Ok(())
+    }
+}
+
+impl Default for ExportConfig {
+    fn default() -> Self {
+        Self {
+            format: 1,
+            tempo: 120.0,
+            ppq: 480,
+        }
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct MidiConfig {
     pub input_device: String,
     pub output_device: String,
+    // A bar of metronome clicks before playback starts in an A-B loop
+    #[serde(default)]
+    pub count_in: bool,
+    // Percent of the file's tempo added to the playback speed each time
+    // round a loop, until it is back to full speed
+    #[serde(default)]
+    pub loop_speed_up: f32,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -56,8 +524,21 @@ impl Default for Config {
             audio: AudioConfig {
                 sample_rate: 44100,
                 buffer_size: 256,
+                bit_depth: default_bit_depth(),
                 sound_font: None,
+                sound_font_bank: 0,
+                sound_font_preset: 0,
                 volume: 0.7,
+                instrument: default_instrument(),
+                instruments: default_instruments(),
+                velocity_curve: VelocityCurve::default(),
+                fixed_velocity: default_fixed_velocity(),
+                max_polyphony: default_max_polyphony(),
+                voice_stealing: VoiceStealing::default(),
+                stereo_width: default_stereo_width(),
+                pitch_bend_range: default_pitch_bend_range(),
+                effects: EffectsConfig::default(),
+                tuning: TuningConfig::default(),
             },
             ui: UiConfig {
                 color_scheme: "classic".to_string(),
@@ -68,6 +549,8 @@ impl Default for Config {
             midi: MidiConfig {
                 input_device: "auto".to_string(),
                 output_device: "auto".to_string(),
+                count_in: false,
+                loop_speed_up: 0.0,
             },
             keybindings: KeyBindings {
                 white_keys: vec![
@@ -89,6 +572,8 @@ impl Default for Config {
                 help: 'F', // F1 key, represented as 'F' in config
                 quit: 'Q',
             },
+            metronome: MetronomeConfig::default(),
+            export: ExportConfig::default(),
         }
     }
 }
@@ -125,6 +610,12 @@ impl Config {
         Ok(home.join(".terminal-piano").join("config.toml"))
     }
     
+    // Mixer settings saved for each MIDI file
+    pub fn mixer_path() -> Result<PathBuf> {
+        let home = dirs::home_dir().ok_or_else(|| anyhow::anyhow!("Could not find home directory"))?;
+        Ok(home.join(".terminal-piano").join("mixer.toml"))
+    }
+    
     pub fn recordings_dir() -> Result<PathBuf> {
         let home = dirs::home_dir().ok_or_else(|| anyhow::anyhow!("Could not find home directory"))?;
         let dir = home.join(".terminal-piano").join("recordings");
@@ -0,0 +1,394 @@
+use std::f32::consts::PI;
+
+use crate::config::EnvelopeConfig;
+use crate::instruments::{Generator, Instrument, NoteParams};
+
+// General MIDI sends percussion on channel 10, which is 9 counting from zero
+pub const DRUM_CHANNEL: u8 = 9;
+// SoundFont bank that holds drum kits
+pub const PERCUSSION_BANK: u16 = 128;
+
+// Frequencies of the square waves behind the metallic cymbal and hat sounds,
+// inharmonic on purpose
+const METAL_FREQUENCIES: [f32; 6] = [205.3, 304.4, 369.6, 522.7, 540.0, 800.0];
+// Below this level every part of a hit has died away
+const SILENCE_THRESHOLD: f32 = 1.0e-4;
+
+// The drum-lane rows a hit is drawn on
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DrumLane {
+    Kick,
+    Snare,
+    HiHat,
+    Toms,
+    Cymbals,
+    Percussion,
+}
+
+impl DrumLane {
+    pub const ALL: [DrumLane; 6] = [
+        DrumLane::Kick,
+        DrumLane::Snare,
+        DrumLane::HiHat,
+        DrumLane::Toms,
+        DrumLane::Cymbals,
+        DrumLane::Percussion,
+    ];
+
+    pub fn for_note(midi_note: u8) -> Self {
+        match midi_note {
+            35 | 36 => DrumLane::Kick,
+            37..=40 => DrumLane::Snare,
+            42 | 44 | 46 => DrumLane::HiHat,
+            41 | 43 | 45 | 47 | 48 | 50 => DrumLane::Toms,
+            49 | 51 | 52 | 53 | 55 | 57 | 59 => DrumLane::Cymbals,
+            _ => DrumLane::Percussion,
+        }
+    }
+
+    pub fn label(self) -> &'static str {
+        match self {
+            DrumLane::Kick => "Kick",
+            DrumLane::Snare => "Snare",
+            DrumLane::HiHat => "Hi-hat",
+            DrumLane::Toms => "Toms",
+            DrumLane::Cymbals => "Cymbals",
+            DrumLane::Percussion => "Perc",
+        }
+    }
+}
+
+// How one drum sound is built: a pitched body that sweeps down, plus noise
+// and metallic squares through a highpass. Times are decay time constants.
+#[derive(Debug, Clone, Copy)]
+struct Recipe {
+    tone_start: f32,
+    tone_end: f32,
+    sweep_seconds: f32,
+    tone_level: f32,
+    tone_seconds: f32,
+    noise_level: f32,
+    noise_seconds: f32,
+    metal_level: f32,
+    metal_seconds: f32,
+    highpass: f32,
+    pan: f32,
+}
+
+const SILENT: Recipe = Recipe {
+    tone_start: 0.0,
+    tone_end: 0.0,
+    sweep_seconds: 0.01,
+    tone_level: 0.0,
+    tone_seconds: 0.01,
+    noise_level: 0.0,
+    noise_seconds: 0.01,
+    metal_level: 0.0,
+    metal_seconds: 0.01,
+    highpass: 20.0,
+    pan: 0.0,
+};
+
+fn tom(frequency: f32, pan: f32) -> Recipe {
+    Recipe {
+        tone_start: frequency * 1.6,
+        tone_end: frequency,
+        sweep_seconds: 0.05,
+        tone_level: 0.9,
+        tone_seconds: 0.25,
+        noise_level: 0.1,
+        noise_seconds: 0.03,
+        highpass: 800.0,
+        pan,
+        ..SILENT
+    }
+}
+
+fn cymbal(seconds: f32, highpass: f32, pan: f32) -> Recipe {
+    Recipe {
+        noise_level: 0.5,
+        noise_seconds: seconds,
+        metal_level: 0.4,
+        metal_seconds: seconds * 0.8,
+        highpass,
+        pan,
+        ..SILENT
+    }
+}
+
+fn hi_hat(seconds: f32) -> Recipe {
+    Recipe {
+        noise_level: 0.4,
+        noise_seconds: seconds,
+        metal_level: 0.5,
+        metal_seconds: seconds,
+        highpass: 7000.0,
+        pan: 0.35,
+        ..SILENT
+    }
+}
+
+fn recipe(midi_note: u8) -> Recipe {
+    match midi_note {
+        // Kicks
+        35 | 36 => Recipe {
+            tone_start: 160.0,
+            tone_end: 48.0,
+            sweep_seconds: 0.03,
+            tone_level: 1.0,
+            tone_seconds: if midi_note == 35 { 0.35 } else { 0.25 },
+            noise_level: 0.15,
+            noise_seconds: 0.004,
+            highpass: 1500.0,
+            ..SILENT
+        },
+        // Side stick
+        37 => Recipe {
+            tone_start: 450.0,
+            tone_end: 400.0,
+            tone_level: 0.5,
+            tone_seconds: 0.02,
+            noise_level: 0.4,
+            noise_seconds: 0.02,
+            highpass: 2000.0,
+            pan: -0.1,
+            ..SILENT
+        },
+        // Snares
+        38 | 40 => Recipe {
+            tone_start: 240.0,
+            tone_end: 180.0,
+            sweep_seconds: 0.02,
+            tone_level: 0.5,
+            tone_seconds: 0.07,
+            noise_level: 0.8,
+            noise_seconds: if midi_note == 40 { 0.12 } else { 0.09 },
+            highpass: 1200.0,
+            pan: -0.1,
+            ..SILENT
+        },
+        // Hand clap
+        39 => Recipe {
+            noise_level: 0.9,
+            noise_seconds: 0.08,
+            highpass: 900.0,
+            pan: -0.2,
+            ..SILENT
+        },
+        // Closed, pedal and open hi-hats
+        42 => hi_hat(0.03),
+        44 => hi_hat(0.05),
+        46 => hi_hat(0.3),
+        // Toms from low floor to high, spread across the kit
+        41 => tom(82.0, 0.5),
+        43 => tom(98.0, 0.4),
+        45 => tom(116.0, 0.2),
+        47 => tom(138.0, 0.0),
+        48 => tom(164.0, -0.2),
+        50 => tom(196.0, -0.4),
+        // Crashes, china, splash and rides
+        49 => cymbal(0.9, 4000.0, -0.5),
+        57 => cymbal(0.9, 4500.0, 0.5),
+        52 => cymbal(0.8, 3000.0, 0.6),
+        55 => cymbal(0.35, 6000.0, -0.3),
+        51 | 59 => Recipe { noise_level: 0.25, metal_level: 0.5, ..cymbal(0.7, 5000.0, 0.45) },
+        53 => Recipe {
+            tone_start: 820.0,
+            tone_end: 820.0,
+            tone_level: 0.4,
+            tone_seconds: 0.4,
+            ..cymbal(0.5, 5000.0, 0.45)
+        },
+        // Tambourine
+        54 => Recipe {
+            noise_level: 0.5,
+            noise_seconds: 0.12,
+            metal_level: 0.3,
+            metal_seconds: 0.1,
+            highpass: 6000.0,
+            pan: 0.3,
+            ..SILENT
+        },
+        // Cowbell
+        56 => Recipe {
+            tone_start: 560.0,
+            tone_end: 560.0,
+            tone_level: 0.5,
+            tone_seconds: 0.12,
+            metal_level: 0.3,
+            metal_seconds: 0.1,
+            highpass: 400.0,
+            pan: 0.25,
+            ..SILENT
+        },
+        // Bongos, congas, timbales, blocks and the rest: short pitched hits
+        // that rise with the key
+        _ => {
+            let frequency = 150.0 * 2.0_f32.powf((midi_note as f32 - 60.0) / 24.0);
+            Recipe {
+                tone_start: frequency * 1.2,
+                tone_end: frequency,
+                sweep_seconds: 0.02,
+                tone_level: 0.7,
+                tone_seconds: 0.1,
+                noise_level: 0.2,
+                noise_seconds: 0.02,
+                highpass: 1000.0,
+                pan: ((midi_note as f32 - 60.0) / 24.0).clamp(-0.6, 0.6),
+                ..SILENT
+            }
+        }
+    }
+}
+
+// Synthesized General MIDI drum kit, played from the percussion channel.
+// Each key is a different drum rather than a pitch.
+#[derive(Debug)]
+pub struct DrumKit;
+
+struct DrumVoice {
+    sample_rate: f32,
+    tone_phase: f32,
+    tone_frequency: f32,
+    tone_end: f32,
+    sweep: f32,
+    tone_level: f32,
+    tone_decay: f32,
+    noise_level: f32,
+    noise_decay: f32,
+    metal_phases: [f32; 6],
+    metal_level: f32,
+    metal_decay: f32,
+    highpass_coefficient: f32,
+    lowpass_state: f32,
+    seed: u32,
+    pan: f32,
+}
+
+impl Instrument for DrumKit {
+    fn name(&self) -> &str {
+        "drums"
+    }
+
+    fn default_envelope(&self) -> EnvelopeConfig {
+        // Each hit shapes itself; the envelope only softens a choke
+        EnvelopeConfig {
+            attack: 0.0,
+            decay: 0.0,
+            sustain: 1.0,
+            release: 0.05,
+        }
+    }
+
+    fn start_note(&self, note: &NoteParams) -> Option<Box<dyn Generator>> {
+        let recipe = recipe(note.midi_note);
+        let sample_rate = note.sample_rate as f32;
+        let decay = |seconds: f32| (-1.0 / (seconds * sample_rate)).exp();
+        Some(Box::new(DrumVoice {
+            sample_rate,
+            tone_phase: 0.0,
+            tone_frequency: recipe.tone_start,
+            tone_end: recipe.tone_end,
+            sweep: decay(recipe.sweep_seconds),
+            tone_level: recipe.tone_level,
+            tone_decay: decay(recipe.tone_seconds),
+            noise_level: recipe.noise_level,
+            noise_decay: decay(recipe.noise_seconds),
+            metal_phases: [0.0; 6],
+            metal_level: recipe.metal_level,
+            metal_decay: decay(recipe.metal_seconds),
+            highpass_coefficient: 1.0 - (-2.0 * PI * recipe.highpass / sample_rate).exp(),
+            lowpass_state: 0.0,
+            seed: 0x2545_f491 ^ (note.midi_note as u32).wrapping_mul(0x9e37_79b9),
+            pan: recipe.pan,
+        }))
+    }
+}
+
+impl DrumVoice {
+    fn noise(&mut self) -> f32 {
+        self.seed ^= self.seed << 13;
+        self.seed ^= self.seed >> 17;
+        self.seed ^= self.seed << 5;
+        self.seed as f32 / u32::MAX as f32 * 2.0 - 1.0
+    }
+}
+
+impl Generator for DrumVoice {
+    fn next_sample(&mut self) -> f32 {
+        self.tone_phase = (self.tone_phase + self.tone_frequency / self.sample_rate).fract();
+        let tone = (2.0 * PI * self.tone_phase).sin() * self.tone_level;
+        self.tone_frequency = self.tone_end + (self.tone_frequency - self.tone_end) * self.sweep;
+
+        let mut metal = 0.0;
+        if self.metal_level > SILENCE_THRESHOLD {
+            for (phase, frequency) in self.metal_phases.iter_mut().zip(METAL_FREQUENCIES) {
+                *phase = (*phase + frequency / self.sample_rate).fract();
+                metal += if *phase < 0.5 { 1.0 } else { -1.0 };
+            }
+            metal *= self.metal_level / METAL_FREQUENCIES.len() as f32;
+        }
+
+        // Noise and metal only keep what lies above the highpass cutoff
+        let bright = self.noise() * self.noise_level + metal;
+        self.lowpass_state += self.highpass_coefficient * (bright - self.lowpass_state);
+
+        self.tone_level *= self.tone_decay;
+        self.noise_level *= self.noise_decay;
+        self.metal_level *= self.metal_decay;
+        (tone + bright - self.lowpass_state).clamp(-1.0, 1.0)
+    }
+
+    fn is_finished(&self) -> bool {
+        self.tone_level < SILENCE_THRESHOLD
+            && self.noise_level < SILENCE_THRESHOLD
+            && self.metal_level < SILENCE_THRESHOLD
+    }
+
+    fn pan(&self) -> Option<f32> {
+        (self.pan != 0.0).then_some(self.pan)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hit(midi_note: u8) -> Vec<f32> {
+        let note = NoteParams {
+            midi_note,
+            velocity: 127,
+            strength: 1.0,
+            frequency: 440.0,
+            sample_rate: 44100,
+        };
+        let mut generator = DrumKit.start_note(&note).unwrap();
+        let mut samples = Vec::new();
+        while !generator.is_finished() && samples.len() < 44100 * 10 {
+            samples.push(generator.next_sample());
+        }
+        samples
+    }
+
+    #[test]
+    fn test_every_drum_sounds_and_dies_away() {
+        for midi_note in 27..=87 {
+            let samples = hit(midi_note);
+            let peak = samples.iter().fold(0.0_f32, |acc, s| acc.max(s.abs()));
+            assert!(peak > 0.05, "drum {} is silent", midi_note);
+            assert!(samples.len() < 44100 * 10, "drum {} never ends", midi_note);
+        }
+        // A crash rings far longer than a closed hi-hat
+        assert!(hit(49).len() > hit(42).len() * 10);
+    }
+
+    #[test]
+    fn test_lanes() {
+        assert_eq!(DrumLane::for_note(36), DrumLane::Kick);
+        assert_eq!(DrumLane::for_note(38), DrumLane::Snare);
+        assert_eq!(DrumLane::for_note(46), DrumLane::HiHat);
+        assert_eq!(DrumLane::for_note(45), DrumLane::Toms);
+        assert_eq!(DrumLane::for_note(49), DrumLane::Cymbals);
+        assert_eq!(DrumLane::for_note(63), DrumLane::Percussion);
+    }
+}
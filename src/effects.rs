@@ -1,6 +1,10 @@
 use ratatui::style::Color;
 use std::time::{Duration, Instant};
-use rand;
+
+use crate::drums::DrumLane;
+
+// How far back the drum lanes show hits
+pub const DRUM_LANE_WINDOW: Duration = Duration::from_secs(3);
 
 #[derive(Debug, Clone)]
 pub struct KeyPressEffect {
@@ -83,11 +87,19 @@ impl ParticleEffect {
     }
 }
 
+#[derive(Debug, Clone)]
+pub struct DrumHit {
+    pub lane: DrumLane,
+    pub velocity: u8,
+    pub time: Instant,
+}
+
 #[derive(Debug)]
 pub struct VisualEffects {
     pub key_effects: Vec<(u8, KeyPressEffect)>,
     pub particles: Vec<ParticleEffect>,
     pub glow_effects: Vec<(u16, u16, KeyPressEffect)>,
+    pub drum_hits: Vec<DrumHit>,
     pub last_update: Instant,
 }
 
@@ -97,6 +109,7 @@ impl VisualEffects {
             key_effects: Vec::new(),
             particles: Vec::new(),
             glow_effects: Vec::new(),
+            drum_hits: Vec::new(),
             last_update: Instant::now(),
         }
     }
@@ -113,6 +126,15 @@ impl VisualEffects {
         self.glow_effects.push((x, y, KeyPressEffect::new(color)));
     }
     
+    // Percussion is drawn on the drum lanes instead of lighting up keys
+    pub fn add_drum_hit(&mut self, midi_note: u8, velocity: u8) {
+        self.drum_hits.push(DrumHit {
+            lane: DrumLane::for_note(midi_note),
+            velocity,
+            time: Instant::now(),
+        });
+    }
+    
     pub fn update(&mut self) {
         let now = Instant::now();
         let dt = now.duration_since(self.last_update).as_secs_f32();
@@ -126,6 +148,7 @@ impl VisualEffects {
         self.particles.retain(|p| p.is_alive());
         
         self.glow_effects.retain(|(_, _, effect)| effect.is_active());
+        self.drum_hits.retain(|hit| hit.time.elapsed() < DRUM_LANE_WINDOW);
     }
     
     pub fn get_key_color(&self, midi_note: u8, base_color: Color) -> Color {
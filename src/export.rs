@@ -0,0 +1,135 @@
+use anyhow::Result;
+use midly::{Format, Header, MetaMessage, MidiMessage, Smf, Timing, TrackEvent, TrackEventKind};
+use std::path::{Path, PathBuf};
+
+use crate::audio::Recording;
+use crate::config::ExportConfig;
+
+// Converts a saved recording to a Standard MIDI File next to it, or at
+// `output`. Returns the path that was written.
+pub fn convert_recording(input: &Path, output: Option<&Path>, config: &ExportConfig) -> Result<PathBuf> {
+    let recording = Recording::load_from_file(input)?;
+    let output = output
+        .map(Path::to_path_buf)
+        .unwrap_or_else(|| input.with_extension("mid"));
+    export_recording(&recording, &output, config)?;
+    Ok(output)
+}
+
+pub fn export_recording(recording: &Recording, path: &Path, config: &ExportConfig) -> Result<()> {
+    recording_to_smf(recording, config)?.save(path)?;
+    Ok(())
+}
+
+// Lays the recording out on a grid of `config.ppq` ticks per quarter note
+// at `config.tempo`. Type 0 puts everything in one track; type 1 keeps the
+// tempo and time signature in a track of their own ahead of the notes.
+pub fn recording_to_smf(recording: &Recording, config: &ExportConfig) -> Result<Smf<'static>> {
+    // A bad [export] section only matters once something is exported
+    config.validate()?;
+    let format = if config.format == 0 { Format::SingleTrack } else { Format::Parallel };
+
+    let micros_per_quarter = (60_000_000.0 / config.tempo as f64).round().clamp(1.0, 0xff_ffff as f64) as u32;
+    let ticks_per_second = 1_000_000.0 / micros_per_quarter as f64 * config.ppq as f64;
+    let to_ticks = |time: std::time::Duration| (time.as_secs_f64() * ticks_per_second).round() as u64;
+
+    let conductor = vec![
+        meta(0, MetaMessage::Tempo(micros_per_quarter.into())),
+        meta(0, MetaMessage::TimeSignature(4, 2, 24, 8)),
+    ];
+    let mut notes = vec![meta(0, MetaMessage::TrackName(b"Piano"))];
+    let mut last_tick = 0;
+    for (time, channel, message) in recording.timed_messages() {
+        let tick = to_ticks(time).max(last_tick);
+        notes.push(midi(tick - last_tick, channel, message));
+        last_tick = tick;
+    }
+    // The track runs on to the end of the take, past the last release
+    let end = to_ticks(recording.duration).max(last_tick);
+    notes.push(meta(end - last_tick, MetaMessage::EndOfTrack));
+
+    let tracks = if format == Format::SingleTrack {
+        vec![conductor.into_iter().chain(notes).collect()]
+    } else {
+        vec![
+            conductor.into_iter().chain([meta(0, MetaMessage::EndOfTrack)]).collect(),
+            notes,
+        ]
+    };
+    Ok(Smf {
+        header: Header::new(format, Timing::Metrical(config.ppq.into())),
+        tracks,
+    })
+}
+
+fn meta(delta: u64, message: MetaMessage<'static>) -> TrackEvent<'static> {
+    TrackEvent { delta: delta_ticks(delta), kind: TrackEventKind::Meta(message) }
+}
+
+fn midi(delta: u64, channel: u8, message: MidiMessage) -> TrackEvent<'static> {
+    TrackEvent {
+        delta: delta_ticks(delta),
+        kind: TrackEventKind::Midi { channel: (channel & 0x0f).into(), message },
+    }
+}
+
+// Deltas are 28-bit; a gap longer than that (days at any sane tempo) is cut short
+fn delta_ticks(ticks: u64) -> midly::num::u28 {
+    (ticks.min(0x0fff_ffff) as u32).into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::audio::{RecordingEventType, LIVE_CHANNEL};
+    use crate::midi::MidiPlayer;
+    use std::time::Duration;
+
+    fn test_recording() -> Recording {
+        let mut recording = Recording::new();
+        let mut add = |millis: u64, event_type: RecordingEventType| {
+            recording.events.push(crate::audio::RecordingEvent { timestamp: Duration::from_millis(millis), event_type });
+        };
+        add(0, RecordingEventType::SustainPedal { pressed: true });
+        add(250, RecordingEventType::NoteOn { midi_note: 60, velocity: 90 });
+        add(700, RecordingEventType::NoteOff { midi_note: 60 });
+        add(1000, RecordingEventType::SustainPedal { pressed: false });
+        recording.duration = Duration::from_millis(1500);
+        recording
+    }
+
+    #[test]
+    fn test_export_keeps_recorded_timing() {
+        for (format, tempo, ppq) in [(0, 120.0, 480), (1, 93.0, 96)] {
+            let config = ExportConfig { format, tempo, ppq };
+            let path = std::env::temp_dir().join(format!("terminal-piano-export-{}-{}.mid", std::process::id(), format));
+            export_recording(&test_recording(), &path, &config).unwrap();
+
+            let data = std::fs::read(&path).unwrap();
+            let smf = Smf::parse(&data).unwrap();
+            assert_eq!(smf.tracks.len(), format as usize + 1);
+            assert_eq!(smf.header.timing, Timing::Metrical(ppq.into()));
+
+            let mut player = MidiPlayer::new();
+            player.load_file(&path).unwrap();
+            std::fs::remove_file(&path).ok();
+            let messages = player.timed_messages();
+            let expected = test_recording().timed_messages();
+            assert_eq!(messages.len(), expected.len());
+            for ((time, channel, message), (expected_time, _, expected_message)) in messages.iter().zip(&expected) {
+                // Within a tick of where it was played
+                assert!((time.as_secs_f64() - expected_time.as_secs_f64()).abs() < 60.0 / tempo as f64 / ppq as f64);
+                assert_eq!((*channel, message), (LIVE_CHANNEL, expected_message));
+            }
+        }
+    }
+
+    #[test]
+    fn test_export_rejects_bad_settings() {
+        let recording = test_recording();
+        let config = ExportConfig::default();
+        assert!(recording_to_smf(&recording, &ExportConfig { format: 2, ..config }).is_err());
+        assert!(recording_to_smf(&recording, &ExportConfig { ppq: 0, ..config }).is_err());
+        assert!(recording_to_smf(&recording, &ExportConfig { tempo: 0.0, ..config }).is_err());
+    }
+}
@@ -0,0 +1,677 @@
+use std::f32::consts::PI;
+use std::fmt;
+use std::sync::Arc;
+
+use crate::config::{EnvelopeConfig, RetriggerMode};
+
+// Everything an instrument needs to know to start a note
+#[derive(Debug, Clone, Copy)]
+pub struct NoteParams {
+    pub midi_note: u8,
+    pub velocity: u8,
+    // Velocity after the velocity curve, from 0.0 to 1.0
+    pub strength: f32,
+    pub frequency: f32,
+    pub sample_rate: u32,
+}
+
+// A playable patch. The synth asks it for a `Generator` per note and wraps
+// that in an ADSR envelope and voice management, so a new sound only has to
+// implement this trait and be added to `builtin_instruments`.
+pub trait Instrument: Send + Sync + fmt::Debug {
+    fn name(&self) -> &str;
+    fn default_envelope(&self) -> EnvelopeConfig;
+
+    fn default_retrigger(&self) -> RetriggerMode {
+        RetriggerMode::Overlap
+    }
+
+    // `None` means the instrument has nothing to play for this note
+    fn start_note(&self, note: &NoteParams) -> Option<Box<dyn Generator>>;
+}
+
+// Produces the raw waveform of one sounding note
+pub trait Generator: Send {
+    fn next_sample(&mut self) -> f32;
+
+    fn release(&mut self) {}
+
+    fn is_finished(&self) -> bool {
+        false
+    }
+
+    // Generators that carry their own amplitude envelope (such as SoundFont
+    // zones) return it here to replace the instrument's
+    fn envelope(&self) -> Option<EnvelopeConfig> {
+        None
+    }
+
+    // A fixed pan position (-1.0 left to 1.0 right) in place of the
+    // key-position default, for generators that place themselves
+    fn pan(&self) -> Option<f32> {
+        None
+    }
+}
+
+pub fn builtin_instruments() -> Vec<Arc<dyn Instrument>> {
+    vec![
+        Arc::new(AcousticPiano),
+        Arc::new(ElectricPiano),
+        Arc::new(DrawbarOrgan),
+        Arc::new(Strings),
+        Arc::new(ChiptuneLead),
+        Arc::new(PluckedString),
+        Arc::new(Bass),
+        Arc::new(Brass),
+        Arc::new(Sine),
+    ]
+}
+
+pub fn builtin_instrument(name: &str) -> Option<Arc<dyn Instrument>> {
+    builtin_instruments().into_iter().find(|i| i.name() == name)
+}
+
+fn envelope(attack: f32, decay: f32, sustain: f32, release: f32) -> EnvelopeConfig {
+    EnvelopeConfig { attack, decay, sustain, release }
+}
+
+// Phase accumulator running from 0.0 to 1.0
+#[derive(Debug, Clone, Copy)]
+struct Phase {
+    value: f32,
+    increment: f32,
+}
+
+impl Phase {
+    fn new(frequency: f32, sample_rate: u32) -> Self {
+        Self {
+            value: 0.0,
+            increment: frequency / sample_rate as f32,
+        }
+    }
+
+    fn advance(&mut self) -> f32 {
+        let current = self.value;
+        self.value = (self.value + self.increment).fract();
+        current
+    }
+}
+
+// Smooths the discontinuity of naive saw and square waves to cut aliasing
+fn poly_blep(t: f32, dt: f32) -> f32 {
+    if t < dt {
+        let t = t / dt;
+        t + t - t * t - 1.0
+    } else if t > 1.0 - dt {
+        let t = (t - 1.0) / dt;
+        t * t + t + t + 1.0
+    } else {
+        0.0
+    }
+}
+
+fn saw(phase: &mut Phase) -> f32 {
+    let t = phase.advance();
+    2.0 * t - 1.0 - poly_blep(t, phase.increment)
+}
+
+fn square(phase: &mut Phase, duty: f32) -> f32 {
+    let t = phase.advance();
+    let naive = if t < duty { 1.0 } else { -1.0 };
+    naive + poly_blep(t, phase.increment) - poly_blep((t + 1.0 - duty).fract(), phase.increment)
+}
+
+#[derive(Debug)]
+pub struct Sine;
+
+struct SineVoice {
+    phase: Phase,
+    brightness: f32,
+}
+
+impl Instrument for Sine {
+    fn name(&self) -> &str {
+        "sine"
+    }
+
+    fn default_envelope(&self) -> EnvelopeConfig {
+        envelope(0.02, 0.2, 0.7, 0.3)
+    }
+
+    fn start_note(&self, note: &NoteParams) -> Option<Box<dyn Generator>> {
+        Some(Box::new(SineVoice {
+            phase: Phase::new(note.frequency, note.sample_rate),
+            brightness: note.strength,
+        }))
+    }
+}
+
+impl Generator for SineVoice {
+    fn next_sample(&mut self) -> f32 {
+        let angle = 2.0 * PI * self.phase.advance();
+        // Harder notes bring in upper harmonics on top of the fundamental
+        let harmonics = 0.3 * (2.0 * angle).sin() + 0.15 * (3.0 * angle).sin();
+        (angle.sin() + self.brightness * harmonics) / (1.0 + 0.45 * self.brightness)
+    }
+}
+
+// Additive piano: slightly stretched partials, each decaying faster than the
+// one below it, with velocity deciding how many upper partials ring
+#[derive(Debug)]
+pub struct AcousticPiano;
+
+const PIANO_PARTIALS: usize = 8;
+// String stiffness that sharpens the upper partials
+const PIANO_INHARMONICITY: f32 = 0.0004;
+
+struct PianoVoice {
+    phases: [Phase; PIANO_PARTIALS],
+    amplitudes: [f32; PIANO_PARTIALS],
+    decays: [f32; PIANO_PARTIALS],
+    normalization: f32,
+}
+
+impl Instrument for AcousticPiano {
+    fn name(&self) -> &str {
+        "piano"
+    }
+
+    fn default_envelope(&self) -> EnvelopeConfig {
+        // The partials decay on their own; the envelope only shapes the ends
+        envelope(0.002, 6.0, 0.0, 0.25)
+    }
+
+    fn start_note(&self, note: &NoteParams) -> Option<Box<dyn Generator>> {
+        let nyquist = note.sample_rate as f32 / 2.0;
+        let mut phases = [Phase::new(0.0, note.sample_rate); PIANO_PARTIALS];
+        let mut amplitudes = [0.0; PIANO_PARTIALS];
+        let mut decays = [1.0; PIANO_PARTIALS];
+
+        // Low notes ring longer than high ones
+        let base_decay_seconds = (3.0 * (261.6 / note.frequency).powf(0.5)).clamp(0.5, 8.0);
+
+        for n in 0..PIANO_PARTIALS {
+            let harmonic = (n + 1) as f32;
+            let frequency = note.frequency * harmonic * (1.0 + PIANO_INHARMONICITY * harmonic * harmonic).sqrt();
+            if frequency >= nyquist {
+                break;
+            }
+            phases[n] = Phase::new(frequency, note.sample_rate);
+            amplitudes[n] = note.strength.powf(0.5 * (harmonic - 1.0)) / harmonic;
+            let decay_seconds = base_decay_seconds / harmonic;
+            decays[n] = (-1.0 / (decay_seconds * note.sample_rate as f32)).exp();
+        }
+
+        let normalization = 1.0 / amplitudes.iter().sum::<f32>().max(1.0);
+        Some(Box::new(PianoVoice { phases, amplitudes, decays, normalization }))
+    }
+}
+
+impl Generator for PianoVoice {
+    fn next_sample(&mut self) -> f32 {
+        let mut value = 0.0;
+        for n in 0..PIANO_PARTIALS {
+            value += (2.0 * PI * self.phases[n].advance()).sin() * self.amplitudes[n];
+            self.amplitudes[n] *= self.decays[n];
+        }
+        value * self.normalization
+    }
+}
+
+// Two-operator FM tine sound: a modulator at the carrier frequency whose
+// index falls away after the strike, plus a short high "tine" partial
+#[derive(Debug)]
+pub struct ElectricPiano;
+
+struct ElectricPianoVoice {
+    carrier: Phase,
+    modulator: Phase,
+    tine: Phase,
+    index: f32,
+    index_floor: f32,
+    index_decay: f32,
+    tine_level: f32,
+    tine_decay: f32,
+}
+
+impl Instrument for ElectricPiano {
+    fn name(&self) -> &str {
+        "electric-piano"
+    }
+
+    fn default_envelope(&self) -> EnvelopeConfig {
+        envelope(0.002, 2.5, 0.15, 0.3)
+    }
+
+    fn start_note(&self, note: &NoteParams) -> Option<Box<dyn Generator>> {
+        let sample_rate = note.sample_rate as f32;
+        Some(Box::new(ElectricPianoVoice {
+            carrier: Phase::new(note.frequency, note.sample_rate),
+            modulator: Phase::new(note.frequency, note.sample_rate),
+            tine: Phase::new((note.frequency * 14.0).min(sample_rate * 0.45), note.sample_rate),
+            index: 0.5 + 3.0 * note.strength,
+            index_floor: 0.3,
+            index_decay: (-1.0 / (0.4 * sample_rate)).exp(),
+            tine_level: 0.25 * note.strength,
+            tine_decay: (-1.0 / (0.03 * sample_rate)).exp(),
+        }))
+    }
+}
+
+impl Generator for ElectricPianoVoice {
+    fn next_sample(&mut self) -> f32 {
+        let modulation = (2.0 * PI * self.modulator.advance()).sin() * self.index;
+        let carrier = (2.0 * PI * self.carrier.advance() + modulation).sin();
+        let tine = (2.0 * PI * self.tine.advance()).sin() * self.tine_level;
+
+        self.index = self.index_floor + (self.index - self.index_floor) * self.index_decay;
+        self.tine_level *= self.tine_decay;
+        (carrier + tine) / 1.25
+    }
+}
+
+// Tonewheel organ with a fixed "888000000"-style drawbar registration and a
+// touch of 4' for sparkle
+#[derive(Debug)]
+pub struct DrawbarOrgan;
+
+// Footages 16', 5 1/3', 8', 4', 2 2/3', 2', 1 3/5', 1 1/3', 1'
+const DRAWBAR_RATIOS: [f32; 9] = [0.5, 1.5, 1.0, 2.0, 3.0, 4.0, 5.0, 6.0, 8.0];
+const DRAWBAR_LEVELS: [f32; 9] = [1.0, 1.0, 1.0, 0.4, 0.0, 0.2, 0.0, 0.0, 0.1];
+
+struct OrganVoice {
+    phases: [Phase; 9],
+    levels: [f32; 9],
+    normalization: f32,
+}
+
+impl Instrument for DrawbarOrgan {
+    fn name(&self) -> &str {
+        "organ"
+    }
+
+    fn default_envelope(&self) -> EnvelopeConfig {
+        envelope(0.005, 0.0, 1.0, 0.05)
+    }
+
+    fn default_retrigger(&self) -> RetriggerMode {
+        RetriggerMode::Crossfade
+    }
+
+    fn start_note(&self, note: &NoteParams) -> Option<Box<dyn Generator>> {
+        let nyquist = note.sample_rate as f32 / 2.0;
+        let mut phases = [Phase::new(0.0, note.sample_rate); 9];
+        let mut levels = [0.0; 9];
+        for n in 0..DRAWBAR_RATIOS.len() {
+            let frequency = note.frequency * DRAWBAR_RATIOS[n];
+            if frequency < nyquist {
+                phases[n] = Phase::new(frequency, note.sample_rate);
+                levels[n] = DRAWBAR_LEVELS[n];
+            }
+        }
+        let normalization = 1.0 / DRAWBAR_LEVELS.iter().sum::<f32>();
+        Some(Box::new(OrganVoice { phases, levels, normalization }))
+    }
+}
+
+impl Generator for OrganVoice {
+    fn next_sample(&mut self) -> f32 {
+        let mut value = 0.0;
+        for (phase, level) in self.phases.iter_mut().zip(self.levels.iter()) {
+            value += (2.0 * PI * phase.advance()).sin() * level;
+        }
+        value * self.normalization
+    }
+}
+
+// String ensemble: three detuned saws through a lowpass that opens with
+// velocity, with vibrato fading in after the attack
+#[derive(Debug)]
+pub struct Strings;
+
+const STRINGS_DETUNE_CENTS: f32 = 7.0;
+const VIBRATO_RATE: f32 = 5.5;
+const VIBRATO_DEPTH: f32 = 0.004;
+const VIBRATO_DELAY_SECONDS: f32 = 0.4;
+
+struct StringsVoice {
+    saws: [Phase; 3],
+    base_increments: [f32; 3],
+    vibrato: Phase,
+    vibrato_amount: f32,
+    vibrato_step: f32,
+    filter_state: f32,
+    filter_coefficient: f32,
+}
+
+impl Instrument for Strings {
+    fn name(&self) -> &str {
+        "strings"
+    }
+
+    fn default_envelope(&self) -> EnvelopeConfig {
+        envelope(0.3, 0.2, 0.9, 0.6)
+    }
+
+    fn start_note(&self, note: &NoteParams) -> Option<Box<dyn Generator>> {
+        let sample_rate = note.sample_rate as f32;
+        let detune = 2.0_f32.powf(STRINGS_DETUNE_CENTS / 1200.0);
+        let frequencies = [note.frequency / detune, note.frequency, note.frequency * detune];
+        let saws = frequencies.map(|f| Phase::new(f, note.sample_rate));
+        let cutoff = (note.frequency * (2.0 + 6.0 * note.strength)).min(sample_rate * 0.45);
+
+        Some(Box::new(StringsVoice {
+            saws,
+            base_increments: saws.map(|p| p.increment),
+            vibrato: Phase::new(VIBRATO_RATE, note.sample_rate),
+            vibrato_amount: 0.0,
+            vibrato_step: 1.0 / (VIBRATO_DELAY_SECONDS * sample_rate),
+            filter_state: 0.0,
+            filter_coefficient: 1.0 - (-2.0 * PI * cutoff / sample_rate).exp(),
+        }))
+    }
+}
+
+impl Generator for StringsVoice {
+    fn next_sample(&mut self) -> f32 {
+        self.vibrato_amount = (self.vibrato_amount + self.vibrato_step).min(1.0);
+        let vibrato = 1.0 + (2.0 * PI * self.vibrato.advance()).sin() * VIBRATO_DEPTH * self.vibrato_amount;
+
+        let mut value = 0.0;
+        for (saw_phase, base) in self.saws.iter_mut().zip(self.base_increments.iter()) {
+            saw_phase.increment = base * vibrato;
+            value += saw(saw_phase);
+        }
+
+        self.filter_state += self.filter_coefficient * (value / 3.0 - self.filter_state);
+        self.filter_state
+    }
+}
+
+// Band-limited square lead in the style of old sound chips
+#[derive(Debug)]
+pub struct ChiptuneLead;
+
+struct ChiptuneVoice {
+    phase: Phase,
+    duty: f32,
+}
+
+impl Instrument for ChiptuneLead {
+    fn name(&self) -> &str {
+        "chiptune"
+    }
+
+    fn default_envelope(&self) -> EnvelopeConfig {
+        envelope(0.001, 0.1, 0.8, 0.05)
+    }
+
+    fn default_retrigger(&self) -> RetriggerMode {
+        RetriggerMode::Crossfade
+    }
+
+    fn start_note(&self, note: &NoteParams) -> Option<Box<dyn Generator>> {
+        Some(Box::new(ChiptuneVoice {
+            phase: Phase::new(note.frequency, note.sample_rate),
+            // Harder notes get the thinner, brighter 25% pulse
+            duty: if note.strength > 0.8 { 0.25 } else { 0.5 },
+        }))
+    }
+}
+
+impl Generator for ChiptuneVoice {
+    fn next_sample(&mut self) -> f32 {
+        square(&mut self.phase, self.duty) * 0.5
+    }
+}
+
+// Karplus-Strong plucked string: a burst of noise circulating through a
+// damped delay line one period long
+#[derive(Debug)]
+pub struct PluckedString;
+
+struct PluckedVoice {
+    line: Vec<f32>,
+    position: usize,
+    // Share of the older sample in the loop filter, tuning the fractional
+    // part of the period
+    weight: f32,
+    damping: f32,
+}
+
+impl Instrument for PluckedString {
+    fn name(&self) -> &str {
+        "guitar"
+    }
+
+    fn default_envelope(&self) -> EnvelopeConfig {
+        // The string decays by itself; the envelope only damps it on release
+        envelope(0.001, 8.0, 0.0, 0.15)
+    }
+
+    fn start_note(&self, note: &NoteParams) -> Option<Box<dyn Generator>> {
+        let period = (note.sample_rate as f32 / note.frequency).max(2.0);
+        let length = period.floor() as usize + 1;
+
+        // Softer plucks start from smoother noise, which sounds duller
+        let smoothing = 0.8 * (1.0 - note.strength);
+        let mut seed = 0x9e37_79b9_u32 ^ (note.midi_note as u32).wrapping_mul(0x85eb_ca6b);
+        let mut previous = 0.0;
+        let line = (0..length)
+            .map(|_| {
+                seed ^= seed << 13;
+                seed ^= seed >> 17;
+                seed ^= seed << 5;
+                let noise = seed as f32 / u32::MAX as f32 * 2.0 - 1.0;
+                previous = previous * smoothing + noise * (1.0 - smoothing);
+                previous
+            })
+            .collect();
+
+        Some(Box::new(PluckedVoice {
+            line,
+            position: 0,
+            weight: period.fract(),
+            // Higher strings lose their energy faster
+            damping: 0.996 - 0.004 * (note.frequency / 2000.0).min(1.0),
+        }))
+    }
+}
+
+impl Generator for PluckedVoice {
+    fn next_sample(&mut self) -> f32 {
+        let oldest = self.line[self.position];
+        let next = self.line[(self.position + 1) % self.line.len()];
+        let value = self.damping * (self.weight * oldest + (1.0 - self.weight) * next);
+        self.line[self.position] = value;
+        self.position = (self.position + 1) % self.line.len();
+        value
+    }
+}
+
+// Saw plus a sine an octave down, through a lowpass that closes after the
+// attack for a plucked synth-bass tone
+#[derive(Debug)]
+pub struct Bass;
+
+struct BassVoice {
+    saw: Phase,
+    sub: Phase,
+    cutoff: f32,
+    cutoff_floor: f32,
+    cutoff_decay: f32,
+    filter_state: f32,
+    sample_rate: f32,
+}
+
+impl Instrument for Bass {
+    fn name(&self) -> &str {
+        "bass"
+    }
+
+    fn default_envelope(&self) -> EnvelopeConfig {
+        envelope(0.003, 1.2, 0.4, 0.08)
+    }
+
+    fn start_note(&self, note: &NoteParams) -> Option<Box<dyn Generator>> {
+        let sample_rate = note.sample_rate as f32;
+        Some(Box::new(BassVoice {
+            saw: Phase::new(note.frequency, note.sample_rate),
+            sub: Phase::new(note.frequency / 2.0, note.sample_rate),
+            cutoff: (note.frequency * (4.0 + 12.0 * note.strength)).min(sample_rate * 0.45),
+            cutoff_floor: note.frequency * 2.0,
+            cutoff_decay: (-1.0 / (0.15 * sample_rate)).exp(),
+            filter_state: 0.0,
+            sample_rate,
+        }))
+    }
+}
+
+impl Generator for BassVoice {
+    fn next_sample(&mut self) -> f32 {
+        let raw = 0.6 * saw(&mut self.saw) + 0.4 * (2.0 * PI * self.sub.advance()).sin();
+        let coefficient = 1.0 - (-2.0 * PI * self.cutoff / self.sample_rate).exp();
+        self.filter_state += coefficient * (raw - self.filter_state);
+        self.cutoff = self.cutoff_floor + (self.cutoff - self.cutoff_floor) * self.cutoff_decay;
+        self.filter_state
+    }
+}
+
+// Brass section: a saw whose lowpass opens over the attack, further for
+// harder notes, as a player blowing into the horn would
+#[derive(Debug)]
+pub struct Brass;
+
+const BRASS_SWELL_SECONDS: f32 = 0.08;
+
+struct BrassVoice {
+    saw: Phase,
+    cutoff: f32,
+    cutoff_target: f32,
+    cutoff_step: f32,
+    filter_state: f32,
+    sample_rate: f32,
+}
+
+impl Instrument for Brass {
+    fn name(&self) -> &str {
+        "brass"
+    }
+
+    fn default_envelope(&self) -> EnvelopeConfig {
+        envelope(0.04, 0.2, 0.8, 0.15)
+    }
+
+    fn start_note(&self, note: &NoteParams) -> Option<Box<dyn Generator>> {
+        let sample_rate = note.sample_rate as f32;
+        let cutoff = note.frequency;
+        let cutoff_target = (note.frequency * (3.0 + 7.0 * note.strength)).min(sample_rate * 0.45);
+        Some(Box::new(BrassVoice {
+            saw: Phase::new(note.frequency, note.sample_rate),
+            cutoff,
+            cutoff_target,
+            cutoff_step: (cutoff_target - cutoff) / (BRASS_SWELL_SECONDS * sample_rate),
+            filter_state: 0.0,
+            sample_rate,
+        }))
+    }
+}
+
+impl Generator for BrassVoice {
+    fn next_sample(&mut self) -> f32 {
+        self.cutoff = (self.cutoff + self.cutoff_step).min(self.cutoff_target);
+        let coefficient = 1.0 - (-2.0 * PI * self.cutoff / self.sample_rate).exp();
+        self.filter_state += coefficient * (saw(&mut self.saw) - self.filter_state);
+        self.filter_state
+    }
+}
+
+// The built-in instrument closest to a General MIDI program, chosen by the
+// program's family of eight
+pub fn general_midi_instrument(program: u8) -> Arc<dyn Instrument> {
+    match program & 0x7f {
+        0..=3 => Arc::new(AcousticPiano),
+        // Electric pianos, chromatic percussion and percussive instruments
+        4..=15 | 112..=119 => Arc::new(ElectricPiano),
+        16..=23 => Arc::new(DrawbarOrgan),
+        // Guitars and plucked ethnic instruments
+        24..=31 | 104..=111 => Arc::new(PluckedString),
+        32..=39 => Arc::new(Bass),
+        // Strings, ensembles, synth pads and effects
+        40..=55 | 88..=103 => Arc::new(Strings),
+        // Brass and reeds
+        56..=71 => Arc::new(Brass),
+        80..=87 => Arc::new(ChiptuneLead),
+        // Pipes and sound effects
+        _ => Arc::new(Sine),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn render(instrument: &dyn Instrument, midi_note: u8, strength: f32, samples: usize) -> Vec<f32> {
+        let note = NoteParams {
+            midi_note,
+            velocity: (strength * 127.0) as u8,
+            strength,
+            frequency: crate::tuning::standard_frequency(midi_note),
+            sample_rate: 44100,
+        };
+        let mut generator = instrument.start_note(&note).unwrap();
+        (0..samples).map(|_| generator.next_sample()).collect()
+    }
+
+    #[test]
+    fn test_builtin_instruments_stay_in_range() {
+        for instrument in builtin_instruments() {
+            for midi_note in [21, 60, 108] {
+                let buffer = render(instrument.as_ref(), midi_note, 1.0, 4410);
+                let peak = buffer.iter().fold(0.0_f32, |acc, s| acc.max(s.abs()));
+                assert!(peak > 0.01, "{} is silent at note {}", instrument.name(), midi_note);
+                assert!(peak <= 1.0, "{} clips at note {}", instrument.name(), midi_note);
+            }
+        }
+    }
+
+    #[test]
+    fn test_builtin_lookup() {
+        assert_eq!(builtin_instrument("organ").unwrap().name(), "organ");
+        assert!(builtin_instrument("kazoo").is_none());
+
+        let mut names: Vec<_> = builtin_instruments().iter().map(|i| i.name().to_string()).collect();
+        names.sort();
+        names.dedup();
+        assert_eq!(names.len(), builtin_instruments().len());
+    }
+
+    #[test]
+    fn test_general_midi_families() {
+        assert_eq!(general_midi_instrument(0).name(), "piano");
+        assert_eq!(general_midi_instrument(25).name(), "guitar");
+        assert_eq!(general_midi_instrument(33).name(), "bass");
+        assert_eq!(general_midi_instrument(48).name(), "strings");
+        assert_eq!(general_midi_instrument(61).name(), "brass");
+        assert_eq!(general_midi_instrument(73).name(), "sine");
+    }
+
+    #[test]
+    fn test_plucked_string_is_in_tune() {
+        // The waveform repeats every period: A3 at 220 Hz is 200.45 samples
+        let buffer = render(&PluckedString, 57, 1.0, 44100);
+        let window = &buffer[4410..8820];
+        let correlation = |lag: usize| -> f32 {
+            window.iter().zip(&buffer[4410 + lag..]).map(|(a, b)| a * b).sum()
+        };
+        let period = (100..400).max_by(|&a, &b| correlation(a).total_cmp(&correlation(b))).unwrap();
+        assert!(period.abs_diff(200) <= 1, "period of {} samples", period);
+    }
+
+    #[test]
+    fn test_piano_partials_decay() {
+        let buffer = render(&AcousticPiano, 60, 1.0, 44100 * 2);
+        let peak = |range: &[f32]| range.iter().fold(0.0_f32, |acc, s| acc.max(s.abs()));
+        assert!(peak(&buffer[..4410]) > peak(&buffer[44100..48510]) * 1.5);
+    }
+}
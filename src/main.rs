@@ -4,12 +4,22 @@ use std::path::PathBuf;
 
 mod app;
 mod audio;
+mod audio_effects;
 mod config;
+mod drums;
 mod file_dialog;
+mod instruments;
+mod metronome;
 mod midi;
+mod mixer;
 mod piano;
+mod render;
+mod soundfont;
+mod synth;
+mod tuning;
 mod ui;
 mod effects;
+mod export;
 
 use app::App;
 
@@ -26,6 +36,10 @@ struct Cli {
     /// Enable debug mode
     #[arg(short, long)]
     debug: bool,
+    
+    /// Run without sound, e.g. over SSH or in a container
+    #[arg(long)]
+    no_audio: bool,
 }
 
 #[derive(Subcommand)]
@@ -41,38 +55,72 @@ enum Commands {
         #[arg(short, long)]
         show: bool,
     },
+    /// Render a MIDI file, a saved recording or a folder of MIDI files to WAV
+    Render {
+        /// MIDI file, recording (.json) or directory of MIDI files
+        input: PathBuf,
+        /// Output WAV file (or directory when rendering a directory)
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+    },
+    /// Convert a saved recording (.json) to a Standard MIDI File
+    Convert {
+        /// Recording to convert
+        input: PathBuf,
+        /// Output MIDI file (defaults to the recording's name with .mid)
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+        /// SMF type: 0 (single track) or 1 (tempo track plus notes)
+        #[arg(long)]
+        format: Option<u8>,
+        /// Tempo in quarter notes per minute
+        #[arg(long)]
+        tempo: Option<f32>,
+        /// Ticks per quarter note
+        #[arg(long)]
+        ppq: Option<u16>,
+    },
 }
 
 #[tokio::main]
 async fn main() -> Result<()> {
     let cli = Cli::parse();
     
-    // Test audio initialization early
-    println!("Initializing audio system...");
-    
-    let mut app = match App::new(cli.debug).await {
-        Ok(app) => {
-            println!("Audio system initialized successfully!");
-            app
+    // Rendering runs without a sound device, so it is handled before any
+    // audio output is opened
+    if let Some(Commands::Render { input, output }) = &cli.command {
+        let config = config::Config::load()?;
+        for path in render::render_path(input, output.as_deref(), &config.audio)? {
+            println!("Rendered {}", path.display());
         }
-        Err(e) => {
-            eprintln!("Failed to initialize audio: {}", e);
-            eprintln!("The app will still work but without sound.");
-            eprintln!("This might be due to audio device permissions or configuration.");
-            return Err(e);
-        }
-    };
+        return Ok(());
+    }
+    if let Some(Commands::Convert { input, output, format, tempo, ppq }) = &cli.command {
+        let mut export = config::Config::load()?.export;
+        export.format = format.unwrap_or(export.format);
+        export.tempo = tempo.unwrap_or(export.tempo);
+        export.ppq = ppq.unwrap_or(export.ppq);
+        let path = export::convert_recording(input, output.as_deref(), &export)?;
+        println!("Wrote {}", path.display());
+        return Ok(());
+    }
+    
+    if !cli.no_audio {
+        println!("Initializing audio system...");
+    }
+    
+    // Falls back to a silent engine when no sound device is available
+    let mut app = App::new(cli.debug, cli.no_audio).await?;
     
     match cli.command {
         Some(Commands::Play { file }) => {
             app.load_midi_file(file).await?;
         }
-        Some(Commands::Config { show }) => {
-            if show {
-                app.show_config()?;
-                return Ok(());
-            }
+        Some(Commands::Config { show: true }) => {
+            app.show_config()?;
+            return Ok(());
         }
+        Some(Commands::Config { show: false }) | Some(Commands::Render { .. }) | Some(Commands::Convert { .. }) => {}
         None => {}
     }
     
@@ -0,0 +1,304 @@
+use std::f32::consts::PI;
+use std::time::{Duration, Instant};
+
+use crate::config::MetronomeConfig;
+
+const ACCENT_FREQUENCY: f32 = 1760.0;
+const BEAT_FREQUENCY: f32 = 1320.0;
+const SUBDIVISION_FREQUENCY: f32 = 880.0;
+const CLICK_SECONDS: f32 = 0.03;
+// How far the click may drift from a sync position before it is snapped back
+const SYNC_TOLERANCE_SECONDS: f64 = 0.01;
+// Taps further apart than this start a new tempo measurement
+const TAP_TIMEOUT: Duration = Duration::from_secs(2);
+const MAX_TAPS: usize = 8;
+
+// Click track driven by the synth's sample clock, so it never drifts from
+// the notes it plays alongside
+pub struct Metronome {
+    config: MetronomeConfig,
+    enabled: bool,
+    sample_rate: f32,
+    // Tempo and time signature taken from a playing MIDI file
+    locked: Option<(f32, u8, u8)>,
+    // Clicks (subdivisions) since the start of the first bar
+    position: f64,
+    click: Option<Click>,
+}
+
+struct Click {
+    frequency: f32,
+    amplitude: f32,
+    age: u32,
+}
+
+impl Metronome {
+    pub fn new(sample_rate: u32) -> Self {
+        Self {
+            config: MetronomeConfig::default(),
+            enabled: false,
+            sample_rate: sample_rate as f32,
+            locked: None,
+            position: 0.0,
+            click: None,
+        }
+    }
+
+    pub fn configure(&mut self, config: MetronomeConfig) {
+        self.config = config;
+    }
+
+    pub fn set_enabled(&mut self, enabled: bool) {
+        if enabled && !self.enabled {
+            // Start on a downbeat; the position sits just before it
+            self.position = -f64::EPSILON;
+        }
+        self.enabled = enabled;
+    }
+
+    // Follows a MIDI file's tempo (in quarter notes per minute) and time
+    // signature, or goes back to the configured ones with `None`
+    pub fn lock(&mut self, locked: Option<(f32, u8, u8)>) {
+        self.locked = locked;
+    }
+
+    // Lines the clicks up with `beat`, counted in beats from the start of the
+    // first bar, unless they are already within tolerance
+    pub fn sync(&mut self, beat: f64) {
+        let target = beat * self.subdivision() as f64;
+        let tolerance = SYNC_TOLERANCE_SECONDS * self.clicks_per_sample() * self.sample_rate as f64;
+        if (self.position - target).abs() > tolerance {
+            self.position = target;
+        }
+    }
+
+    // Beats per minute in the time signature's beat unit
+    pub fn bpm(&self) -> f32 {
+        match self.locked {
+            Some((quarter_bpm, _, beat_unit)) => quarter_bpm * beat_unit as f32 / 4.0,
+            None => self.config.bpm,
+        }
+    }
+
+    pub fn quarter_bpm(&self) -> f32 {
+        self.bpm() * 4.0 / self.time_signature().1.max(1) as f32
+    }
+
+    pub fn time_signature(&self) -> (u8, u8) {
+        match self.locked {
+            Some((_, beats_per_bar, beat_unit)) => (beats_per_bar, beat_unit),
+            None => (self.config.beats_per_bar, self.config.beat_unit),
+        }
+    }
+
+    fn subdivision(&self) -> u32 {
+        self.config.subdivision.max(1) as u32
+    }
+
+    fn clicks_per_sample(&self) -> f64 {
+        self.bpm().max(1.0) as f64 / 60.0 * self.subdivision() as f64 / self.sample_rate as f64
+    }
+
+    pub fn next_sample(&mut self) -> f32 {
+        if !self.enabled {
+            return 0.0;
+        }
+
+        let previous = self.position;
+        self.position += self.clicks_per_sample();
+        if self.position.floor() > previous.floor() {
+            self.start_click(self.position.floor() as i64);
+        }
+
+        let Some(click) = &mut self.click else {
+            return 0.0;
+        };
+        let t = click.age as f32 / self.sample_rate;
+        click.age += 1;
+        if t >= CLICK_SECONDS {
+            self.click = None;
+            return 0.0;
+        }
+        let decay = (-t / CLICK_SECONDS * 5.0).exp();
+        (2.0 * PI * click.frequency * t).sin() * decay * click.amplitude * self.config.volume
+    }
+
+    fn start_click(&mut self, index: i64) {
+        let subdivision = self.subdivision() as i64;
+        let (beats_per_bar, _) = self.time_signature();
+        let (frequency, amplitude) = if index.rem_euclid(subdivision) != 0 {
+            (SUBDIVISION_FREQUENCY, 0.4)
+        } else if self.config.accent && index.div_euclid(subdivision).rem_euclid(beats_per_bar.max(1) as i64) == 0 {
+            (ACCENT_FREQUENCY, 1.0)
+        } else {
+            (BEAT_FREQUENCY, 0.7)
+        };
+        self.click = Some(Click { frequency, amplitude, age: 0 });
+    }
+}
+
+// Averages the gaps between recent taps into a tempo
+#[derive(Debug, Default)]
+pub struct TapTempo {
+    taps: Vec<Instant>,
+}
+
+impl TapTempo {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Records a tap and returns the tempo once there are at least two
+    pub fn tap(&mut self, now: Instant) -> Option<f32> {
+        if self.taps.last().is_some_and(|&last| now.duration_since(last) > TAP_TIMEOUT) {
+            self.taps.clear();
+        }
+        self.taps.push(now);
+        if self.taps.len() > MAX_TAPS {
+            self.taps.remove(0);
+        }
+
+        let (first, last) = (self.taps.first()?, self.taps.last()?);
+        let gaps = self.taps.len() as f32 - 1.0;
+        if gaps < 1.0 {
+            return None;
+        }
+        let seconds = last.duration_since(*first).as_secs_f32() / gaps;
+        (seconds > 0.0).then(|| (60.0 / seconds).clamp(20.0, 300.0))
+    }
+}
+
+// The settings the metronome panel can step through and adjust
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MetronomeParam {
+    Bpm,
+    BeatsPerBar,
+    BeatUnit,
+    Subdivision,
+    Accent,
+    Volume,
+}
+
+impl MetronomeParam {
+    pub const ALL: [MetronomeParam; 6] = [
+        MetronomeParam::Bpm,
+        MetronomeParam::BeatsPerBar,
+        MetronomeParam::BeatUnit,
+        MetronomeParam::Subdivision,
+        MetronomeParam::Accent,
+        MetronomeParam::Volume,
+    ];
+
+    pub fn label(self) -> &'static str {
+        match self {
+            MetronomeParam::Bpm => "Tempo",
+            MetronomeParam::BeatsPerBar => "Beats per Bar",
+            MetronomeParam::BeatUnit => "Beat Unit",
+            MetronomeParam::Subdivision => "Subdivision",
+            MetronomeParam::Accent => "Accent",
+            MetronomeParam::Volume => "Volume",
+        }
+    }
+
+    pub fn value(self, config: &MetronomeConfig) -> String {
+        match self {
+            MetronomeParam::Bpm => format!("{:.0} BPM", config.bpm),
+            MetronomeParam::BeatsPerBar => config.beats_per_bar.to_string(),
+            MetronomeParam::BeatUnit => format!("1/{}", config.beat_unit),
+            MetronomeParam::Subdivision => match config.subdivision {
+                1 => "off".to_string(),
+                n => format!("{} per beat", n),
+            },
+            MetronomeParam::Accent => if config.accent { "on" } else { "off" }.to_string(),
+            MetronomeParam::Volume => format!("{:.0}%", config.volume * 100.0),
+        }
+    }
+
+    pub fn adjust(self, config: &mut MetronomeConfig, steps: i32) {
+        match self {
+            MetronomeParam::Bpm => config.bpm = (config.bpm.round() + steps as f32).clamp(20.0, 300.0),
+            MetronomeParam::BeatsPerBar => config.beats_per_bar = (config.beats_per_bar as i32 + steps).clamp(1, 16) as u8,
+            MetronomeParam::BeatUnit => {
+                let units = [2, 4, 8, 16];
+                let current = units.iter().position(|&u| u == config.beat_unit).unwrap_or(1) as i32;
+                config.beat_unit = units[(current + steps).clamp(0, units.len() as i32 - 1) as usize];
+            }
+            MetronomeParam::Subdivision => config.subdivision = (config.subdivision as i32 + steps).clamp(1, 4) as u8,
+            MetronomeParam::Accent => config.accent = !config.accent,
+            MetronomeParam::Volume => config.volume = ((config.volume * 20.0).round() + steps as f32).clamp(0.0, 20.0) / 20.0,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SAMPLE_RATE: u32 = 1000;
+
+    // Sample indices where a click starts, with its frequency
+    fn clicks(metronome: &mut Metronome, samples: usize) -> Vec<(usize, f32)> {
+        let mut starts = Vec::new();
+        for i in 0..samples {
+            metronome.next_sample();
+            if let Some(click) = &metronome.click {
+                if click.age == 1 {
+                    starts.push((i, click.frequency));
+                }
+            }
+        }
+        starts
+    }
+
+    // Within a sample, allowing for rounding in the accumulated position
+    fn near(a: usize, b: usize) -> bool {
+        a.abs_diff(b) <= 1
+    }
+
+    #[test]
+    fn test_clicks_on_the_beat_with_accented_downbeat() {
+        let mut metronome = Metronome::new(SAMPLE_RATE);
+        metronome.configure(MetronomeConfig { bpm: 120.0, beats_per_bar: 3, ..MetronomeConfig::default() });
+        metronome.set_enabled(true);
+
+        let starts = clicks(&mut metronome, 2000);
+        let frequencies: Vec<f32> = starts.iter().map(|&(_, f)| f).collect();
+        assert_eq!(frequencies, vec![ACCENT_FREQUENCY, BEAT_FREQUENCY, BEAT_FREQUENCY, ACCENT_FREQUENCY]);
+        // Half a second apart at 120 BPM
+        assert!(near(starts[1].0 - starts[0].0, 500));
+        assert!(near(starts[3].0 - starts[0].0, 1500));
+    }
+
+    #[test]
+    fn test_subdivisions_and_lock() {
+        let mut metronome = Metronome::new(SAMPLE_RATE);
+        metronome.configure(MetronomeConfig { subdivision: 2, ..MetronomeConfig::default() });
+        metronome.lock(Some((60.0, 4, 4)));
+        metronome.set_enabled(true);
+
+        let starts = clicks(&mut metronome, 1100);
+        // A MIDI tempo of 60 wins over the configured 120
+        assert_eq!(starts.len(), 3);
+        assert!(near(starts[1].0, 500));
+        assert_eq!(starts[1].1, SUBDIVISION_FREQUENCY);
+
+        // Syncing to the middle of beat 2 puts the next click on beat 3
+        metronome.sync(1.5);
+        let starts = clicks(&mut metronome, 1000);
+        assert!(near(starts[0].0, 500));
+        assert_eq!(starts[0].1, BEAT_FREQUENCY);
+    }
+
+    #[test]
+    fn test_tap_tempo() {
+        let mut tap = TapTempo::new();
+        let start = Instant::now();
+        assert_eq!(tap.tap(start), None);
+        assert_eq!(tap.tap(start + Duration::from_millis(500)), Some(120.0));
+        let bpm = tap.tap(start + Duration::from_millis(1100)).unwrap();
+        assert!((bpm - 109.09).abs() < 0.1);
+
+        // A long pause starts over
+        assert_eq!(tap.tap(start + Duration::from_secs(10)), None);
+    }
+}
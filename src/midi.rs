@@ -1,93 +1,509 @@
 use anyhow::{anyhow, Result};
 use midly::{Smf, MidiMessage, MetaMessage, Timing};
-use std::collections::VecDeque;
+use std::collections::HashMap;
 use std::path::{Path, PathBuf};
 use std::time::{Duration, Instant};
 
 use crate::audio::{Recording, RecordingEventType};
+use crate::drums::DRUM_CHANNEL;
+use crate::mixer::MixerStrip;
+use crate::tuning::Tuning;
 
 #[derive(Debug, Clone)]
 pub struct MidiEvent {
     pub delta_time: u32,
     pub absolute_time: u64,
+    // From the start of the song, through the file's timing
+    pub time: Duration,
+    // Index of the track the event came from
+    pub track: usize,
+    pub channel: u8,
     pub event: MidiMessage,
 }
 
-#[derive(Debug)]
-pub struct MidiPlayer {
-    pub current_file: Option<PathBuf>,
-    pub events: VecDeque<MidiEvent>,
-    pub is_playing: bool,
-    pub start_time: Option<Instant>,
-    pub current_position: u64,
-    pub tempo: u32,
-    pub ticks_per_quarter: u16,
-    pub total_ticks: u64,
-    pub loop_enabled: bool,
+// Microseconds per quarter note until a file says otherwise (120 BPM)
+const DEFAULT_TEMPO: u32 = 500_000;
+// Resolution recordings are placed at: 1920 ticks a second at 120 BPM
+const RECORDING_TICKS_PER_QUARTER: u16 = 960;
+// Registered parameter select (CC101/CC100) and the data entry controllers
+// (CC6/CC38, increment and decrement) whose meaning depends on it
+const PARAMETER_MSB: u8 = 101;
+const PARAMETER_LSB: u8 = 100;
+const DATA_ENTRY: [u8; 4] = [6, 38, 96, 97];
+
+// The messages whose latest value makes up a channel's state at a point in
+// the song, replayed after a seek
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum ChaseKey {
+    Note(u8, u8),
+    Controller(u8, u8),
+    // Data entry for the parameter that was selected when it was sent
+    DataEntry(u8, (u8, u8), u8),
+    Program(u8),
+    PitchBend(u8),
 }
 
-impl MidiPlayer {
-    pub fn new() -> Self {
-        Self {
-            current_file: None,
-            events: VecDeque::new(),
-            is_playing: false,
-            start_time: None,
-            current_position: 0,
-            tempo: 500000, // Default tempo (120 BPM)
-            ticks_per_quarter: 480,
-            total_ticks: 0,
-            loop_enabled: false,
+// A stretch of the song at one tempo, starting at `tick`
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct TempoSegment {
+    tick: u64,
+    micros_per_quarter: u32,
+    // Time from the start of the song to `tick`
+    start_micros: f64,
+}
+
+// Converts between ticks and time across every tempo change in a file
+#[derive(Debug, Clone)]
+pub struct TempoMap {
+    ticks_per_quarter: u16,
+    segments: Vec<TempoSegment>,
+}
+
+impl TempoMap {
+    // `changes` are (tick, microseconds per quarter) pairs in any order; of
+    // several on the same tick the last one wins
+    pub fn new(ticks_per_quarter: u16, changes: &[(u64, u32)]) -> Self {
+        let mut changes = changes.to_vec();
+        changes.sort_by_key(|&(tick, _)| tick);
+
+        let mut segments = vec![TempoSegment { tick: 0, micros_per_quarter: DEFAULT_TEMPO, start_micros: 0.0 }];
+        for (tick, micros_per_quarter) in changes {
+            let last = *segments.last().unwrap();
+            if tick == last.tick {
+                segments.last_mut().unwrap().micros_per_quarter = micros_per_quarter;
+            } else {
+                let start_micros = last.start_micros + Self::micros_for(last.micros_per_quarter, tick - last.tick, ticks_per_quarter);
+                segments.push(TempoSegment { tick, micros_per_quarter, start_micros });
+            }
         }
+        Self { ticks_per_quarter, segments }
     }
-    
-    pub fn load_file<P: AsRef<Path>>(&mut self, path: P) -> Result<()> {
-        let path = path.as_ref();
-        let data = std::fs::read(path)?;
-        let smf = Smf::parse(&data)?;
-        
-        self.current_file = Some(path.to_path_buf());
-        self.events.clear();
-        self.current_position = 0;
-        self.is_playing = false;
-        self.start_time = None;
-        
-        match smf.header.timing {
-            Timing::Metrical(tpq) => {
-                self.ticks_per_quarter = tpq.as_int();
+
+    fn micros_for(micros_per_quarter: u32, ticks: u64, ticks_per_quarter: u16) -> f64 {
+        ticks as f64 * micros_per_quarter as f64 / ticks_per_quarter.max(1) as f64
+    }
+
+    fn segment_at_tick(&self, tick: u64) -> &TempoSegment {
+        let index = self.segments.partition_point(|s| s.tick <= tick);
+        &self.segments[index.saturating_sub(1)]
+    }
+
+    pub fn ticks_to_time(&self, tick: u64) -> Duration {
+        let segment = self.segment_at_tick(tick);
+        let micros = segment.start_micros + Self::micros_for(segment.micros_per_quarter, tick - segment.tick, self.ticks_per_quarter);
+        Duration::from_secs_f64(micros / 1_000_000.0)
+    }
+
+    pub fn time_to_ticks(&self, time: Duration) -> u64 {
+        let micros = time.as_secs_f64() * 1_000_000.0;
+        let index = self.segments.partition_point(|s| s.start_micros <= micros);
+        let segment = &self.segments[index.saturating_sub(1)];
+        let quarters = (micros - segment.start_micros) / segment.micros_per_quarter as f64;
+        segment.tick + (quarters * self.ticks_per_quarter as f64) as u64
+    }
+
+    // Microseconds per quarter note in effect at `tick`
+    pub fn tempo_at(&self, tick: u64) -> u32 {
+        self.segment_at_tick(tick).micros_per_quarter
+    }
+
+    pub fn tempo_changes(&self) -> usize {
+        self.segments.len() - 1
+    }
+}
+
+impl Default for TempoMap {
+    fn default() -> Self {
+        Self::new(480, &[])
+    }
+}
+
+// How a file's ticks relate to time, from its header
+#[derive(Debug, Clone)]
+pub enum TimingModel {
+    // Ticks are fractions of a quarter note, so their length follows the tempo
+    Metrical(TempoMap),
+    // Ticks are fractions of a SMPTE frame: a fixed length of real time.
+    // Tempo events do not affect these files.
+    Timecode { ticks_per_second: f64 },
+}
+
+impl TimingModel {
+    pub fn from_header(timing: Timing, tempo_changes: &[(u64, u32)]) -> Self {
+        match timing {
+            Timing::Metrical(tpq) => TimingModel::Metrical(TempoMap::new(tpq.as_int(), tempo_changes)),
+            Timing::Timecode(fps, ticks_per_frame) => TimingModel::Timecode {
+                ticks_per_second: fps.as_f32() as f64 * ticks_per_frame.max(1) as f64,
+            },
+        }
+    }
+
+    pub fn ticks_to_time(&self, tick: u64) -> Duration {
+        match self {
+            TimingModel::Metrical(map) => map.ticks_to_time(tick),
+            TimingModel::Timecode { ticks_per_second } => Duration::from_secs_f64(tick as f64 / ticks_per_second),
+        }
+    }
+
+    pub fn time_to_ticks(&self, time: Duration) -> u64 {
+        match self {
+            TimingModel::Metrical(map) => map.time_to_ticks(time),
+            TimingModel::Timecode { ticks_per_second } => (time.as_secs_f64() * ticks_per_second) as u64,
+        }
+    }
+
+    // Microseconds per quarter note at `tick`. Timecode files have no beat
+    // grid, so they count at the default tempo.
+    pub fn tempo_at(&self, tick: u64) -> u32 {
+        match self {
+            TimingModel::Metrical(map) => map.tempo_at(tick),
+            TimingModel::Timecode { .. } => DEFAULT_TEMPO,
+        }
+    }
+
+    pub fn quarters_to_ticks(&self, quarters: f64) -> u64 {
+        match self {
+            TimingModel::Metrical(map) => (quarters * map.ticks_per_quarter as f64) as u64,
+            TimingModel::Timecode { .. } => {
+                self.time_to_ticks(Duration::from_secs_f64(quarters.max(0.0) * DEFAULT_TEMPO as f64 / 1_000_000.0))
             }
-            Timing::Timecode(fps, tpf) => {
-                self.ticks_per_quarter = (fps.as_int() as u16) * (tpf as u16);
+        }
+    }
+    
+    // Quarter notes from the start of the song to `tick`
+    pub fn quarters_at(&self, tick: u64) -> f64 {
+        match self {
+            TimingModel::Metrical(map) => tick as f64 / map.ticks_per_quarter.max(1) as f64,
+            TimingModel::Timecode { .. } => {
+                self.ticks_to_time(tick).as_secs_f64() * 1_000_000.0 / DEFAULT_TEMPO as f64
             }
         }
+    }
+
+    pub fn tempo_changes(&self) -> usize {
+        match self {
+            TimingModel::Metrical(map) => map.tempo_changes(),
+            TimingModel::Timecode { .. } => 0,
+        }
+    }
+}
+
+impl Default for TimingModel {
+    fn default() -> Self {
+        TimingModel::Metrical(TempoMap::default())
+    }
+}
+
+// Slowest and fastest playback rates, for practice
+pub const MIN_PLAYBACK_RATE: f64 = 0.25;
+pub const MAX_PLAYBACK_RATE: f64 = 2.0;
+
+// From this instant on, playback advances at `rate` times real time
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct RateSegment {
+    start: Instant,
+    // Playhead time at `start`
+    playhead: Duration,
+    rate: f64,
+}
+
+// Maps wall-clock instants to the playhead: time into the song, counting on
+// through every loop. A rate change can be set to begin a little in the
+// future, once what the synth already has has played at the old rate.
+#[derive(Debug, Clone)]
+struct PlaybackClock {
+    segments: Vec<RateSegment>,
+}
+
+impl PlaybackClock {
+    fn new(start: Instant, playhead: Duration, rate: f64) -> Self {
+        Self { segments: vec![RateSegment { start, playhead, rate }] }
+    }
+
+    fn segment_at(&self, when: Instant) -> &RateSegment {
+        let index = self.segments.partition_point(|s| s.start <= when);
+        &self.segments[index.saturating_sub(1)]
+    }
+
+    fn playhead_at(&self, when: Instant) -> Duration {
+        let segment = self.segment_at(when);
+        if when >= segment.start {
+            segment.playhead + (when - segment.start).mul_f64(segment.rate)
+        } else {
+            segment.playhead.saturating_sub((segment.start - when).mul_f64(segment.rate))
+        }
+    }
+
+    fn instant_at(&self, playhead: Duration) -> Instant {
+        let index = self.segments.partition_point(|s| s.playhead <= playhead);
+        let segment = &self.segments[index.saturating_sub(1)];
+        if playhead >= segment.playhead {
+            segment.start + (playhead - segment.playhead).div_f64(segment.rate)
+        } else {
+            let before = (segment.playhead - playhead).div_f64(segment.rate);
+            segment.start.checked_sub(before).unwrap_or(segment.start)
+        }
+    }
+
+    fn rate_at(&self, when: Instant) -> f64 {
+        self.segment_at(when).rate
+    }
+
+    // Switches to `rate` at `from`, which is no earlier than any previous change
+    fn set_rate(&mut self, rate: f64, from: Instant) {
+        let playhead = self.playhead_at(from);
+        self.segments.retain(|s| s.start < from);
+        self.segments.push(RateSegment { start: from, playhead, rate });
+    }
+
+    // Forgets segments that ended before `now`
+    fn prune(&mut self, now: Instant) {
+        let index = self.segments.partition_point(|s| s.start <= now);
+        self.segments.drain(..index.saturating_sub(1));
+    }
+}
+
+// The stretch of the song playback goes round: between the A and B
+// markers, or the whole file
+#[derive(Debug, Clone)]
+struct LoopRegion {
+    start_index: usize,
+    // Events from here on are past the loop
+    end_index: usize,
+    start_time: Duration,
+    end_time: Duration,
+    // Played on the way round: note-offs for notes still down at the end,
+    // then the state the song is in at the start
+    wrap_events: Vec<MidiEvent>,
+}
+
+// A run of bars in one time signature, from where it takes effect
+#[derive(Debug, Clone, Copy)]
+struct MeterSegment {
+    start_quarters: f64,
+    // Bars before this one, counting a bar cut short by the change
+    first_bar: f64,
+    bar_quarters: f64,
+    signature: (u8, u8),
+}
+
+// The last segment `started` accepts, or the first if it accepts none
+fn meter_segment_at(segments: &[MeterSegment], started: impl Fn(&MeterSegment) -> bool) -> MeterSegment {
+    segments.iter().rev().find(|s| started(s)).copied().unwrap_or(segments[0])
+}
+
+impl LoopRegion {
+    fn length(&self) -> Duration {
+        self.end_time - self.start_time
+    }
+}
+
+// A song in memory, whatever it was loaded from: every event in time order
+// and the timing that places them
+#[derive(Debug, Clone, Default)]
+pub struct Song {
+    pub events: Vec<MidiEvent>,
+    pub timing: TimingModel,
+    // Beats per bar and beat unit of every time signature, by the tick
+    // it takes effect at
+    pub time_signatures: Vec<(u64, (u8, u8))>,
+    // From each track's name event, or its number
+    pub track_names: Vec<String>,
+}
+
+impl Song {
+    // Recordings are saved as JSON; anything else is read as a MIDI file
+    pub fn load(path: &Path) -> Result<Self> {
+        let is_recording = path.extension().is_some_and(|ext| ext.eq_ignore_ascii_case("json"));
+        if is_recording {
+            Ok(Self::from_recording(&Recording::load_from_file(path)?))
+        } else {
+            Self::from_smf(&std::fs::read(path)?)
+        }
+    }
+    
+    pub fn from_smf(data: &[u8]) -> Result<Self> {
+        let smf = Smf::parse(data)?;
+        let mut time_signatures = Vec::new();
+        let mut tempo_changes = Vec::new();
+        let mut events = Vec::new();
+        let mut track_names = Vec::new();
         
-        let mut absolute_time = 0u64;
-        let mut all_events = Vec::new();
-        
-        for track in smf.tracks {
-            absolute_time = 0;
+        for (index, track) in smf.tracks.into_iter().enumerate() {
+            track_names.push(format!("Track {}", index + 1));
+            let mut absolute_time = 0u64;
             for event in track {
                 absolute_time += event.delta.as_int() as u64;
                 
                 match event.kind {
                     midly::TrackEventKind::Midi { channel, message } => {
-                        all_events.push(MidiEvent {
+                        events.push(MidiEvent {
                             delta_time: event.delta.as_int(),
                             absolute_time,
+                            time: Duration::ZERO,
+                            track: index,
+                            channel: channel.as_int(),
                             event: message,
                         });
                     }
+                    midly::TrackEventKind::Meta(MetaMessage::TrackName(name)) if absolute_time == 0 => {
+                        let name = String::from_utf8_lossy(name).trim().to_string();
+                        if !name.is_empty() {
+                            track_names[index] = name;
+                        }
+                    }
                     midly::TrackEventKind::Meta(MetaMessage::Tempo(tempo)) => {
-                        self.tempo = tempo.as_int();
+                        tempo_changes.push((absolute_time, tempo.as_int()));
+                    }
+                    midly::TrackEventKind::Meta(MetaMessage::TimeSignature(numerator, denominator_power, _, _)) => {
+                        time_signatures.push((absolute_time, (numerator, 1u8 << denominator_power.min(7))));
                     }
                     _ => {}
                 }
             }
         }
         
-        all_events.sort_by_key(|e| e.absolute_time);
-        self.total_ticks = all_events.last().map(|e| e.absolute_time).unwrap_or(0);
-        self.events = all_events.into();
+        let timing = TimingModel::from_header(smf.header.timing, &tempo_changes);
+        events.sort_by_key(|e| e.absolute_time);
+        time_signatures.sort_by_key(|(tick, _)| *tick);
+        for event in &mut events {
+            event.time = timing.ticks_to_time(event.absolute_time);
+        }
+        Ok(Self {
+            events,
+            timing,
+            time_signatures,
+            track_names,
+        })
+    }
+    
+    // A recording as one track on the live channel, on a 120 BPM grid of
+    // fine enough ticks to keep its timing
+    pub fn from_recording(recording: &Recording) -> Self {
+        let timing = TimingModel::Metrical(TempoMap::new(RECORDING_TICKS_PER_QUARTER, &[]));
+        let mut last_tick = 0;
+        let events = recording
+            .timed_messages()
+            .into_iter()
+            .map(|(time, channel, event)| {
+                let absolute_time = timing.time_to_ticks(time).max(last_tick);
+                let delta_time = (absolute_time - last_tick) as u32;
+                last_tick = absolute_time;
+                MidiEvent { delta_time, absolute_time, time: timing.ticks_to_time(absolute_time), track: 0, channel, event }
+            })
+            .collect();
+        Self {
+            events,
+            timing,
+            time_signatures: Vec::new(),
+            track_names: vec!["Recording".to_string()],
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct MidiPlayer {
+    pub current_file: Option<PathBuf>,
+    // Every event in the file in time order. It never changes once loaded;
+    // playback just moves the cursors through it.
+    pub events: Vec<MidiEvent>,
+    // The next event to show
+    cursor: usize,
+    // The next event to hand to the synth, which runs a little ahead of `cursor`
+    schedule_cursor: usize,
+    // Playhead time where the pass each cursor is in started. They move on
+    // by the song's length each time playback loops round.
+    pass_start: Duration,
+    schedule_pass_start: Duration,
+    // Everything due before this has been handed to the synth
+    scheduled_until: Option<Instant>,
+    clock: Option<PlaybackClock>,
+    // Playback speed as a fraction of the file's tempo
+    rate: f64,
+    pub is_playing: bool,
+    pub current_position: u64,
+    pub timing: TimingModel,
+    pub total_ticks: u64,
+    pub loop_enabled: bool,
+    // A and B loop markers in ticks; with both set, looping stays between them
+    pub loop_markers: (Option<u64>, Option<u64>),
+    loop_region: Option<LoopRegion>,
+    // Give a bar of clicks before playback starts in a loop
+    pub count_in: bool,
+    // Added to the playback rate each time round a loop, up to full speed
+    pub loop_speed_up: f64,
+    // Beats per bar and beat unit of each time signature in the file, by tick
+    pub time_signatures: Vec<(u64, (u8, u8))>,
+    // Whether the file plays anything on the percussion channel
+    pub has_drums: bool,
+    // From each track's name event, or its number
+    pub track_names: Vec<String>,
+}
+
+impl MidiPlayer {
+    pub fn new() -> Self {
+        Self {
+            current_file: None,
+            events: Vec::new(),
+            cursor: 0,
+            schedule_cursor: 0,
+            pass_start: Duration::ZERO,
+            schedule_pass_start: Duration::ZERO,
+            scheduled_until: None,
+            clock: None,
+            rate: 1.0,
+            is_playing: false,
+            current_position: 0,
+            timing: TimingModel::default(),
+            total_ticks: 0,
+            loop_enabled: false,
+            loop_markers: (None, None),
+            loop_region: None,
+            count_in: false,
+            loop_speed_up: 0.0,
+            time_signatures: Vec::new(),
+            has_drums: false,
+            track_names: Vec::new(),
+        }
+    }
+    
+    // A mixer strip for every track and channel that plays notes
+    pub fn mixer_strips(&self) -> Vec<MixerStrip> {
+        let mut parts: Vec<(usize, u8)> = self.events
+            .iter()
+            .filter(|e| matches!(e.event, MidiMessage::NoteOn { .. }))
+            .map(|e| (e.track, e.channel))
+            .collect();
+        parts.sort_unstable();
+        parts.dedup();
+        parts
+            .into_iter()
+            .map(|(track, channel)| {
+                let name = self.track_names.get(track).cloned().unwrap_or_else(|| format!("Track {}", track + 1));
+                MixerStrip::new(track, channel, name)
+            })
+            .collect()
+    }
+    
+    // Loads a MIDI file, or a recording saved as JSON
+    pub fn load_file<P: AsRef<Path>>(&mut self, path: P) -> Result<()> {
+        let path = path.as_ref();
+        let song = Song::load(path)?;
+        self.load_song(song, path);
+        Ok(())
+    }
+    
+    pub fn load_song(&mut self, song: Song, path: &Path) {
+        self.stop();
+        self.current_file = Some(path.to_path_buf());
+        self.total_ticks = song.events.last().map(|e| e.absolute_time).unwrap_or(0);
+        self.has_drums = song.events.iter().any(|e| e.channel == DRUM_CHANNEL);
+        self.events = song.events;
+        self.timing = song.timing;
+        self.time_signatures = song.time_signatures;
+        self.track_names = song.track_names;
+        self.loop_markers = (None, None);
+        self.update_loop_region();
         
         // Debug file loading
         use std::io::Write;
@@ -95,22 +511,26 @@ impl MidiPlayer {
             .create(true)
             .append(true)
             .open("/tmp/piano_debug.log") {
-            writeln!(file, "MIDI file loaded: {} events, {} total ticks, tempo: {}, tpq: {}", 
-                    self.events.len(), self.total_ticks, self.tempo, self.ticks_per_quarter).ok();
+            writeln!(file, "MIDI file loaded: {} events, {} total ticks, tempo changes: {}", 
+                    self.events.len(), self.total_ticks, self.timing.tempo_changes()).ok();
             if !self.events.is_empty() {
                 let first_event = &self.events[0];
                 writeln!(file, "  First event at tick: {}", first_event.absolute_time).ok();
             }
         }
-        
-        Ok(())
     }
     
     pub fn play(&mut self) {
         if !self.events.is_empty() || self.current_file.is_some() {
             self.is_playing = true;
-            // Always reset start time for now to simplify debugging
-            self.start_time = Some(Instant::now());
+            // Carry on from the current position; the schedule restarts from
+            // the display cursor, since anything sent ahead was cancelled
+            let playhead = self.ticks_to_time(self.current_position);
+            self.clock = Some(PlaybackClock::new(Instant::now(), playhead, self.rate));
+            self.pass_start = Duration::ZERO;
+            self.schedule_pass_start = Duration::ZERO;
+            self.scheduled_until = None;
+            self.schedule_cursor = self.cursor;
             
             // Debug playback start
             use std::io::Write;
@@ -118,25 +538,23 @@ impl MidiPlayer {
                 .create(true)
                 .append(true)
                 .open("/tmp/piano_debug.log") {
-                writeln!(file, "PLAYBACK STARTED: {} events available, tempo: {}, tpq: {}", 
-                        self.events.len(), self.tempo, self.ticks_per_quarter).ok();
+                writeln!(file, "PLAYBACK STARTED: {} events available, tempo: {}", 
+                        self.events.len() - self.cursor, self.timing.tempo_at(self.current_position)).ok();
             }
         }
     }
     
     pub fn pause(&mut self) {
         self.is_playing = false;
-        // Keep start_time for resuming
+        // The position is kept for resuming
     }
     
     pub fn stop(&mut self) {
         self.is_playing = false;
-        self.start_time = None;
+        self.clock = None;
         self.current_position = 0;
-        
-        if let Some(path) = self.current_file.clone() {
-            let _ = self.load_file(path);
-        }
+        self.cursor = 0;
+        self.schedule_cursor = 0;
     }
     
     pub fn toggle_playback(&mut self) {
@@ -147,111 +565,280 @@ impl MidiPlayer {
             .append(true)
             .open("/tmp/piano_debug.log") {
             writeln!(file, "TOGGLE_PLAYBACK called - currently playing: {}, events: {}", 
-                    self.is_playing, self.events.len()).ok();
+                    self.is_playing, self.events.len() - self.cursor).ok();
         }
         
         if self.is_playing {
             self.pause();
         } else {
             // If we've reached the end, restart from beginning
-            if self.events.is_empty() && self.current_file.is_some() {
-                if let Some(path) = self.current_file.clone() {
-                    let _ = self.load_file(path);
-                }
+            if self.is_finished() {
+                self.stop();
+            }
+            if self.count_in && self.loops_between_markers() {
+                self.play_with_count_in();
+            } else {
+                self.play();
             }
-            self.play();
         }
     }
     
-    pub fn get_pending_events(&mut self) -> Vec<MidiMessage> {
-        if !self.is_playing || self.start_time.is_none() {
+    // Whether every event has been played
+    pub fn is_finished(&self) -> bool {
+        self.cursor >= self.events.len()
+    }
+    
+    // Events whose time has come, for the display. Every due event is
+    // returned, however many pile up between two calls.
+    pub fn get_pending_events(&mut self) -> Vec<MidiEvent> {
+        if self.count_in_remaining().is_some() {
             return Vec::new();
         }
+        let Some(clock) = self.clock.as_mut().filter(|_| self.is_playing) else {
+            return Vec::new();
+        };
         
-        let elapsed = self.start_time.unwrap().elapsed();
-        let current_tick = self.time_to_ticks(elapsed);
-        
-        // Debug timing and event processing - write to file
-        if elapsed.as_millis() % 1000 < 50 {  // Print every second
-            use std::io::Write;
-            if let Ok(mut file) = std::fs::OpenOptions::new()
-                .create(true)
-                .append(true)
-                .open("/tmp/piano_debug.log") {
-                writeln!(file, "Debug: elapsed={}ms, current_tick={}, events_left={}, tempo={}, tpq={}", 
-                        elapsed.as_millis(), current_tick, self.events.len(), self.tempo, self.ticks_per_quarter).ok();
-                
-                // Show next few events
-                if !self.events.is_empty() {
-                    let next_event = self.events.front().unwrap();
-                    writeln!(file, "  Next event at tick: {}, current tick: {}", next_event.absolute_time, current_tick).ok();
+        let now = Instant::now();
+        clock.prune(now);
+        let mut elapsed = clock.playhead_at(now).saturating_sub(self.pass_start);
+        let mut pending_events = Vec::new();
+        loop {
+            let end = self.loop_region.as_ref().map_or(self.events.len(), |r| r.end_index);
+            if self.cursor < end {
+                let due = self.events[self.cursor..end].partition_point(|e| e.time <= elapsed);
+                pending_events.extend_from_slice(&self.events[self.cursor..self.cursor + due]);
+                self.cursor += due;
+            }
+            
+            let Some(region) = &self.loop_region else {
+                if self.is_finished() {
+                    self.is_playing = false;
                 }
+                break;
+            };
+            if elapsed < region.end_time {
+                break;
             }
+            // Straight back to the loop start, with no gap
+            pending_events.extend_from_slice(&region.wrap_events);
+            self.cursor = region.start_index;
+            self.pass_start += region.length();
+            elapsed -= region.length();
         }
         
-        let mut pending_events = Vec::new();
-        let mut events_processed = 0;
+        self.current_position = self.time_to_ticks(elapsed).min(self.total_ticks);
+        pending_events
+    }
+    
+    // Events due within `lookahead` from now that haven't been handed out
+    // yet, each with the instant it should sound at. Sent to the synth this
+    // far ahead, notes keep exact timing however irregularly the UI runs.
+    pub fn schedule_ahead(&mut self, lookahead: Duration) -> Vec<(Instant, MidiEvent)> {
+        let Some(clock) = self.clock.as_mut().filter(|_| self.is_playing) else {
+            return Vec::new();
+        };
         
-        while !self.events.is_empty() && events_processed < 10 { // Limit to prevent infinite loops
-            if let Some(event) = self.events.front() {
-                if event.absolute_time <= current_tick {
-                    let event = self.events.pop_front().unwrap();
-                    pending_events.push(event.event);
-                    self.current_position = event.absolute_time;
-                    events_processed += 1;
-                } else {
+        let horizon = Instant::now() + lookahead;
+        let mut scheduled = Vec::new();
+        loop {
+            let end = self.loop_region.as_ref().map_or(self.events.len(), |r| r.end_index);
+            while self.schedule_cursor < end {
+                let event = &self.events[self.schedule_cursor];
+                let due = clock.instant_at(self.schedule_pass_start + event.time);
+                if due > horizon {
                     break;
                 }
-            } else {
+                scheduled.push((due, event.clone()));
+                self.schedule_cursor += 1;
+            }
+            
+            let Some(region) = &self.loop_region else {
+                break;
+            };
+            let wrap_at = clock.instant_at(self.schedule_pass_start + region.end_time);
+            if self.schedule_cursor < end || wrap_at > horizon {
                 break;
             }
+            scheduled.extend(region.wrap_events.iter().map(|e| (wrap_at, e.clone())));
+            self.schedule_cursor = region.start_index;
+            self.schedule_pass_start += region.length();
+            // Each pass through a practice loop can go a little faster,
+            // up to the file's own tempo
+            if self.loop_speed_up > 0.0 && self.rate < 1.0 {
+                self.rate = (self.rate + self.loop_speed_up).min(1.0);
+                clock.set_rate(self.rate, wrap_at);
+            }
         }
-        
-        // Update current position even if no events to process
-        if current_tick > self.current_position {
-            self.current_position = current_tick;
+        self.scheduled_until = Some(horizon);
+        scheduled
+    }
+    
+    pub fn rate(&self) -> f64 {
+        self.rate
+    }
+    
+    // Changes the playback speed without a jump: what the synth already has
+    // plays out at the old rate, and the new one takes over from there
+    pub fn set_rate(&mut self, rate: f64) {
+        self.rate = rate.clamp(MIN_PLAYBACK_RATE, MAX_PLAYBACK_RATE);
+        if let Some(clock) = &mut self.clock {
+            // Never before playback starts, so a count-in stays a bar long
+            let now = Instant::now().max(clock.segments[0].start);
+            let from = self.scheduled_until.map_or(now, |until| until.max(now));
+            clock.set_rate(self.rate, from);
         }
-        
-        if self.events.is_empty() && self.loop_enabled {
-            if let Some(path) = self.current_file.clone() {
-                let _ = self.load_file(path);
-                self.play();
-            }
-        } else if self.events.is_empty() {
-            self.is_playing = false;
+    }
+    
+    // The rate playback is going at right now, which lags a change by up to
+    // the scheduling lookahead
+    fn current_rate(&self) -> f64 {
+        self.clock.as_ref().filter(|_| self.is_playing).map_or(self.rate, |clock| clock.rate_at(Instant::now()))
+    }
+    
+    // Moves playback to `target_tick` in the loaded song. Returns the
+    // messages that bring the synth to the state the song is in there.
+    pub fn seek_to_tick(&mut self, target_tick: u64) -> Vec<MidiEvent> {
+        let target_tick = target_tick.min(self.total_ticks);
+        self.cursor = self.events.partition_point(|e| e.absolute_time < target_tick);
+        self.current_position = target_tick;
+        if self.is_playing {
+            self.play();
         }
-        
-        pending_events
+        self.state_at(self.cursor)
     }
     
-    pub fn seek_to_position(&mut self, position: f32) {
+    pub fn seek_to_position(&mut self, position: f32) -> Vec<MidiEvent> {
         let position = position.clamp(0.0, 1.0);
-        let target_tick = (self.total_ticks as f32 * position) as u64;
-        
-        if let Some(path) = self.current_file.clone() {
-            let _ = self.load_file(path);
-            
-            let mut absolute_time = 0u64;
-            while !self.events.is_empty() {
-                if let Some(event) = self.events.front() {
-                    if event.absolute_time > target_tick {
-                        break;
+        self.seek_to_tick((self.total_ticks as f32 * position) as u64)
+    }
+    
+    pub fn seek_by(&mut self, seconds: f64) -> Vec<MidiEvent> {
+        let now = self.ticks_to_time(self.current_position).as_secs_f64();
+        let target = Duration::from_secs_f64((now + seconds).max(0.0));
+        self.seek_to_tick(self.time_to_ticks(target))
+    }
+    
+    // Jumps to the start of the bar `bars` away from the current one
+    pub fn seek_bars(&mut self, bars: i64) -> Vec<MidiEvent> {
+        let segments = self.meter_segments();
+        let quarters = self.timing.quarters_at(self.current_position);
+        let segment = meter_segment_at(&segments, |s| s.start_quarters <= quarters);
+        // A little slack, so sitting on a bar line counts as being in that bar
+        let bar = segment.first_bar + ((quarters - segment.start_quarters) / segment.bar_quarters + 1e-6).floor();
+        let target_bar = (bar + bars as f64).max(0.0);
+        let segment = meter_segment_at(&segments, |s| s.first_bar <= target_bar);
+        let target = segment.start_quarters + (target_bar - segment.first_bar) * segment.bar_quarters;
+        self.seek_to_tick(self.timing.quarters_to_ticks(target))
+    }
+    
+    // The song's time signatures as runs of bars, starting from 4/4 until
+    // the file says otherwise
+    fn meter_segments(&self) -> Vec<MeterSegment> {
+        let mut segments = vec![MeterSegment { start_quarters: 0.0, first_bar: 0.0, bar_quarters: 4.0, signature: (4, 4) }];
+        for &(tick, (beats_per_bar, beat_unit)) in &self.time_signatures {
+            let start_quarters = self.timing.quarters_at(tick);
+            let last = *segments.last().unwrap();
+            let bar_quarters = beats_per_bar.max(1) as f64 * 4.0 / beat_unit.max(1) as f64;
+            let segment = MeterSegment {
+                start_quarters,
+                first_bar: last.first_bar + ((start_quarters - last.start_quarters) / last.bar_quarters - 1e-6).ceil().max(0.0),
+                bar_quarters,
+                signature: (beats_per_bar, beat_unit),
+            };
+            // A later signature at the same spot replaces the earlier one
+            if start_quarters <= last.start_quarters {
+                *segments.last_mut().unwrap() = MeterSegment { first_bar: last.first_bar, ..segment };
+            } else {
+                segments.push(segment);
+            }
+        }
+        segments
+    }
+    
+    // Beats per bar and beat unit at the playback position
+    pub fn time_signature(&self) -> (u8, u8) {
+        let quarters = self.timing.quarters_at(self.current_position);
+        meter_segment_at(&self.meter_segments(), |s| s.start_quarters <= quarters).signature
+    }
+    
+    // Notes sounding at the playback position, struck again when playback
+    // starts from there
+    pub fn held_notes(&self) -> Vec<MidiEvent> {
+        self.state_at(self.cursor)
+            .into_iter()
+            .filter(|event| matches!(event.event, MidiMessage::NoteOn { .. }))
+            .collect()
+    }
+    
+    // The messages that recreate the song's state just before event `index`:
+    // each channel's latest program, controllers and pitch bend in their
+    // original order, then the notes still held. Drum hits are one-shots
+    // and aren't struck again.
+    pub fn state_at(&self, index: usize) -> Vec<MidiEvent> {
+        let mut latest: HashMap<ChaseKey, usize> = HashMap::new();
+        let mut parameters = [(127u8, 127u8); 16];
+        let mut selections: HashMap<u8, usize> = HashMap::new();
+        for (i, event) in self.events[..index.min(self.events.len())].iter().enumerate() {
+            let channel = event.channel;
+            let parameter = &mut parameters[(channel & 0x0f) as usize];
+            match event.event {
+                MidiMessage::NoteOn { key, vel } if vel.as_int() > 0 && channel != DRUM_CHANNEL => {
+                    latest.insert(ChaseKey::Note(channel, key.as_int()), i);
+                }
+                MidiMessage::NoteOn { key, .. } | MidiMessage::NoteOff { key, .. } => {
+                    latest.remove(&ChaseKey::Note(channel, key.as_int()));
+                }
+                MidiMessage::Controller { controller, value } => match controller.as_int() {
+                    PARAMETER_MSB | PARAMETER_LSB => {
+                        if controller.as_int() == PARAMETER_MSB {
+                            parameter.0 = value.as_int();
+                        } else {
+                            parameter.1 = value.as_int();
+                        }
+                        selections.insert(channel, i);
                     }
-                    if let Some(event) = self.events.pop_front() {
-                        absolute_time = event.absolute_time;
+                    cc if DATA_ENTRY.contains(&cc) => {
+                        latest.insert(ChaseKey::DataEntry(channel, *parameter, cc), i);
                     }
-                } else {
-                    break;
+                    cc => {
+                        latest.insert(ChaseKey::Controller(channel, cc), i);
+                    }
+                },
+                MidiMessage::ProgramChange { .. } => {
+                    latest.insert(ChaseKey::Program(channel), i);
                 }
+                MidiMessage::PitchBend { .. } => {
+                    latest.insert(ChaseKey::PitchBend(channel), i);
+                }
+                _ => {}
             }
-            
-            self.current_position = absolute_time;
-            
-            if self.is_playing {
-                let elapsed_time = self.ticks_to_time(self.current_position);
-                self.start_time = Some(Instant::now() - elapsed_time);
+        }
+        
+        let mut chased: Vec<(ChaseKey, usize)> = latest.into_iter().collect();
+        // Notes go last, once the sound they start with is set up
+        chased.sort_by_key(|&(key, i)| (matches!(key, ChaseKey::Note(..)), i));
+        let select = |event: &MidiEvent, (msb, lsb): (u8, u8)| {
+            [(PARAMETER_MSB, msb), (PARAMETER_LSB, lsb)].map(|(controller, value)| MidiEvent {
+                event: MidiMessage::Controller { controller: controller.into(), value: value.into() },
+                ..event.clone()
+            })
+        };
+        
+        let mut state = Vec::new();
+        for (key, i) in chased {
+            let event = &self.events[i];
+            if let ChaseKey::DataEntry(_, parameter, _) = key {
+                state.extend(select(event, parameter));
             }
+            state.push(event.clone());
+        }
+        // Leave each channel with the parameter it last selected
+        let mut selections: Vec<(u8, usize)> = selections.into_iter().collect();
+        selections.sort_by_key(|&(_, i)| i);
+        for (channel, i) in selections {
+            state.extend(select(&self.events[i], parameters[(channel & 0x0f) as usize]));
         }
+        state
     }
     
     pub fn get_progress(&self) -> f32 {
@@ -267,25 +854,132 @@ impl MidiPlayer {
         (current_time, total_time)
     }
     
+    // Every loaded event with its time from the start of the file, for rendering
+    pub fn timed_messages(&self) -> Vec<(Duration, u8, MidiMessage)> {
+        self.events
+            .iter()
+            .map(|e| (e.time, e.channel, e.event))
+            .collect()
+    }
+    
     fn time_to_ticks(&self, time: Duration) -> u64 {
-        // Convert time to ticks based on tempo
-        // tempo is in microseconds per quarter note
-        // ticks_per_quarter is how many ticks make up a quarter note
-        let total_microseconds = time.as_micros() as f64;
-        let quarters = total_microseconds / (self.tempo as f64);
-        let ticks = quarters * (self.ticks_per_quarter as f64);
-        ticks as u64
+        self.timing.time_to_ticks(time)
+    }
+    
+    pub fn ticks_to_time(&self, ticks: u64) -> Duration {
+        self.timing.ticks_to_time(ticks)
     }
     
-    fn ticks_to_time(&self, ticks: u64) -> Duration {
-        // Convert ticks to time
-        let quarters = (ticks as f64) / (self.ticks_per_quarter as f64);
-        let microseconds = quarters * (self.tempo as f64);
-        Duration::from_micros(microseconds as u64)
+    // Quarter notes per minute at the playback position and rate
+    pub fn bpm(&self) -> f32 {
+        60_000_000.0 / self.timing.tempo_at(self.current_position).max(1) as f32 * self.current_rate() as f32
+    }
+    
+    // Playback position in beats of the time signature in force, counted so
+    // that each bar starts on a multiple of its beats. During a count-in it
+    // counts up to the position playback starts from.
+    pub fn beat_position(&self) -> f64 {
+        let count_in = self.count_in_remaining().map_or(0.0, |remaining| {
+            remaining.as_secs_f64() * self.bpm() as f64 / 60.0
+        });
+        let quarters = self.timing.quarters_at(self.current_position) - count_in;
+        let segments = self.meter_segments();
+        let segment = meter_segment_at(&segments, |s| s.start_quarters <= quarters);
+        let bars = segment.first_bar + (quarters - segment.start_quarters) / segment.bar_quarters;
+        bars * segment.signature.0.max(1) as f64
     }
     
     pub fn set_loop(&mut self, enabled: bool) {
         self.loop_enabled = enabled;
+        self.update_loop_region();
+    }
+    
+    pub fn set_loop_start(&mut self, tick: u64) {
+        self.loop_markers.0 = Some(tick.min(self.total_ticks));
+        self.order_loop_markers();
+    }
+    
+    // Setting B starts looping between the markers
+    pub fn set_loop_end(&mut self, tick: u64) {
+        self.loop_markers.1 = Some(tick.min(self.total_ticks));
+        self.loop_enabled = true;
+        self.order_loop_markers();
+    }
+    
+    // For clicks on the progress bar: the first sets A, the second B, and
+    // a third starts a new region
+    pub fn mark_loop_point(&mut self, tick: u64) {
+        match self.loop_markers {
+            (Some(_), None) => self.set_loop_end(tick),
+            _ => {
+                self.loop_markers = (None, None);
+                self.set_loop_start(tick);
+            }
+        }
+    }
+    
+    pub fn clear_loop_markers(&mut self) {
+        self.loop_markers = (None, None);
+        self.update_loop_region();
+    }
+    
+    fn order_loop_markers(&mut self) {
+        if let (Some(a), Some(b)) = self.loop_markers {
+            self.loop_markers = (Some(a.min(b)), Some(a.max(b)));
+        }
+        self.update_loop_region();
+    }
+    
+    // Whether looping is confined to the A-B markers
+    pub fn loops_between_markers(&self) -> bool {
+        self.loop_enabled && matches!(self.loop_markers, (Some(a), Some(b)) if a < b)
+    }
+    
+    fn update_loop_region(&mut self) {
+        self.loop_region = None;
+        if !self.loop_enabled || self.events.is_empty() {
+            return;
+        }
+        
+        let (start, end_index, end) = match self.loop_markers {
+            (Some(a), Some(b)) if a < b => (a, self.events.partition_point(|e| e.absolute_time < b), b),
+            _ => (0, self.events.len(), self.total_ticks),
+        };
+        let start_index = self.events.partition_point(|e| e.absolute_time < start);
+        let (start_time, end_time) = (self.ticks_to_time(start), self.ticks_to_time(end));
+        if end_time <= start_time {
+            return;
+        }
+        
+        let mut wrap_events: Vec<MidiEvent> = self.state_at(end_index)
+            .into_iter()
+            .filter_map(|event| match event.event {
+                MidiMessage::NoteOn { key, .. } => Some(MidiEvent {
+                    event: MidiMessage::NoteOff { key, vel: 0.into() },
+                    ..event
+                }),
+                _ => None,
+            })
+            .collect();
+        wrap_events.extend(self.state_at(start_index));
+        self.loop_region = Some(LoopRegion { start_index, end_index, start_time, end_time, wrap_events });
+    }
+    
+    // Starts playback after a bar of count-in at the current tempo and rate
+    pub fn play_with_count_in(&mut self) {
+        self.play();
+        let (beats_per_bar, beat_unit) = self.time_signature();
+        let quarters = beats_per_bar.max(1) as f64 * 4.0 / beat_unit.max(1) as f64;
+        let bar = Duration::from_micros(self.timing.tempo_at(self.current_position) as u64).mul_f64(quarters / self.rate);
+        let playhead = self.ticks_to_time(self.current_position);
+        self.clock = Some(PlaybackClock::new(Instant::now() + bar, playhead, self.rate));
+    }
+    
+    // How long is left of the count-in, while there is one
+    pub fn count_in_remaining(&self) -> Option<Duration> {
+        let start = self.clock.as_ref().filter(|_| self.is_playing)?.segments.first()?.start;
+        let remaining = start.saturating_duration_since(Instant::now());
+        (!remaining.is_zero()).then_some(remaining)
     }
     
     pub fn is_loop_enabled(&self) -> bool {
@@ -356,12 +1050,12 @@ impl MidiRecorder {
     }
 }
 
-pub fn midi_note_to_frequency(midi_note: u8) -> f32 {
-    440.0 * 2.0_f32.powf((midi_note as f32 - 69.0) / 12.0)
+pub fn midi_note_to_frequency(midi_note: u8, tuning: &Tuning) -> Option<f32> {
+    tuning.frequency(midi_note)
 }
 
-pub fn frequency_to_midi_note(frequency: f32) -> u8 {
-    (69.0 + 12.0 * (frequency / 440.0).log2()).round() as u8
+pub fn frequency_to_midi_note(frequency: f32, tuning: &Tuning) -> Option<u8> {
+    tuning.nearest_note(frequency)
 }
 
 pub fn note_name_to_midi_note(note_name: &str, octave: u8) -> Result<u8> {
@@ -397,8 +1091,9 @@ mod tests {
 
     #[test]
     fn test_midi_note_conversion() {
-        assert_eq!(midi_note_to_frequency(69), 440.0);
-        assert_eq!(frequency_to_midi_note(440.0), 69);
+        let tuning = Tuning::default();
+        assert_eq!(midi_note_to_frequency(69, &tuning), Some(440.0));
+        assert_eq!(frequency_to_midi_note(440.0, &tuning), Some(69));
         
         let (note, octave) = midi_note_to_note_name(69);
         assert_eq!(note, "A");
@@ -411,8 +1106,7 @@ mod tests {
     #[test]
     fn test_time_conversion() {
         let mut player = MidiPlayer::new();
-        player.tempo = 500000; // 120 BPM
-        player.ticks_per_quarter = 480;
+        player.timing = TimingModel::Metrical(TempoMap::new(480, &[(0, 500000)])); // 120 BPM
         
         let one_second = Duration::from_secs(1);
         let ticks = player.time_to_ticks(one_second);
@@ -420,4 +1114,401 @@ mod tests {
         
         assert!((time_back.as_secs_f64() - 1.0).abs() < 0.01);
     }
-}
\ No newline at end of file
+    // A one-track file at 480 PPQ: one second at 120 BPM, half a second at
+    // 240 BPM, then a second at 60 BPM
+    // Built in memory so tests running in parallel don't share a file
+    fn tempo_change_player() -> MidiPlayer {
+        use midly::{Format, Header, TrackEvent, TrackEventKind};
+
+        let tempo = |delta: u32, micros: u32| TrackEvent {
+            delta: delta.into(),
+            kind: TrackEventKind::Meta(MetaMessage::Tempo(micros.into())),
+        };
+        let note = |delta: u32, key: u8| TrackEvent {
+            delta: delta.into(),
+            kind: TrackEventKind::Midi {
+                channel: 0.into(),
+                message: MidiMessage::NoteOn { key: key.into(), vel: 100.into() },
+            },
+        };
+        let track = vec![
+            tempo(0, 500_000),
+            note(0, 60),
+            tempo(960, 250_000),
+            note(0, 62),
+            tempo(960, 1_000_000),
+            note(0, 64),
+            note(480, 65),
+            TrackEvent { delta: 0.into(), kind: TrackEventKind::Meta(MetaMessage::EndOfTrack) },
+        ];
+        let smf = Smf {
+            header: Header::new(Format::SingleTrack, Timing::Metrical(480.into())),
+            tracks: vec![track],
+        };
+        let mut data = Vec::new();
+        smf.write(&mut data).unwrap();
+        let mut player = MidiPlayer::new();
+        player.load_song(Song::from_smf(&data).unwrap(), Path::new("tempo.mid"));
+        player
+    }
+
+    #[test]
+    fn test_tempo_map_follows_tempo_changes() {
+        let mut player = tempo_change_player();
+
+        let times: Vec<f64> = player.timed_messages().iter().map(|(time, _, _)| time.as_secs_f64()).collect();
+        assert_eq!(times, vec![0.0, 1.0, 1.5, 2.5]);
+        let (_, total) = player.get_time_info();
+        assert!((total.as_secs_f64() - 2.5).abs() < 1e-6);
+
+        // Time and ticks convert both ways inside every segment
+        for (seconds, tick) in [(0.5, 480), (1.25, 1440), (2.0, 2160)] {
+            assert_eq!(player.time_to_ticks(Duration::from_secs_f64(seconds)), tick);
+            assert!((player.ticks_to_time(tick).as_secs_f64() - seconds).abs() < 1e-6);
+        }
+
+        player.current_position = 1500;
+        assert_eq!(player.bpm(), 240.0);
+        assert_eq!(player.timing.tempo_changes(), 2);
+
+        // An unnamed track is named by its number, with one mixer strip
+        let strips = player.mixer_strips();
+        assert_eq!(strips.len(), 1);
+        assert_eq!(strips[0].label(), "Track 1 (ch 1)");
+    }
+
+    // Pretends playback started `seconds` ago
+    fn rewind_start(player: &mut MidiPlayer, seconds: f64) {
+        let start = Instant::now() - Duration::from_secs_f64(seconds);
+        player.clock = Some(PlaybackClock::new(start, Duration::ZERO, player.rate));
+    }
+
+    #[test]
+    fn test_cursor_playback_schedules_ahead_and_loops() {
+        let mut player = tempo_change_player();
+
+        player.play();
+        rewind_start(&mut player, 1.2);
+        assert_eq!(player.get_pending_events().len(), 2);
+        assert!(player.current_position >= 1344 && player.current_position < 1400);
+
+        // The synth gets events before the display does, timed from the start
+        let start = player.clock.as_ref().unwrap().instant_at(Duration::ZERO);
+        let scheduled = player.schedule_ahead(Duration::from_millis(500));
+        let offsets: Vec<f64> = scheduled.iter().map(|(when, _)| (*when - start).as_secs_f64()).collect();
+        assert_eq!(offsets.len(), 3);
+        assert!((offsets[2] - 1.5).abs() < 1e-6);
+        assert!(player.schedule_ahead(Duration::from_millis(500)).is_empty());
+
+        // Looping goes straight back to the first event without reloading,
+        // letting go of the notes still held at the end
+        player.set_loop(true);
+        rewind_start(&mut player, 2.6);
+        player.cursor = 2;
+        assert_eq!(player.get_pending_events().len(), 7);
+        assert!(player.is_playing);
+        assert_eq!(player.cursor, 1);
+
+        // Pausing and resuming carries on from the same place
+        player.pause();
+        let position = player.current_position;
+        player.play();
+        assert!(player.current_position == position && player.schedule_cursor == 1);
+    }
+
+    #[test]
+    fn test_recordings_load_like_midi_files() {
+        let mut recording = Recording::new();
+        for (millis, event_type) in [
+            (0, RecordingEventType::SustainPedal { pressed: true }),
+            (250, RecordingEventType::NoteOn { midi_note: 60, velocity: 90 }),
+            (1250, RecordingEventType::NoteOff { midi_note: 60 }),
+        ] {
+            recording.events.push(crate::audio::RecordingEvent { timestamp: Duration::from_millis(millis), event_type });
+        }
+        let path = std::env::temp_dir().join(format!("terminal-piano-song-{}.json", std::process::id()));
+        recording.save_to_file(&path).unwrap();
+
+        let mut player = MidiPlayer::new();
+        player.load_file(&path).unwrap();
+        std::fs::remove_file(&path).ok();
+        assert_eq!(player.track_names, vec!["Recording".to_string()]);
+        let times: Vec<f64> = player.timed_messages().iter().map(|(time, _, _)| time.as_secs_f64()).collect();
+        assert!(times.iter().zip([0.0, 0.25, 1.25]).all(|(a, b)| (a - b).abs() < 1e-3));
+
+        // The transport works on it as on any file: a second in at 120 BPM
+        // is two beats, and seeking brings back the pedal and the held note
+        let state = player.seek_by(1.0);
+        assert!((player.beat_position() - 2.0).abs() < 1e-2);
+        assert_eq!(state.len(), 2);
+        assert_eq!(player.mixer_strips().len(), 1);
+    }
+
+    #[test]
+    fn test_loop_between_markers() {
+        let mut player = tempo_change_player();
+
+        // Markers set back to front are swapped; B turns looping on
+        player.set_loop_start(2400);
+        assert!(!player.loops_between_markers());
+        player.set_loop_end(960);
+        assert_eq!(player.loop_markers, (Some(960), Some(2400)));
+        assert!(player.loops_between_markers());
+
+        // Past B playback goes round to A: the held notes are let go, the
+        // one sounding at A comes back, and the note at B never plays
+        player.seek_to_tick(960);
+        player.play();
+        player.clock = Some(PlaybackClock::new(Instant::now() - Duration::from_secs_f64(1.6), Duration::from_secs(1), 1.0));
+        let events = player.get_pending_events();
+        let keys: Vec<(bool, u8)> = events.iter().filter_map(|e| match e.event {
+            MidiMessage::NoteOn { key, .. } => Some((true, key.as_int())),
+            MidiMessage::NoteOff { key, .. } => Some((false, key.as_int())),
+            _ => None,
+        }).collect();
+        assert_eq!(keys, vec![(true, 62), (true, 64), (false, 60), (false, 62), (false, 64), (true, 60), (true, 62)]);
+        assert_eq!(player.cursor, 2);
+        assert!(player.current_position >= 1152 && player.current_position < 1200);
+
+        // Right clicks start a new pair of markers, and clearing them leaves
+        // the whole song looping
+        player.mark_loop_point(1920);
+        assert_eq!(player.loop_markers, (Some(1920), None));
+        player.mark_loop_point(0);
+        assert_eq!(player.loop_markers, (Some(0), Some(1920)));
+        player.clear_loop_markers();
+        assert!(player.is_loop_enabled() && !player.loops_between_markers());
+    }
+
+    #[test]
+    fn test_loop_count_in_and_speed_up() {
+        let mut player = tempo_change_player();
+        player.set_loop_start(960);
+        player.set_loop_end(2400);
+        player.seek_to_tick(960);
+        player.set_rate(0.5);
+        player.count_in = true;
+        player.loop_speed_up = 0.25;
+
+        // A bar of 4/4 at 240 BPM, at half speed, before anything plays
+        player.toggle_playback();
+        let remaining = player.count_in_remaining().unwrap();
+        assert!(remaining > Duration::from_millis(1900) && remaining <= Duration::from_secs(2));
+        assert!(player.get_pending_events().is_empty());
+        assert!(player.beat_position() < 1.0);
+        let start = player.clock.as_ref().unwrap().instant_at(Duration::from_secs(1));
+        assert!(start >= Instant::now() + Duration::from_millis(1900));
+
+        // Each pass round the loop goes a quarter faster, up to full speed
+        let scheduled = player.schedule_ahead(Duration::from_secs(10));
+        assert_eq!(player.rate(), 1.0);
+        let clock = player.clock.as_ref().unwrap();
+        let first_wrap = start + Duration::from_secs(3);
+        assert_eq!(clock.rate_at(first_wrap - Duration::from_millis(1)), 0.5);
+        assert_eq!(clock.rate_at(first_wrap + Duration::from_millis(1)), 0.75);
+        assert!(scheduled.iter().any(|(when, _)| *when == first_wrap));
+    }
+
+    #[test]
+    fn test_rate_changes_take_over_after_what_is_scheduled() {
+        let mut player = tempo_change_player();
+
+        player.play();
+        rewind_start(&mut player, 0.5);
+        // Events at 0.0 and 1.0 seconds are already with the synth
+        assert_eq!(player.schedule_ahead(Duration::from_millis(600)).len(), 2);
+        let until = player.scheduled_until.unwrap();
+        player.set_rate(0.5);
+
+        // The event at 1.5 seconds is 0.4 seconds of song past the change,
+        // which now takes 0.8 seconds
+        let clock = player.clock.clone().unwrap();
+        let due = clock.instant_at(Duration::from_secs_f64(1.5));
+        assert!(((due - until).as_secs_f64() - 0.8).abs() < 1e-3);
+        assert_eq!(clock.rate_at(Instant::now()), 1.0);
+        assert_eq!(clock.rate_at(until + Duration::from_millis(1)), 0.5);
+        // Nothing already scheduled moves, and the playhead doesn't jump
+        assert!((clock.playhead_at(until).as_secs_f64() - 1.1).abs() < 1e-2);
+
+        // The length shown is the song's own, with the rate beside it
+        let (_, total) = player.get_time_info();
+        assert!((total.as_secs_f64() - 2.5).abs() < 1e-6);
+        player.set_rate(9.0);
+        assert_eq!(player.rate(), MAX_PLAYBACK_RATE);
+    }
+
+    #[test]
+    fn test_every_due_event_is_returned() {
+        let mut player = MidiPlayer::new();
+        player.events = (0..40u8)
+            .map(|key| MidiEvent {
+                delta_time: 0,
+                absolute_time: 0,
+                time: Duration::ZERO,
+                track: 0,
+                channel: 0,
+                event: MidiMessage::NoteOn { key: (40 + key).into(), vel: 100.into() },
+            })
+            .collect();
+        player.play();
+        assert_eq!(player.schedule_ahead(Duration::ZERO).len(), 40);
+        assert_eq!(player.get_pending_events().len(), 40);
+        assert!(!player.is_playing);
+    }
+
+    #[test]
+    fn test_seek_rebuilds_channel_state() {
+        let event = |tick: u64, channel: u8, event: MidiMessage| MidiEvent {
+            delta_time: 0,
+            absolute_time: tick,
+            time: Duration::from_millis(tick),
+            track: 0,
+            channel,
+            event,
+        };
+        let cc = |controller: u8, value: u8| MidiMessage::Controller { controller: controller.into(), value: value.into() };
+        let on = |key: u8| MidiMessage::NoteOn { key: key.into(), vel: 90.into() };
+        let mut player = MidiPlayer::new();
+        player.events = vec![
+            event(0, 1, MidiMessage::ProgramChange { program: 24.into() }),
+            event(0, 1, cc(7, 100)),
+            event(0, 1, cc(101, 0)),
+            event(0, 1, cc(100, 0)),
+            event(0, 1, cc(6, 12)),
+            event(0, 1, cc(101, 127)),
+            event(0, 1, cc(100, 127)),
+            event(10, 1, on(60)),
+            event(10, 1, on(64)),
+            event(10, DRUM_CHANNEL, on(36)),
+            event(20, 1, cc(7, 80)),
+            event(20, 1, MidiMessage::NoteOff { key: 60.into(), vel: 0.into() }),
+            event(30, 1, on(67)),
+        ];
+        player.total_ticks = 30;
+
+        let state = player.seek_to_tick(25);
+        assert_eq!(player.cursor, 12);
+        let messages: Vec<MidiMessage> = state.iter().map(|e| e.event).collect();
+        assert_eq!(messages, vec![
+            MidiMessage::ProgramChange { program: 24.into() },
+            // The bend range is set on the parameter it was meant for
+            cc(101, 0),
+            cc(100, 0),
+            cc(6, 12),
+            cc(7, 80),
+            on(64),
+            cc(101, 127),
+            cc(100, 127),
+        ]);
+        // Only the held note is struck again when playback resumes there
+        let held: Vec<MidiMessage> = player.held_notes().iter().map(|e| e.event).collect();
+        assert_eq!(held, vec![on(64)]);
+
+        // Back at the start there is nothing to replay
+        assert!(player.seek_to_position(0.0).is_empty());
+        assert_eq!(player.cursor, 0);
+    }
+
+    #[test]
+    fn test_seek_by_time_and_bars() {
+        let mut player = tempo_change_player();
+
+        // One second in is tick 960; a bar of 4/4 is 1920 ticks
+        player.seek_by(1.0);
+        assert_eq!(player.current_position, 960);
+        assert_eq!(player.cursor, 1);
+        player.seek_bars(1);
+        assert_eq!(player.current_position, 1920);
+        player.seek_bars(1);
+        assert_eq!(player.current_position, player.total_ticks);
+        // The last tick is in the second bar, so back one is the first
+        player.seek_bars(-1);
+        assert_eq!(player.current_position, 0);
+        player.seek_by(2.0);
+        player.seek_by(-10.0);
+        assert_eq!(player.current_position, 0);
+    }
+
+    #[test]
+    fn test_bars_follow_time_signature_changes() {
+        use midly::{Format, Header, TrackEvent, TrackEventKind};
+
+        let signature = |delta: u32, numerator: u8, denominator_power: u8| TrackEvent {
+            delta: delta.into(),
+            kind: TrackEventKind::Meta(MetaMessage::TimeSignature(numerator, denominator_power, 24, 8)),
+        };
+        let note = TrackEvent {
+            delta: 3840.into(),
+            kind: TrackEventKind::Midi {
+                channel: 0.into(),
+                message: MidiMessage::NoteOn { key: 60.into(), vel: 100.into() },
+            },
+        };
+        // The change to 2/4 comes first in track order, the opening 6/8 after it
+        let smf = Smf {
+            header: Header::new(Format::Parallel, Timing::Metrical(480.into())),
+            tracks: vec![vec![signature(2880, 2, 2), note], vec![signature(0, 6, 3)]],
+        };
+        let mut data = Vec::new();
+        smf.write(&mut data).unwrap();
+        let mut player = MidiPlayer::new();
+        player.load_song(Song::from_smf(&data).unwrap(), Path::new("meter.mid"));
+        assert_eq!(player.time_signature(), (6, 8));
+
+        // Two bars of 6/8 at 1440 ticks each, then bars of 2/4 at 960
+        player.seek_bars(1);
+        assert_eq!(player.current_position, 1440);
+        assert!((player.beat_position() - 6.0).abs() < 1e-6);
+        player.seek_bars(1);
+        assert_eq!(player.current_position, 2880);
+        assert_eq!(player.time_signature(), (2, 4));
+        assert!((player.beat_position() - 4.0).abs() < 1e-6);
+        player.seek_bars(1);
+        assert_eq!(player.current_position, 3840);
+        player.seek_bars(-2);
+        assert_eq!(player.current_position, 1440);
+    }
+
+    #[test]
+    fn test_timecode_files_play_in_absolute_time() {
+        use midly::{Format, Fps, Header, TrackEvent, TrackEventKind};
+
+        // 25 fps with 40 ticks per frame is 1000 ticks per second, whatever
+        // the tempo events say
+        let note = |delta: u32, key: u8| TrackEvent {
+            delta: delta.into(),
+            kind: TrackEventKind::Midi {
+                channel: 0.into(),
+                message: MidiMessage::NoteOn { key: key.into(), vel: 100.into() },
+            },
+        };
+        let track = vec![
+            TrackEvent { delta: 0.into(), kind: TrackEventKind::Meta(MetaMessage::Tempo(250_000.into())) },
+            note(0, 60),
+            note(500, 62),
+            TrackEvent { delta: 0.into(), kind: TrackEventKind::Meta(MetaMessage::Tempo(1_000_000.into())) },
+            note(1500, 64),
+            TrackEvent { delta: 0.into(), kind: TrackEventKind::Meta(MetaMessage::EndOfTrack) },
+        ];
+        let smf = Smf {
+            header: Header::new(Format::SingleTrack, Timing::Timecode(Fps::Fps25, 40)),
+            tracks: vec![track],
+        };
+        let path = std::env::temp_dir().join(format!("terminal-piano-timecode-{}.mid", std::process::id()));
+        smf.save(&path).unwrap();
+
+        let mut player = MidiPlayer::new();
+        player.load_file(&path).unwrap();
+        std::fs::remove_file(&path).ok();
+
+        let times: Vec<f64> = player.timed_messages().iter().map(|(time, _, _)| time.as_secs_f64()).collect();
+        assert_eq!(times, vec![0.0, 0.5, 2.0]);
+        assert_eq!(player.time_to_ticks(Duration::from_millis(1250)), 1250);
+        assert_eq!(player.timing.tempo_changes(), 0);
+        assert_eq!(player.bpm(), 120.0);
+
+        // 29.97 fps drop-frame runs slightly slower than 30
+        let timing = TimingModel::from_header(Timing::Timecode(Fps::Fps29, 100), &[]);
+        assert!((timing.ticks_to_time(2997).as_secs_f64() - 1.0).abs() < 1e-6);
+    }
+}
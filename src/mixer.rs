@@ -0,0 +1,191 @@
+use anyhow::Result;
+use midly::MidiMessage;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+
+// How much one step in the mixer panel changes a strip's volume
+const VOLUME_STEP: f32 = 0.1;
+
+// One row of the mixer: the notes one track of a MIDI file plays on one channel
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MixerStrip {
+    pub track: usize,
+    pub channel: u8,
+    #[serde(skip)]
+    pub name: String,
+    pub muted: bool,
+    pub solo: bool,
+    // From 0.0 to 1.0, scaling the velocity of the strip's notes
+    pub volume: f32,
+}
+
+impl MixerStrip {
+    pub fn new(track: usize, channel: u8, name: String) -> Self {
+        Self { track, channel, name, muted: false, solo: false, volume: 1.0 }
+    }
+
+    pub fn label(&self) -> String {
+        format!("{} (ch {})", self.name, self.channel + 1)
+    }
+
+    pub fn adjust_volume(&mut self, steps: i32) {
+        self.volume = ((self.volume / VOLUME_STEP).round() + steps as f32).clamp(0.0, 1.0 / VOLUME_STEP) * VOLUME_STEP;
+    }
+
+    fn is_default(&self) -> bool {
+        !self.muted && !self.solo && self.volume == 1.0
+    }
+}
+
+// Mute, solo and volume for the parts of a MIDI file, so one hand can be
+// practiced against the rest of the arrangement
+#[derive(Debug, Clone, Default)]
+pub struct Mixer {
+    pub strips: Vec<MixerStrip>,
+}
+
+// Saved mixer settings, by MIDI file path
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct MixerFile {
+    #[serde(default)]
+    files: BTreeMap<String, Vec<MixerStrip>>,
+}
+
+impl Mixer {
+    pub fn new(strips: Vec<MixerStrip>) -> Self {
+        Self { strips }
+    }
+
+    fn strip(&self, track: usize, channel: u8) -> Option<&MixerStrip> {
+        self.strips.iter().find(|s| s.track == track && s.channel == channel)
+    }
+
+    // Velocity scale for notes of `track` on `channel`, or `None` while they
+    // are muted or another strip is soloed
+    pub fn gain(&self, track: usize, channel: u8) -> Option<f32> {
+        let Some(strip) = self.strip(track, channel) else {
+            return Some(1.0);
+        };
+        let soloing = self.strips.iter().any(|s| s.solo);
+        (!strip.muted && (strip.solo || !soloing)).then_some(strip.volume)
+    }
+
+    // A message as it should reach the synth. Only note-ons are dropped or
+    // scaled; note-offs, controllers and program changes always get through,
+    // so nothing hangs when a strip is muted mid-note.
+    pub fn apply(&self, track: usize, channel: u8, message: &MidiMessage) -> Option<MidiMessage> {
+        match *message {
+            MidiMessage::NoteOn { key, vel } if vel.as_int() > 0 => {
+                let gain = self.gain(track, channel)?;
+                let vel = (vel.as_int() as f32 * gain).round() as u8;
+                (vel > 0).then(|| MidiMessage::NoteOn { key, vel: vel.min(127).into() })
+            }
+            _ => Some(*message),
+        }
+    }
+
+    // Takes the settings saved for `song` in the settings file, if any
+    pub fn restore(&mut self, settings: &Path, song: &Path) {
+        let Ok(contents) = fs::read_to_string(settings) else {
+            return;
+        };
+        let Ok(file) = toml::from_str::<MixerFile>(&contents) else {
+            return;
+        };
+        for saved in file.files.get(&song_key(song)).into_iter().flatten() {
+            if let Some(strip) = self.strips.iter_mut().find(|s| s.track == saved.track && s.channel == saved.channel) {
+                strip.muted = saved.muted;
+                strip.solo = saved.solo;
+                strip.volume = saved.volume.clamp(0.0, 1.0);
+            }
+        }
+    }
+
+    // Saves the strips for `song`, keeping other files' settings. Files
+    // back at the defaults are left out.
+    pub fn store(&self, settings: &Path, song: &Path) -> Result<()> {
+        let mut file: MixerFile = match fs::read_to_string(settings) {
+            Ok(contents) => toml::from_str(&contents).unwrap_or_default(),
+            Err(_) => MixerFile::default(),
+        };
+        let changed: Vec<MixerStrip> = self.strips.iter().filter(|s| !s.is_default()).cloned().collect();
+        if changed.is_empty() {
+            file.files.remove(&song_key(song));
+        } else {
+            file.files.insert(song_key(song), changed);
+        }
+
+        if let Some(parent) = settings.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(settings, toml::to_string_pretty(&file)?)?;
+        Ok(())
+    }
+}
+
+fn song_key(song: &Path) -> String {
+    song.canonicalize().unwrap_or_else(|_| song.to_path_buf()).to_string_lossy().into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn note_on(vel: u8) -> MidiMessage {
+        MidiMessage::NoteOn { key: 60.into(), vel: vel.into() }
+    }
+
+    fn test_mixer() -> Mixer {
+        Mixer::new(vec![
+            MixerStrip::new(1, 0, "Right Hand".to_string()),
+            MixerStrip::new(2, 0, "Left Hand".to_string()),
+            MixerStrip::new(3, 9, "Drums".to_string()),
+        ])
+    }
+
+    #[test]
+    fn test_mute_solo_and_volume() {
+        let mut mixer = test_mixer();
+        mixer.strips[0].muted = true;
+        mixer.strips[1].volume = 0.5;
+        assert_eq!(mixer.apply(1, 0, &note_on(100)), None);
+        assert_eq!(mixer.apply(2, 0, &note_on(100)), Some(note_on(50)));
+        // Note-offs get through a mute, and unknown parts play as they are
+        let off = MidiMessage::NoteOff { key: 60.into(), vel: 0.into() };
+        assert_eq!(mixer.apply(1, 0, &off), Some(off));
+        assert_eq!(mixer.apply(5, 3, &note_on(100)), Some(note_on(100)));
+
+        // Soloing silences everything else
+        mixer.strips[2].solo = true;
+        assert_eq!(mixer.gain(2, 0), None);
+        assert_eq!(mixer.gain(3, 9), Some(1.0));
+
+        // Turning a strip right down doesn't turn its notes into note-offs
+        mixer.strips[2].adjust_volume(-10);
+        assert_eq!(mixer.strips[2].volume, 0.0);
+        assert_eq!(mixer.apply(3, 9, &note_on(100)), None);
+    }
+
+    #[test]
+    fn test_settings_persist_per_file() {
+        let dir = std::env::temp_dir().join(format!("terminal-piano-mixer-{}", std::process::id()));
+        let settings = dir.join("mixer.toml");
+        let (first, second) = (Path::new("/songs/first.mid"), Path::new("/songs/second.mid"));
+
+        let mut mixer = test_mixer();
+        mixer.strips[1].muted = true;
+        mixer.strips[2].adjust_volume(-3);
+        mixer.store(&settings, first).unwrap();
+        test_mixer().store(&settings, second).unwrap();
+
+        let mut restored = test_mixer();
+        restored.restore(&settings, first);
+        assert_eq!(restored.strips, mixer.strips);
+        let mut other = test_mixer();
+        other.restore(&settings, second);
+        assert_eq!(other.strips, test_mixer().strips);
+        std::fs::remove_dir_all(&dir).ok();
+    }
+}
@@ -43,7 +43,7 @@ impl NoteName {
         matches!(self, NoteName::CSharp | NoteName::DSharp | NoteName::FSharp | NoteName::GSharp | NoteName::ASharp)
     }
     
-    pub fn to_string(&self) -> &'static str {
+    pub fn to_string(self) -> &'static str {
         match self {
             NoteName::C => "C",
             NoteName::CSharp => "C#",
@@ -78,10 +78,6 @@ impl Note {
             note_type,
         }
     }
-    
-    pub fn frequency(&self) -> f32 {
-        440.0 * 2.0_f32.powf((self.midi_note as f32 - 69.0) / 12.0)
-    }
 }
 
 #[derive(Debug)]
@@ -89,6 +85,7 @@ pub struct Piano {
     pub current_octave: u8,
     pub pressed_keys: HashMap<u8, Instant>, // Track when keys were pressed
     pub sustain_pedal: bool,
+    pub soft_pedal: bool,
     pub volume: f32,
     pub key_mappings: HashMap<char, u8>,
 }
@@ -99,6 +96,7 @@ impl Piano {
             current_octave: 4,
             pressed_keys: HashMap::new(),
             sustain_pedal: false,
+            soft_pedal: false,
             volume: 0.7,
             key_mappings: HashMap::new(),
         };
@@ -194,8 +192,8 @@ impl Piano {
         }
     }
     
-    pub fn toggle_sustain(&mut self) {
-        self.sustain_pedal = !self.sustain_pedal;
+    pub fn set_sustain(&mut self, pressed: bool) {
+        self.sustain_pedal = pressed;
         if !self.sustain_pedal {
             self.pressed_keys.clear();
         }
@@ -0,0 +1,207 @@
+use anyhow::{anyhow, Result};
+use midly::MidiMessage;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use crate::audio::AudioEngine;
+use crate::config::AudioConfig;
+use crate::midi::MidiPlayer;
+
+// How long to keep rendering after the last event while notes ring out
+const MAX_TAIL_SECONDS: f32 = 5.0;
+const BLOCK_SIZE: usize = 1024;
+const CHANNELS: usize = 2;
+const SILENCE_THRESHOLD: f32 = 1e-4;
+
+// Renders a MIDI file or recording to a WAV file, or every MIDI file in a
+// directory. Returns the paths that were written.
+pub fn render_path(input: &Path, output: Option<&Path>, config: &AudioConfig) -> Result<Vec<PathBuf>> {
+    if !input.is_dir() {
+        let output = output
+            .map(Path::to_path_buf)
+            .unwrap_or_else(|| input.with_extension("wav"));
+        render_file(input, &output, config)?;
+        return Ok(vec![output]);
+    }
+
+    let mut inputs: Vec<PathBuf> = std::fs::read_dir(input)?
+        .filter_map(|entry| entry.ok().map(|e| e.path()))
+        .filter(|path| has_extension(path, &["mid", "midi"]))
+        .collect();
+    inputs.sort();
+
+    let output_dir = output.unwrap_or(input);
+    std::fs::create_dir_all(output_dir)?;
+
+    let mut written = Vec::new();
+    for path in inputs {
+        let file_name = path.file_name().ok_or_else(|| anyhow!("Invalid file name: {}", path.display()))?;
+        let output = output_dir.join(file_name).with_extension("wav");
+        render_file(&path, &output, config)?;
+        written.push(output);
+    }
+    Ok(written)
+}
+
+pub fn render_file(input: &Path, output: &Path, config: &AudioConfig) -> Result<()> {
+    // Recordings (JSON) and MIDI files load into the same player
+    let mut player = MidiPlayer::new();
+    player.load_file(input)?;
+    let messages = player.timed_messages();
+
+    let mut engine = AudioEngine::offline(config);
+    if let Some(path) = &config.sound_font {
+        if let Err(e) = engine.load_sound_font(path, config.sound_font_bank, config.sound_font_preset) {
+            eprintln!("SoundFont unavailable ({}), using built-in synth", e);
+        }
+    }
+    if let Err(e) = engine.set_tuning(&config.tuning) {
+        eprintln!("Tuning unavailable ({}), using equal temperament", e);
+    }
+
+    let samples = render_messages(&mut engine, &messages)?;
+    write_wav(output, &samples, config.sample_rate, config.bit_depth)
+}
+
+// Plays `messages` through an offline engine, each one landing on the frame
+// its timestamp falls on, then lets the last notes ring out. The result is
+// interleaved stereo.
+pub fn render_messages(engine: &mut AudioEngine, messages: &[(Duration, u8, MidiMessage)]) -> Result<Vec<f32>> {
+    let sample_rate = engine.sample_rate() as f64;
+    let mut samples = Vec::new();
+
+    for (time, channel, message) in messages {
+        let target = (time.as_secs_f64() * sample_rate).round() as usize * CHANNELS;
+        let len = target.saturating_sub(samples.len());
+        if len > 0 {
+            render_block(engine, &mut samples, len)?;
+        }
+        engine.handle_midi_message(*channel, message)?;
+    }
+
+    let max_tail = (MAX_TAIL_SECONDS as f64 * sample_rate) as usize * CHANNELS;
+    let mut tail = 0;
+    while tail < max_tail {
+        render_block(engine, &mut samples, BLOCK_SIZE)?;
+        tail += BLOCK_SIZE;
+        // Wait for reverb and delay tails as well as the voices themselves
+        let peak = samples[samples.len() - BLOCK_SIZE..].iter().fold(0.0f32, |peak, s| peak.max(s.abs()));
+        if engine.active_voices() == Some(0) && peak < SILENCE_THRESHOLD {
+            break;
+        }
+    }
+
+    Ok(samples)
+}
+
+fn render_block(engine: &mut AudioEngine, samples: &mut Vec<f32>, len: usize) -> Result<()> {
+    let start = samples.len();
+    samples.resize(start + len, 0.0);
+    if !engine.render(&mut samples[start..]) {
+        return Err(anyhow!("Audio engine is not in offline mode"));
+    }
+    Ok(())
+}
+
+pub fn write_wav(path: &Path, samples: &[f32], sample_rate: u32, bit_depth: u16) -> Result<()> {
+    let sample_format = match bit_depth {
+        16 | 24 => hound::SampleFormat::Int,
+        32 => hound::SampleFormat::Float,
+        other => return Err(anyhow!("Unsupported bit depth: {} (use 16, 24 or 32)", other)),
+    };
+    let spec = hound::WavSpec {
+        channels: CHANNELS as u16,
+        sample_rate,
+        bits_per_sample: bit_depth,
+        sample_format,
+    };
+
+    let mut writer = hound::WavWriter::create(path, spec)?;
+    match bit_depth {
+        16 => {
+            for &sample in samples {
+                writer.write_sample((sample.clamp(-1.0, 1.0) * i16::MAX as f32) as i16)?;
+            }
+        }
+        24 => {
+            let max = ((1 << 23) - 1) as f32;
+            for &sample in samples {
+                writer.write_sample((sample.clamp(-1.0, 1.0) * max) as i32)?;
+            }
+        }
+        _ => {
+            for &sample in samples {
+                writer.write_sample(sample)?;
+            }
+        }
+    }
+    writer.finalize()?;
+    Ok(())
+}
+
+fn has_extension(path: &Path, extensions: &[&str]) -> bool {
+    path.extension()
+        .and_then(|ext| ext.to_str())
+        .is_some_and(|ext| extensions.iter().any(|e| ext.eq_ignore_ascii_case(e)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+
+    fn note(seconds: f32, message: MidiMessage) -> (Duration, u8, MidiMessage) {
+        (Duration::from_secs_f32(seconds), 0, message)
+    }
+
+    #[test]
+    fn test_render_messages_places_notes_and_rings_out() {
+        let config = Config::default().audio;
+        let mut engine = AudioEngine::offline(&config);
+        let messages = vec![
+            note(0.5, MidiMessage::NoteOn { key: 60.into(), vel: 100.into() }),
+            note(1.0, MidiMessage::NoteOff { key: 60.into(), vel: 0.into() }),
+        ];
+
+        let samples = render_messages(&mut engine, &messages).unwrap();
+        let sample_rate = config.sample_rate as usize * CHANNELS;
+
+        // Nothing before the first note, sound while it is held
+        assert!(samples[..sample_rate / 2].iter().all(|s| *s == 0.0));
+        assert!(samples[sample_rate / 2..sample_rate].iter().any(|s| s.abs() > 0.01));
+        // The release tail is rendered, but stops once the voice is done
+        assert!(samples.len() > sample_rate);
+        assert!(samples.len() < sample_rate + (MAX_TAIL_SECONDS as usize) * sample_rate);
+        assert_eq!(engine.active_voices(), Some(0));
+    }
+
+    #[test]
+    fn test_write_wav_round_trip() {
+        let samples: Vec<f32> = (0..100).map(|i| (i as f32 / 50.0) - 1.0).collect();
+
+        for bit_depth in [16, 24, 32] {
+            let path = std::env::temp_dir().join(format!("terminal-piano-render-{}-{}.wav", std::process::id(), bit_depth));
+            write_wav(&path, &samples, 22050, bit_depth).unwrap();
+
+            let mut reader = hound::WavReader::open(&path).unwrap();
+            let spec = reader.spec();
+            assert_eq!(spec.sample_rate, 22050);
+            assert_eq!(spec.bits_per_sample, bit_depth);
+            let read: Vec<f32> = match bit_depth {
+                32 => reader.samples::<f32>().map(|s| s.unwrap()).collect(),
+                _ => {
+                    let max = (1i32 << (bit_depth - 1)) as f32;
+                    reader.samples::<i32>().map(|s| s.unwrap() as f32 / max).collect()
+                }
+            };
+            std::fs::remove_file(&path).ok();
+
+            assert_eq!(read.len(), samples.len());
+            for (a, b) in read.iter().zip(&samples) {
+                assert!((a - b).abs() < 0.001);
+            }
+        }
+
+        assert!(write_wav(Path::new("unused.wav"), &samples, 22050, 8).is_err());
+    }
+}
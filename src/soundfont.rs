@@ -0,0 +1,677 @@
+use anyhow::{anyhow, Result};
+use std::path::Path;
+use std::sync::Arc;
+
+use crate::config::EnvelopeConfig;
+use crate::instruments::{Generator, Instrument, NoteParams};
+use crate::tuning::standard_frequency;
+
+// Generator operators from the SF2 2.01 spec that the player understands
+const GEN_START_OFFSET: usize = 0;
+const GEN_END_OFFSET: usize = 1;
+const GEN_START_LOOP_OFFSET: usize = 2;
+const GEN_END_LOOP_OFFSET: usize = 3;
+const GEN_START_COARSE_OFFSET: usize = 4;
+const GEN_END_COARSE_OFFSET: usize = 12;
+const GEN_PAN: usize = 17;
+const GEN_ATTACK_VOL_ENV: usize = 34;
+const GEN_HOLD_VOL_ENV: usize = 35;
+const GEN_DECAY_VOL_ENV: usize = 36;
+const GEN_SUSTAIN_VOL_ENV: usize = 37;
+const GEN_RELEASE_VOL_ENV: usize = 38;
+const GEN_INSTRUMENT: usize = 41;
+const GEN_KEY_RANGE: usize = 43;
+const GEN_VEL_RANGE: usize = 44;
+const GEN_START_LOOP_COARSE_OFFSET: usize = 45;
+const GEN_INITIAL_ATTENUATION: usize = 48;
+const GEN_END_LOOP_COARSE_OFFSET: usize = 50;
+const GEN_COARSE_TUNE: usize = 51;
+const GEN_FINE_TUNE: usize = 52;
+const GEN_SAMPLE_ID: usize = 53;
+const GEN_SAMPLE_MODES: usize = 54;
+const GEN_OVERRIDING_ROOT_KEY: usize = 58;
+const GEN_COUNT: usize = 61;
+
+// Generators that a preset zone adds on top of the instrument's value
+const ADDITIVE_GENERATORS: [usize; 9] = [
+    GEN_PAN,
+    GEN_ATTACK_VOL_ENV,
+    GEN_HOLD_VOL_ENV,
+    GEN_DECAY_VOL_ENV,
+    GEN_SUSTAIN_VOL_ENV,
+    GEN_RELEASE_VOL_ENV,
+    GEN_INITIAL_ATTENUATION,
+    GEN_COARSE_TUNE,
+    GEN_FINE_TUNE,
+];
+
+// Envelope times are stored in timecents; -12000 is the spec's "instant"
+const DEFAULT_ENVELOPE_TIMECENTS: i16 = -12000;
+
+#[derive(Debug, Clone)]
+pub struct SampleZone {
+    pub key_range: (u8, u8),
+    pub velocity_range: (u8, u8),
+    pub start: usize,
+    pub end: usize,
+    pub loop_start: usize,
+    pub loop_end: usize,
+    pub looping: bool,
+    // Sample modes 3: loop while held, then play the rest of the sample
+    pub loop_until_release: bool,
+    pub sample_rate: u32,
+    pub root_key: u8,
+    pub tune_cents: f32,
+    pub gain: f32,
+    // -1.0 is hard left, 1.0 hard right
+    pub pan: f32,
+    pub envelope: EnvelopeConfig,
+}
+
+impl SampleZone {
+    pub fn contains(&self, midi_note: u8, velocity: u8) -> bool {
+        (self.key_range.0..=self.key_range.1).contains(&midi_note)
+            && (self.velocity_range.0..=self.velocity_range.1).contains(&velocity)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Preset {
+    pub name: String,
+    pub bank: u16,
+    pub program: u16,
+    pub zones: Vec<SampleZone>,
+}
+
+// A single preset ready to be played, together with the sample data it uses
+#[derive(Debug)]
+pub struct SamplePatch {
+    pub name: String,
+    zones: Vec<SampleZone>,
+    samples: Arc<Vec<i16>>,
+}
+
+impl SamplePatch {
+    pub fn zone_for(&self, midi_note: u8, velocity: u8) -> Option<&SampleZone> {
+        self.zones.iter().find(|zone| zone.contains(midi_note, velocity))
+    }
+
+    pub fn oscillator(&self, zone: &SampleZone, frequency_ratio: f64, output_rate: u32) -> SampleOscillator {
+        SampleOscillator {
+            samples: self.samples.clone(),
+            gain: zone.gain,
+            envelope: zone.envelope,
+            pan: zone.pan,
+            position: zone.start as f64,
+            increment: frequency_ratio * zone.sample_rate as f64 / output_rate as f64,
+            end: zone.end,
+            loop_start: zone.loop_start,
+            loop_end: zone.loop_end,
+            looping: zone.looping && zone.loop_end > zone.loop_start,
+            loop_until_release: zone.loop_until_release,
+            finished: false,
+        }
+    }
+}
+
+impl Instrument for SamplePatch {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn default_envelope(&self) -> EnvelopeConfig {
+        // Only used for zones without their own envelope, which never happens
+        // since every zone gets the spec defaults
+        EnvelopeConfig::default()
+    }
+
+    fn start_note(&self, note: &NoteParams) -> Option<Box<dyn Generator>> {
+        let zone = self.zone_for(note.midi_note, note.velocity)?;
+        // The sample was recorded at standard pitch for its root key
+        let root_frequency = standard_frequency(zone.root_key) as f64;
+        let frequency_ratio = note.frequency as f64 / root_frequency * 2.0_f64.powf(zone.tune_cents as f64 / 1200.0);
+        Some(Box::new(self.oscillator(zone, frequency_ratio, note.sample_rate)))
+    }
+}
+
+// Plays one zone's sample at a given pitch with linear interpolation
+#[derive(Debug, Clone)]
+pub struct SampleOscillator {
+    samples: Arc<Vec<i16>>,
+    gain: f32,
+    envelope: EnvelopeConfig,
+    pan: f32,
+    position: f64,
+    increment: f64,
+    end: usize,
+    loop_start: usize,
+    loop_end: usize,
+    looping: bool,
+    loop_until_release: bool,
+    finished: bool,
+}
+
+impl Generator for SampleOscillator {
+    fn next_sample(&mut self) -> f32 {
+        if self.finished {
+            return 0.0;
+        }
+
+        let index = self.position as usize;
+        let fraction = (self.position - index as f64) as f32;
+        let read = |i: usize| self.samples.get(i).copied().unwrap_or(0) as f32 / 32768.0;
+        let value = read(index) + (read(index + 1) - read(index)) * fraction;
+
+        self.position += self.increment;
+        if self.looping && self.position >= self.loop_end as f64 {
+            self.position -= (self.loop_end - self.loop_start) as f64;
+        } else if self.position >= self.end as f64 {
+            self.finished = true;
+        }
+
+        value * self.gain
+    }
+
+    fn release(&mut self) {
+        if self.loop_until_release {
+            self.looping = false;
+        }
+    }
+
+    fn is_finished(&self) -> bool {
+        self.finished
+    }
+
+    fn envelope(&self) -> Option<EnvelopeConfig> {
+        Some(self.envelope)
+    }
+
+    // Zones that leave pan at the centre get the usual key-position spread
+    fn pan(&self) -> Option<f32> {
+        (self.pan != 0.0).then_some(self.pan)
+    }
+}
+
+#[derive(Debug)]
+pub struct SoundFont {
+    pub name: String,
+    pub presets: Vec<Preset>,
+    samples: Arc<Vec<i16>>,
+}
+
+impl SoundFont {
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let data = std::fs::read(path)?;
+        Self::parse(&data)
+    }
+
+    pub fn parse(data: &[u8]) -> Result<Self> {
+        let mut reader = ChunkReader::new(data);
+        let riff = reader.next_chunk()?.ok_or_else(|| anyhow!("Empty SoundFont file"))?;
+        if riff.id != *b"RIFF" || riff.data.len() < 4 || &riff.data[..4] != b"sfbk" {
+            return Err(anyhow!("Not a SoundFont 2 file"));
+        }
+
+        let mut name = String::new();
+        let mut samples = None;
+        let mut pdta = None;
+
+        let mut lists = ChunkReader::new(&riff.data[4..]);
+        while let Some(list) = lists.next_chunk()? {
+            if list.id != *b"LIST" || list.data.len() < 4 {
+                continue;
+            }
+            let mut chunks = ChunkReader::new(&list.data[4..]);
+            match &list.data[..4] {
+                b"INFO" => {
+                    while let Some(chunk) = chunks.next_chunk()? {
+                        if chunk.id == *b"INAM" {
+                            name = read_name(chunk.data);
+                        }
+                    }
+                }
+                b"sdta" => {
+                    while let Some(chunk) = chunks.next_chunk()? {
+                        if chunk.id == *b"smpl" {
+                            samples = Some(
+                                chunk.data
+                                    .chunks_exact(2)
+                                    .map(|b| i16::from_le_bytes([b[0], b[1]]))
+                                    .collect::<Vec<_>>(),
+                            );
+                        }
+                    }
+                }
+                b"pdta" => {
+                    pdta = Some(list.data);
+                }
+                _ => {}
+            }
+        }
+
+        let samples = samples.ok_or_else(|| anyhow!("SoundFont has no sample data"))?;
+        let pdta = pdta.ok_or_else(|| anyhow!("SoundFont has no preset data"))?;
+        let presets = parse_presets(&pdta[4..], samples.len())?;
+        if presets.is_empty() {
+            return Err(anyhow!("SoundFont contains no presets"));
+        }
+
+        Ok(Self {
+            name,
+            presets,
+            samples: Arc::new(samples),
+        })
+    }
+
+    // Falls back to the first preset when the bank/program pair is missing
+    pub fn patch(&self, bank: u16, program: u16) -> SamplePatch {
+        self.preset(bank, program)
+            .unwrap_or_else(|| self.patch_for(&self.presets[0]))
+    }
+
+    pub fn preset(&self, bank: u16, program: u16) -> Option<SamplePatch> {
+        self.presets
+            .iter()
+            .find(|p| p.bank == bank && p.program == program)
+            .map(|preset| self.patch_for(preset))
+    }
+
+    fn patch_for(&self, preset: &Preset) -> SamplePatch {
+        SamplePatch {
+            name: preset.name.clone(),
+            zones: preset.zones.clone(),
+            samples: self.samples.clone(),
+        }
+    }
+}
+
+struct Chunk<'a> {
+    id: [u8; 4],
+    data: &'a [u8],
+}
+
+struct ChunkReader<'a> {
+    data: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> ChunkReader<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Self { data, offset: 0 }
+    }
+
+    fn next_chunk(&mut self) -> Result<Option<Chunk<'a>>> {
+        if self.offset + 8 > self.data.len() {
+            return Ok(None);
+        }
+        let header = &self.data[self.offset..self.offset + 8];
+        let id = [header[0], header[1], header[2], header[3]];
+        let size = u32::from_le_bytes([header[4], header[5], header[6], header[7]]) as usize;
+        let start = self.offset + 8;
+        let end = start.checked_add(size)
+            .filter(|&end| end <= self.data.len())
+            .ok_or_else(|| anyhow!("Truncated SoundFont chunk {}", String::from_utf8_lossy(&id)))?;
+
+        // Chunks are padded to an even length
+        self.offset = end + (size & 1);
+        Ok(Some(Chunk { id, data: &self.data[start..end] }))
+    }
+}
+
+fn read_name(data: &[u8]) -> String {
+    let end = data.iter().position(|&b| b == 0).unwrap_or(data.len());
+    String::from_utf8_lossy(&data[..end]).trim().to_string()
+}
+
+fn read_u16(data: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes([data[offset], data[offset + 1]])
+}
+
+fn read_u32(data: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes([data[offset], data[offset + 1], data[offset + 2], data[offset + 3]])
+}
+
+fn records<'a>(chunk: Option<&'a [u8]>, name: &str, size: usize) -> Result<Vec<&'a [u8]>> {
+    let data = chunk.ok_or_else(|| anyhow!("SoundFont is missing the {} chunk", name))?;
+    Ok(data.chunks_exact(size).collect())
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Generators {
+    values: [Option<i16>; GEN_COUNT],
+}
+
+impl Generators {
+    fn new() -> Self {
+        Self { values: [None; GEN_COUNT] }
+    }
+
+    fn set(&mut self, operator: u16, amount: i16) {
+        if let Some(value) = self.values.get_mut(operator as usize) {
+            *value = Some(amount);
+        }
+    }
+
+    fn get(&self, operator: usize) -> Option<i16> {
+        self.values[operator]
+    }
+
+    fn value(&self, operator: usize, default: i16) -> i32 {
+        self.get(operator).unwrap_or(default) as i32
+    }
+
+    fn range(&self, operator: usize) -> (u8, u8) {
+        match self.get(operator) {
+            Some(amount) => {
+                let amount = amount as u16;
+                ((amount & 0xFF) as u8, (amount >> 8) as u8)
+            }
+            None => (0, 127),
+        }
+    }
+
+    // Local zone values replace the global zone's
+    fn overlay(&self, local: &Generators) -> Generators {
+        let mut merged = *self;
+        for (operator, value) in local.values.iter().enumerate() {
+            if value.is_some() {
+                merged.values[operator] = *value;
+            }
+        }
+        merged
+    }
+}
+
+// Splits a bag list into zones of generators, separating out the global zone
+fn zone_generators(bags: &[&[u8]], gens: &[&[u8]], first_bag: usize, last_bag: usize, link: usize) -> (Generators, Vec<Generators>) {
+    let mut global = Generators::new();
+    let mut zones = Vec::new();
+
+    for bag_index in first_bag..last_bag.min(bags.len().saturating_sub(1)) {
+        let first_gen = read_u16(bags[bag_index], 0) as usize;
+        let last_gen = read_u16(bags[bag_index + 1], 0) as usize;
+
+        let mut generators = Generators::new();
+        for gen in gens.iter().take(last_gen.min(gens.len())).skip(first_gen) {
+            generators.set(read_u16(gen, 0), read_u16(gen, 2) as i16);
+        }
+
+        if generators.get(link).is_some() {
+            zones.push(generators);
+        } else if bag_index == first_bag {
+            global = generators;
+        }
+    }
+
+    (global, zones)
+}
+
+fn timecents_to_seconds(timecents: i32) -> f32 {
+    2.0_f32.powf(timecents as f32 / 1200.0)
+}
+
+fn centibels_to_gain(centibels: i32) -> f32 {
+    10.0_f32.powf(-(centibels.max(0) as f32) / 200.0)
+}
+
+fn parse_presets(pdta: &[u8], sample_count: usize) -> Result<Vec<Preset>> {
+    let mut chunks = std::collections::HashMap::new();
+    let mut reader = ChunkReader::new(pdta);
+    while let Some(chunk) = reader.next_chunk()? {
+        chunks.insert(chunk.id, chunk.data);
+    }
+
+    let phdr = records(chunks.get(b"phdr").copied(), "phdr", 38)?;
+    let pbag = records(chunks.get(b"pbag").copied(), "pbag", 4)?;
+    let pgen = records(chunks.get(b"pgen").copied(), "pgen", 4)?;
+    let inst = records(chunks.get(b"inst").copied(), "inst", 22)?;
+    let ibag = records(chunks.get(b"ibag").copied(), "ibag", 4)?;
+    let igen = records(chunks.get(b"igen").copied(), "igen", 4)?;
+    let shdr = records(chunks.get(b"shdr").copied(), "shdr", 46)?;
+
+    let mut presets = Vec::new();
+
+    // The last header of each list is a terminator that only marks the end
+    for pair in phdr.windows(2) {
+        let (header, next) = (pair[0], pair[1]);
+        let (preset_global, preset_zones) = zone_generators(
+            &pbag,
+            &pgen,
+            read_u16(header, 24) as usize,
+            read_u16(next, 24) as usize,
+            GEN_INSTRUMENT,
+        );
+
+        let mut zones = Vec::new();
+        for preset_zone in preset_zones {
+            let preset_gens = preset_global.overlay(&preset_zone);
+            let instrument_index = preset_gens.value(GEN_INSTRUMENT, 0) as usize;
+            if instrument_index + 1 >= inst.len() {
+                continue;
+            }
+
+            let (instrument_global, instrument_zones) = zone_generators(
+                &ibag,
+                &igen,
+                read_u16(inst[instrument_index], 20) as usize,
+                read_u16(inst[instrument_index + 1], 20) as usize,
+                GEN_SAMPLE_ID,
+            );
+
+            for instrument_zone in instrument_zones {
+                let mut gens = instrument_global.overlay(&instrument_zone);
+                for &operator in &ADDITIVE_GENERATORS {
+                    if let Some(offset) = preset_gens.get(operator) {
+                        let base = gens.value(operator, default_generator(operator));
+                        gens.values[operator] = Some((base + offset as i32).clamp(i16::MIN as i32, i16::MAX as i32) as i16);
+                    }
+                }
+
+                let sample_index = gens.value(GEN_SAMPLE_ID, 0) as usize;
+                if sample_index + 1 >= shdr.len() {
+                    continue;
+                }
+                if let Some(zone) = build_zone(&gens, &preset_gens, shdr[sample_index], sample_count) {
+                    zones.push(zone);
+                }
+            }
+        }
+
+        presets.push(Preset {
+            name: read_name(&header[..20]),
+            program: read_u16(header, 20),
+            bank: read_u16(header, 22),
+            zones,
+        });
+    }
+
+    Ok(presets)
+}
+
+fn default_generator(operator: usize) -> i16 {
+    match operator {
+        GEN_ATTACK_VOL_ENV | GEN_HOLD_VOL_ENV | GEN_DECAY_VOL_ENV | GEN_RELEASE_VOL_ENV => DEFAULT_ENVELOPE_TIMECENTS,
+        _ => 0,
+    }
+}
+
+fn build_zone(gens: &Generators, preset_gens: &Generators, sample: &[u8], sample_count: usize) -> Option<SampleZone> {
+    let offset = |fine: usize, coarse: usize| gens.value(fine, 0) as i64 + gens.value(coarse, 0) as i64 * 32768;
+    let address = |base: u32, delta: i64| (base as i64 + delta).clamp(0, sample_count as i64) as usize;
+
+    let start = address(read_u32(sample, 20), offset(GEN_START_OFFSET, GEN_START_COARSE_OFFSET));
+    let end = address(read_u32(sample, 24), offset(GEN_END_OFFSET, GEN_END_COARSE_OFFSET));
+    let loop_start = address(read_u32(sample, 28), offset(GEN_START_LOOP_OFFSET, GEN_START_LOOP_COARSE_OFFSET));
+    let loop_end = address(read_u32(sample, 32), offset(GEN_END_LOOP_OFFSET, GEN_END_LOOP_COARSE_OFFSET));
+    let sample_rate = read_u32(sample, 36);
+    if end <= start || sample_rate == 0 {
+        return None;
+    }
+
+    let original_pitch = sample[40];
+    let pitch_correction = sample[41] as i8;
+    let root_key = match gens.get(GEN_OVERRIDING_ROOT_KEY) {
+        Some(key) if (0..=127).contains(&key) => key as u8,
+        _ if original_pitch <= 127 => original_pitch,
+        _ => 60,
+    };
+
+    let sample_mode = gens.value(GEN_SAMPLE_MODES, 0) & 3;
+    let intersect = |a: (u8, u8), b: (u8, u8)| (a.0.max(b.0), a.1.min(b.1));
+
+    // Hold is folded into the attack since the synth envelope has no hold stage
+    let attack = timecents_to_seconds(gens.value(GEN_ATTACK_VOL_ENV, DEFAULT_ENVELOPE_TIMECENTS))
+        + timecents_to_seconds(gens.value(GEN_HOLD_VOL_ENV, DEFAULT_ENVELOPE_TIMECENTS));
+
+    Some(SampleZone {
+        key_range: intersect(gens.range(GEN_KEY_RANGE), preset_gens.range(GEN_KEY_RANGE)),
+        velocity_range: intersect(gens.range(GEN_VEL_RANGE), preset_gens.range(GEN_VEL_RANGE)),
+        start,
+        end,
+        loop_start,
+        loop_end,
+        looping: sample_mode == 1 || sample_mode == 3,
+        loop_until_release: sample_mode == 3,
+        sample_rate,
+        root_key,
+        tune_cents: (gens.value(GEN_COARSE_TUNE, 0) * 100 + gens.value(GEN_FINE_TUNE, 0) + pitch_correction as i32) as f32,
+        gain: centibels_to_gain(gens.value(GEN_INITIAL_ATTENUATION, 0)),
+        pan: (gens.value(GEN_PAN, 0) as f32 / 500.0).clamp(-1.0, 1.0),
+        envelope: EnvelopeConfig {
+            attack,
+            decay: timecents_to_seconds(gens.value(GEN_DECAY_VOL_ENV, DEFAULT_ENVELOPE_TIMECENTS)),
+            sustain: centibels_to_gain(gens.value(GEN_SUSTAIN_VOL_ENV, 0)),
+            release: timecents_to_seconds(gens.value(GEN_RELEASE_VOL_ENV, DEFAULT_ENVELOPE_TIMECENTS)),
+        },
+    })
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+
+    fn chunk(id: &[u8; 4], data: &[u8]) -> Vec<u8> {
+        let mut out = id.to_vec();
+        out.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        out.extend_from_slice(data);
+        if data.len() % 2 == 1 {
+            out.push(0);
+        }
+        out
+    }
+
+    fn list(kind: &[u8; 4], chunks: &[Vec<u8>]) -> Vec<u8> {
+        let mut data = kind.to_vec();
+        for c in chunks {
+            data.extend_from_slice(c);
+        }
+        chunk(b"LIST", &data)
+    }
+
+    fn name(text: &str) -> Vec<u8> {
+        let mut bytes = text.as_bytes().to_vec();
+        bytes.resize(20, 0);
+        bytes
+    }
+
+    fn generator(operator: u16, amount: u16) -> Vec<u8> {
+        [operator.to_le_bytes(), amount.to_le_bytes()].concat()
+    }
+
+    fn bag(first_gen: u16) -> Vec<u8> {
+        [first_gen.to_le_bytes(), 0u16.to_le_bytes()].concat()
+    }
+
+    fn preset_header(text: &str, program: u16, bank: u16, first_bag: u16) -> Vec<u8> {
+        let mut out = name(text);
+        out.extend_from_slice(&program.to_le_bytes());
+        out.extend_from_slice(&bank.to_le_bytes());
+        out.extend_from_slice(&first_bag.to_le_bytes());
+        out.extend_from_slice(&[0; 12]);
+        out
+    }
+
+    fn instrument_header(text: &str, first_bag: u16) -> Vec<u8> {
+        let mut out = name(text);
+        out.extend_from_slice(&first_bag.to_le_bytes());
+        out
+    }
+
+    fn sample_header(text: &str, start: u32, end: u32, loop_start: u32, loop_end: u32, root: u8) -> Vec<u8> {
+        let mut out = name(text);
+        for value in [start, end, loop_start, loop_end, 44100] {
+            out.extend_from_slice(&value.to_le_bytes());
+        }
+        out.extend_from_slice(&[root, 0, 0, 0, 1, 0]);
+        out
+    }
+
+    // A one-preset font whose only sample is a looped 441 Hz sine wave
+    pub fn build_test_sound_font() -> Vec<u8> {
+        let period = 100;
+        let samples: Vec<u8> = (0..period * 10)
+            .flat_map(|i| {
+                let value = (2.0 * std::f32::consts::PI * i as f32 / period as f32).sin();
+                ((value * 16000.0) as i16).to_le_bytes()
+            })
+            .collect();
+
+        let pdta = list(b"pdta", &[
+            chunk(b"phdr", &[preset_header("Test Sine", 0, 0, 0), preset_header("EOP", 0, 0, 1)].concat()),
+            chunk(b"pbag", &[bag(0), bag(1)].concat()),
+            chunk(b"pmod", &[0; 10]),
+            chunk(b"pgen", &[generator(GEN_INSTRUMENT as u16, 0), generator(0, 0)].concat()),
+            chunk(b"inst", &[instrument_header("Sine", 0), instrument_header("EOI", 1)].concat()),
+            chunk(b"ibag", &[bag(0), bag(3)].concat()),
+            chunk(b"imod", &[0; 10]),
+            chunk(b"igen", &[
+                generator(GEN_KEY_RANGE as u16, 0x7F00),
+                generator(GEN_SAMPLE_MODES as u16, 1),
+                generator(GEN_SAMPLE_ID as u16, 0),
+                generator(0, 0),
+            ].concat()),
+            chunk(b"shdr", &[
+                sample_header("Sine", 0, 1000, 100, 900, 69),
+                sample_header("EOS", 0, 0, 0, 0, 0),
+            ].concat()),
+        ]);
+
+        let mut body = b"sfbk".to_vec();
+        body.extend(list(b"INFO", &[chunk(b"INAM", b"Test Font\0")]));
+        body.extend(list(b"sdta", &[chunk(b"smpl", &samples)]));
+        body.extend(pdta);
+        chunk(b"RIFF", &body)
+    }
+
+    #[test]
+    fn test_parse_sound_font() {
+        let font = SoundFont::parse(&build_test_sound_font()).unwrap();
+        assert_eq!(font.name, "Test Font");
+        assert_eq!(font.presets.len(), 1);
+        assert_eq!(font.presets[0].name, "Test Sine");
+
+        let patch = font.patch(0, 0);
+        let zone = patch.zone_for(60, 100).unwrap();
+        assert_eq!(zone.root_key, 69);
+        assert_eq!((zone.loop_start, zone.loop_end), (100, 900));
+        assert!(zone.looping);
+    }
+
+    #[test]
+    fn test_rejects_invalid_data() {
+        assert!(SoundFont::parse(b"not a soundfont").is_err());
+        assert!(SoundFont::parse(&chunk(b"RIFF", b"WAVEfmt ")).is_err());
+    }
+
+    #[test]
+    fn test_looped_sample_keeps_playing() {
+        let font = SoundFont::parse(&build_test_sound_font()).unwrap();
+        let patch = font.patch(0, 0);
+        let zone = patch.zone_for(69, 100).unwrap().clone();
+        let mut oscillator = patch.oscillator(&zone, 1.0, 44100);
+
+        let peak = (0..44100).map(|_| oscillator.next_sample().abs()).fold(0.0, f32::max);
+        assert!(!oscillator.is_finished());
+        assert!(peak > 0.4);
+    }
+}
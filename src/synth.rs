@@ -0,0 +1,1102 @@
+use rodio::Source;
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::audio_effects::EffectsChain;
+use crate::config::{EffectsConfig, EnvelopeConfig, MetronomeConfig, RetriggerMode, VelocityCurve, VoiceStealing};
+use crate::instruments::{Generator, Instrument, NoteParams, Sine};
+use crate::metronome::Metronome;
+use crate::tuning::Tuning;
+
+const VOICE_GAIN: f32 = 0.3;
+// Middle of the 88-key range (A0 to C8) and the distance to either end
+const KEYBOARD_CENTER: f32 = 64.5;
+const KEYBOARD_HALF_SPAN: f32 = 43.5;
+// How long a crossfaded retrigger takes to fade out the old voice
+const CROSSFADE_SECONDS: f32 = 0.03;
+// Stolen voices fade this fast instead of being cut, which would click
+const STEAL_FADE_SECONDS: f32 = 0.005;
+// Below this level a releasing voice is inaudible and can be dropped
+const SILENCE_THRESHOLD: f32 = 1.0e-4;
+// How much of a note's strength is left with the soft pedal down
+const SOFT_PEDAL_STRENGTH: f32 = 0.65;
+// Per-sample smoothing of channel volume changes, so CC7/CC11 steps don't click
+const CHANNEL_GAIN_SMOOTHING: f32 = 0.005;
+
+#[derive(Debug, Clone)]
+pub enum SynthCommand {
+    // Notes on different MIDI channels never release or retrigger each other
+    NoteOn { channel: u8, midi_note: u8, velocity: u8 },
+    NoteOff { channel: u8, midi_note: u8 },
+    AllNotesOff,
+    SetVolume(f32),
+    SetEnvelope(EnvelopeConfig),
+    SetVelocityCurve { curve: VelocityCurve, fixed_velocity: u8 },
+    SetInstrument(Arc<dyn Instrument>),
+    // Gives a MIDI channel its own instrument; `None` goes back to the
+    // synth-wide one
+    SetChannelPatch { channel: u8, patch: Option<ChannelPatch> },
+    // Clears every channel's patch and pan, e.g. when a new file is loaded
+    ResetChannels,
+    SustainPedal { channel: u8, pressed: bool },
+    // Una corda: notes started while it is down play softer
+    SoftPedal { channel: u8, pressed: bool },
+    // Channel volume (CC7) and expression (CC11), both 0-127
+    SetChannelVolume { channel: u8, volume: u8 },
+    SetExpression { channel: u8, expression: u8 },
+    // From -1.0 to 1.0 of the channel's bend range; applies to sounding notes
+    PitchBend { channel: u8, bend: f32 },
+    // Bend range in semitones for channels that haven't set their own
+    SetPitchBendRange(f32),
+    // A channel's own bend range, as set through RPN 0
+    SetChannelBendRange { channel: u8, semitones: f32 },
+    SetRetrigger(RetriggerMode),
+    SetPolyphony { max_voices: usize, stealing: VoiceStealing },
+    SetEffects(EffectsConfig),
+    // 0.0 plays every note in the centre, 1.0 spreads the keyboard from
+    // hard left to hard right
+    SetStereoWidth(f32),
+    // A pan position from -1.0 (left) to 1.0 (right) for notes started on
+    // `channel` from now on, in place of their key position. `None` goes
+    // back to key panning.
+    SetPan { channel: u8, pan: Option<f32> },
+    SetTuning(Arc<Tuning>),
+    SetMetronome(MetronomeConfig),
+    MetronomeEnabled(bool),
+    // Tempo in quarter notes per minute, beats per bar and beat unit of a
+    // playing MIDI file, or `None` for the metronome's own settings
+    LockMetronome(Option<(f32, u8, u8)>),
+    // Beat position of the playing MIDI file, to keep the clicks on its beats
+    SyncMetronome(f64),
+    // Applies `command` when the synth's clock reaches `frame`, or straight
+    // away if it already has
+    Scheduled { frame: u64, command: Box<SynthCommand> },
+    // Drops every scheduled command that hasn't been applied yet
+    CancelScheduled,
+}
+
+// An instrument with the envelope and retrigger mode it plays with
+#[derive(Debug, Clone)]
+pub struct ChannelPatch {
+    pub instrument: Arc<dyn Instrument>,
+    pub envelope: EnvelopeConfig,
+    pub retrigger: RetriggerMode,
+}
+
+// Settings that MIDI messages change for one channel only
+#[derive(Debug, Clone)]
+struct Channel {
+    patch: Option<ChannelPatch>,
+    pan: Option<f32>,
+    volume: u8,
+    expression: u8,
+    sustain: bool,
+    soft: bool,
+    bend: f32,
+    bend_range: Option<f32>,
+}
+
+impl Default for Channel {
+    fn default() -> Self {
+        Self {
+            patch: None,
+            pan: None,
+            // The General MIDI power-on values
+            volume: 100,
+            expression: 127,
+            sustain: false,
+            soft: false,
+            bend: 0.0,
+            bend_range: None,
+        }
+    }
+}
+
+impl Channel {
+    // Squared so the controllers follow loudness, scaled so the General MIDI
+    // default volume plays at unity gain
+    fn gain(&self) -> f32 {
+        let level = self.volume as f32 * self.expression as f32 / (100.0 * 127.0);
+        level * level
+    }
+
+    fn pitch_ratio(&self, default_range: f32) -> f32 {
+        if self.bend == 0.0 {
+            return 1.0;
+        }
+        2.0_f32.powf(self.bend * self.bend_range.unwrap_or(default_range) / 12.0)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EnvelopeStage {
+    Attack,
+    Decay,
+    Sustain,
+    Release,
+    Finished,
+}
+
+// Linear ADSR envelope advanced one sample at a time. Stage lengths are
+// converted to per-sample steps up front so `next_level` stays cheap.
+#[derive(Debug, Clone)]
+pub struct Envelope {
+    stage: EnvelopeStage,
+    level: f32,
+    attack_step: f32,
+    decay_step: f32,
+    sustain: f32,
+    release_seconds: f32,
+    release_step: f32,
+    sample_rate: f32,
+}
+
+impl Envelope {
+    pub fn new(config: &EnvelopeConfig, sample_rate: u32) -> Self {
+        let sample_rate = sample_rate as f32;
+        let sustain = config.sustain.clamp(0.0, 1.0);
+        let step = |seconds: f32, distance: f32| {
+            if seconds <= 0.0 {
+                f32::INFINITY
+            } else {
+                distance / (seconds * sample_rate)
+            }
+        };
+
+        Self {
+            stage: EnvelopeStage::Attack,
+            level: 0.0,
+            attack_step: step(config.attack, 1.0),
+            decay_step: step(config.decay, 1.0 - sustain),
+            sustain,
+            release_seconds: config.release,
+            release_step: 0.0,
+            sample_rate,
+        }
+    }
+
+    pub fn stage(&self) -> EnvelopeStage {
+        self.stage
+    }
+
+    pub fn level(&self) -> f32 {
+        self.level
+    }
+
+    pub fn release(&mut self) {
+        if matches!(self.stage, EnvelopeStage::Release | EnvelopeStage::Finished) {
+            return;
+        }
+        // Release always takes the configured time, whatever level it starts from
+        self.stage = EnvelopeStage::Release;
+        self.release_step = if self.release_seconds <= 0.0 {
+            f32::INFINITY
+        } else {
+            self.level / (self.release_seconds * self.sample_rate)
+        };
+    }
+
+    pub fn next_level(&mut self) -> f32 {
+        match self.stage {
+            EnvelopeStage::Attack => {
+                self.level += self.attack_step;
+                if self.level >= 1.0 {
+                    self.level = 1.0;
+                    self.stage = EnvelopeStage::Decay;
+                }
+            }
+            EnvelopeStage::Decay => {
+                self.level -= self.decay_step;
+                if self.level <= self.sustain {
+                    self.level = self.sustain;
+                    self.stage = EnvelopeStage::Sustain;
+                }
+            }
+            EnvelopeStage::Sustain => {
+                if self.level <= SILENCE_THRESHOLD {
+                    self.level = 0.0;
+                    self.stage = EnvelopeStage::Finished;
+                }
+            }
+            EnvelopeStage::Release => {
+                self.level -= self.release_step;
+                if self.level <= SILENCE_THRESHOLD {
+                    self.level = 0.0;
+                    self.stage = EnvelopeStage::Finished;
+                }
+            }
+            EnvelopeStage::Finished => {}
+        }
+        self.level
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.stage == EnvelopeStage::Finished
+    }
+}
+
+struct Voice {
+    channel: u8,
+    midi_note: u8,
+    // Whether the key is still down; a voice whose key is up but has not been
+    // released is being held by the sustain pedal
+    key_down: bool,
+    generator: Box<dyn Generator>,
+    envelope: Envelope,
+    amplitude: f32,
+    // Sample clock at note-on, used to find the oldest voice
+    started_at: u64,
+    // Left and right gains from the voice's pan position
+    gains: [f32; 2],
+    // Extra gain used to fade a voice out early when it is stolen or crossfaded
+    fade_gain: f32,
+    fade_step: f32,
+    // The channel's volume and expression, eased towards their current value
+    channel_gain: f32,
+    // The last two generator samples and the read position between them.
+    // Under pitch bend the generator is read faster or slower than real time.
+    previous_sample: f32,
+    current_sample: f32,
+    read_position: f32,
+}
+
+impl Voice {
+    fn new(channel: u8, note: &NoteParams, generator: Box<dyn Generator>, envelope: &EnvelopeConfig, started_at: u64, pan: f32, channel_gain: f32) -> Self {
+        let envelope = generator.envelope().unwrap_or(*envelope);
+        Self {
+            channel,
+            midi_note: note.midi_note,
+            key_down: true,
+            generator,
+            envelope: Envelope::new(&envelope, note.sample_rate),
+            // Keep quiet notes audible rather than scaling all the way to zero
+            amplitude: 0.1 + 0.9 * note.strength,
+            started_at,
+            gains: pan_gains(pan),
+            fade_gain: 1.0,
+            fade_step: 0.0,
+            channel_gain,
+            previous_sample: 0.0,
+            current_sample: 0.0,
+            read_position: 1.0,
+        }
+    }
+
+    fn next_frame(&mut self, pitch_ratio: f32, channel_gain: f32) -> [f32; 2] {
+        self.read_position += pitch_ratio;
+        while self.read_position >= 1.0 {
+            self.previous_sample = self.current_sample;
+            self.current_sample = self.generator.next_sample();
+            self.read_position -= 1.0;
+        }
+        let sample = self.previous_sample + (self.current_sample - self.previous_sample) * self.read_position;
+        self.channel_gain += (channel_gain - self.channel_gain) * CHANNEL_GAIN_SMOOTHING;
+
+        let value = sample * self.amplitude * self.envelope.next_level() * self.fade_gain * self.channel_gain;
+        self.fade_gain = (self.fade_gain - self.fade_step).max(0.0);
+        [value * self.gains[0], value * self.gains[1]]
+    }
+
+    fn fade_out(&mut self, seconds: f32, sample_rate: u32) {
+        self.key_down = false;
+        self.fade_step = self.fade_step.max(1.0 / (seconds * sample_rate as f32));
+    }
+
+    fn is_fading(&self) -> bool {
+        self.fade_step > 0.0
+    }
+
+    fn current_level(&self) -> f32 {
+        self.amplitude * self.envelope.level() * self.fade_gain
+    }
+
+    // Lower values are stolen first
+    fn priority(&self) -> u8 {
+        if self.envelope.stage() == EnvelopeStage::Release {
+            0
+        } else if !self.key_down {
+            1
+        } else {
+            2
+        }
+    }
+
+    fn release(&mut self) {
+        self.key_down = false;
+        self.envelope.release();
+        self.generator.release();
+    }
+
+    fn is_finished(&self) -> bool {
+        self.generator.is_finished() || self.envelope.is_finished() || self.fade_gain <= 0.0
+    }
+
+    fn plays(&self, channel: u8, midi_note: u8) -> bool {
+        self.channel == channel && self.midi_note == midi_note
+    }
+}
+
+// Where a key sits in the stereo field, low notes left and high notes right
+// as a player hears a grand piano
+pub fn key_pan(midi_note: u8, width: f32) -> f32 {
+    ((midi_note as f32 - KEYBOARD_CENTER) / KEYBOARD_HALF_SPAN).clamp(-1.0, 1.0) * width.clamp(0.0, 1.0)
+}
+
+// Equal-power pan law, scaled so a centred voice keeps unity gain on both sides
+fn pan_gains(pan: f32) -> [f32; 2] {
+    let angle = (pan.clamp(-1.0, 1.0) + 1.0) * std::f32::consts::FRAC_PI_4;
+    [angle.cos() * std::f32::consts::SQRT_2, angle.sin() * std::f32::consts::SQRT_2]
+}
+
+// Mixes every sounding voice into one stream. All state changes arrive as
+// `SynthCommand`s, so the synth can be driven from the UI thread without
+// locking and rendered deterministically in tests.
+pub struct Synth {
+    sample_rate: u32,
+    voices: Vec<Voice>,
+    volume: f32,
+    envelope: EnvelopeConfig,
+    velocity_curve: VelocityCurve,
+    fixed_velocity: u8,
+    instrument: Arc<dyn Instrument>,
+    tuning: Arc<Tuning>,
+    pitch_bend_range: f32,
+    retrigger: RetriggerMode,
+    max_voices: usize,
+    stealing: VoiceStealing,
+    clock: u64,
+    // `clock` for other threads, to schedule commands against
+    shared_clock: Arc<AtomicU64>,
+    // Commands waiting for their frame, in the order they are due
+    scheduled: VecDeque<(u64, SynthCommand)>,
+    effects: EffectsChain,
+    metronome: Metronome,
+    stereo_width: f32,
+    channels: [Channel; 16],
+    // The right half of the current frame, still to be handed to the output
+    pending_right: Option<f32>,
+    commands: Receiver<SynthCommand>,
+}
+
+impl Synth {
+    pub fn new(sample_rate: u32) -> (Self, Sender<SynthCommand>) {
+        let (sender, receiver) = mpsc::channel();
+        let synth = Self {
+            sample_rate,
+            voices: Vec::new(),
+            volume: 0.7,
+            envelope: EnvelopeConfig::default(),
+            velocity_curve: VelocityCurve::default(),
+            fixed_velocity: 100,
+            instrument: Arc::new(Sine),
+            tuning: Arc::new(Tuning::default()),
+            pitch_bend_range: 2.0,
+            retrigger: RetriggerMode::default(),
+            max_voices: 64,
+            stealing: VoiceStealing::default(),
+            clock: 0,
+            shared_clock: Arc::new(AtomicU64::new(0)),
+            scheduled: VecDeque::new(),
+            // The master bus starts dry; the engine sends the configured effects
+            effects: EffectsChain::new(&EffectsConfig::default().bypassed(), sample_rate),
+            metronome: Metronome::new(sample_rate),
+            stereo_width: 0.0,
+            channels: Default::default(),
+            pending_right: None,
+            commands: receiver,
+        };
+        (synth, sender)
+    }
+
+    // Frames rendered so far, readable while the synth plays on the audio thread
+    pub fn clock(&self) -> Arc<AtomicU64> {
+        self.shared_clock.clone()
+    }
+
+    pub fn active_voices(&self) -> usize {
+        self.voices.len()
+    }
+
+    #[cfg(test)]
+    fn releasing_voices(&self) -> usize {
+        self.voices.iter().filter(|v| v.envelope.stage() == EnvelopeStage::Release).count()
+    }
+
+    pub fn handle_command(&mut self, command: SynthCommand) {
+        match command {
+            SynthCommand::NoteOn { channel, midi_note, velocity } => {
+                // Keys the tuning leaves unmapped are silent
+                let Some(frequency) = self.tuning.frequency(midi_note) else {
+                    return;
+                };
+                let channel = channel & 0x0f;
+                let state = &self.channels[channel as usize];
+                let pan_override = state.pan;
+                let soft = state.soft;
+                let channel_gain = state.gain();
+                let (instrument, envelope, retrigger) = match &state.patch {
+                    Some(patch) => (patch.instrument.clone(), patch.envelope, patch.retrigger),
+                    None => (self.instrument.clone(), self.envelope, self.retrigger),
+                };
+                match retrigger {
+                    RetriggerMode::Overlap => self.release_voices(channel, midi_note),
+                    RetriggerMode::Crossfade => {
+                        for voice in self.voices.iter_mut().filter(|v| v.plays(channel, midi_note)) {
+                            voice.fade_out(CROSSFADE_SECONDS, self.sample_rate);
+                        }
+                    }
+                }
+                self.make_room();
+                let mut strength = self.velocity_curve.apply(velocity, self.fixed_velocity);
+                if soft {
+                    strength *= SOFT_PEDAL_STRENGTH;
+                }
+                let note = NoteParams {
+                    midi_note,
+                    velocity,
+                    strength,
+                    frequency,
+                    sample_rate: self.sample_rate,
+                };
+                if let Some(generator) = instrument.start_note(&note) {
+                    // Controller pan wins over the instrument's own placement,
+                    // which wins over the key position
+                    let pan = pan_override
+                        .or_else(|| generator.pan())
+                        .unwrap_or_else(|| key_pan(midi_note, self.stereo_width));
+                    self.voices.push(Voice::new(channel, &note, generator, &envelope, self.clock, pan, channel_gain));
+                }
+            }
+            SynthCommand::NoteOff { channel, midi_note } => {
+                let channel = channel & 0x0f;
+                if self.channels[channel as usize].sustain {
+                    // Keep ringing until the pedal comes up
+                    for voice in self.voices.iter_mut().filter(|v| v.plays(channel, midi_note)) {
+                        voice.key_down = false;
+                    }
+                } else {
+                    self.release_voices(channel, midi_note);
+                }
+            }
+            SynthCommand::AllNotesOff => {
+                for voice in &mut self.voices {
+                    voice.release();
+                }
+            }
+            SynthCommand::SetVolume(volume) => {
+                self.volume = volume.clamp(0.0, 1.0);
+            }
+            SynthCommand::SetEnvelope(envelope) => {
+                self.envelope = envelope;
+            }
+            SynthCommand::SetVelocityCurve { curve, fixed_velocity } => {
+                self.velocity_curve = curve;
+                self.fixed_velocity = fixed_velocity;
+            }
+            SynthCommand::SetInstrument(instrument) => {
+                self.instrument = instrument;
+            }
+            SynthCommand::SetChannelPatch { channel, patch } => {
+                self.channels[(channel & 0x0f) as usize].patch = patch;
+            }
+            SynthCommand::ResetChannels => {
+                self.channels = Default::default();
+            }
+            SynthCommand::SetRetrigger(mode) => {
+                self.retrigger = mode;
+            }
+            SynthCommand::SetPolyphony { max_voices, stealing } => {
+                self.max_voices = max_voices.max(1);
+                self.stealing = stealing;
+            }
+            SynthCommand::SetEffects(effects) => {
+                self.effects.configure(&effects);
+            }
+            SynthCommand::SetStereoWidth(width) => {
+                self.stereo_width = width.clamp(0.0, 1.0);
+            }
+            SynthCommand::SetPan { channel, pan } => {
+                self.channels[(channel & 0x0f) as usize].pan = pan.map(|p| p.clamp(-1.0, 1.0));
+            }
+            SynthCommand::SetTuning(tuning) => {
+                self.tuning = tuning;
+            }
+            SynthCommand::SetMetronome(config) => {
+                self.metronome.configure(config);
+                self.effects.set_tempo(self.metronome.quarter_bpm());
+            }
+            SynthCommand::MetronomeEnabled(enabled) => self.metronome.set_enabled(enabled),
+            SynthCommand::LockMetronome(locked) => {
+                self.metronome.lock(locked);
+                self.effects.set_tempo(self.metronome.quarter_bpm());
+            }
+            SynthCommand::SyncMetronome(beat) => self.metronome.sync(beat),
+            SynthCommand::Scheduled { frame, command } => {
+                if frame <= self.clock {
+                    self.handle_command(*command);
+                } else {
+                    // Commands for the same frame keep the order they were sent in
+                    let index = self.scheduled.partition_point(|&(due, _)| due <= frame);
+                    self.scheduled.insert(index, (frame, *command));
+                }
+            }
+            SynthCommand::CancelScheduled => self.scheduled.clear(),
+            SynthCommand::SustainPedal { channel, pressed } => {
+                let channel = channel & 0x0f;
+                self.channels[channel as usize].sustain = pressed;
+                if !pressed {
+                    for voice in self.voices.iter_mut().filter(|v| v.channel == channel && !v.key_down) {
+                        voice.release();
+                    }
+                }
+            }
+            SynthCommand::SoftPedal { channel, pressed } => {
+                self.channels[(channel & 0x0f) as usize].soft = pressed;
+            }
+            SynthCommand::SetChannelVolume { channel, volume } => {
+                self.channels[(channel & 0x0f) as usize].volume = volume.min(127);
+            }
+            SynthCommand::SetExpression { channel, expression } => {
+                self.channels[(channel & 0x0f) as usize].expression = expression.min(127);
+            }
+            SynthCommand::PitchBend { channel, bend } => {
+                self.channels[(channel & 0x0f) as usize].bend = bend.clamp(-1.0, 1.0);
+            }
+            SynthCommand::SetPitchBendRange(semitones) => {
+                self.pitch_bend_range = semitones.max(0.0);
+            }
+            SynthCommand::SetChannelBendRange { channel, semitones } => {
+                self.channels[(channel & 0x0f) as usize].bend_range = Some(semitones.max(0.0));
+            }
+        }
+    }
+
+    // Voices that are already fading out don't count towards the limit
+    fn sounding_voices(&self) -> usize {
+        self.voices.iter().filter(|v| !v.is_fading()).count()
+    }
+
+    // Steals voices until a new note fits under the polyphony limit
+    fn make_room(&mut self) {
+        while self.sounding_voices() >= self.max_voices {
+            let candidates = self.voices.iter().enumerate().filter(|(_, v)| !v.is_fading());
+            let victim = match self.stealing {
+                VoiceStealing::Oldest => candidates.min_by_key(|(_, v)| v.started_at),
+                VoiceStealing::Quietest => candidates.min_by(|(_, a), (_, b)| {
+                    a.current_level().total_cmp(&b.current_level())
+                }),
+                VoiceStealing::Released => candidates.min_by_key(|(_, v)| (v.priority(), v.started_at)),
+            };
+
+            match victim.map(|(index, _)| index) {
+                Some(index) => self.voices[index].fade_out(STEAL_FADE_SECONDS, self.sample_rate),
+                None => break,
+            }
+        }
+    }
+
+    fn release_voices(&mut self, channel: u8, midi_note: u8) {
+        for voice in self.voices.iter_mut().filter(|v| v.plays(channel, midi_note)) {
+            voice.release();
+        }
+    }
+
+    fn process_commands(&mut self) {
+        while let Ok(command) = self.commands.try_recv() {
+            self.handle_command(command);
+        }
+        while self.scheduled.front().is_some_and(|&(frame, _)| frame <= self.clock) {
+            if let Some((_, command)) = self.scheduled.pop_front() {
+                self.handle_command(command);
+            }
+        }
+    }
+
+    pub fn next_frame(&mut self) -> [f32; 2] {
+        self.process_commands();
+
+        let pitch_ratios = self.channels.each_ref().map(|c| c.pitch_ratio(self.pitch_bend_range));
+        let channel_gains = self.channels.each_ref().map(Channel::gain);
+        let mut mix = [0.0; 2];
+        for voice in &mut self.voices {
+            let channel = voice.channel as usize;
+            let [left, right] = voice.next_frame(pitch_ratios[channel], channel_gains[channel]);
+            mix[0] += left;
+            mix[1] += right;
+        }
+        self.voices.retain(|v| !v.is_finished());
+        self.clock += 1;
+        self.shared_clock.store(self.clock, Ordering::Relaxed);
+
+        let gain = VOICE_GAIN * self.volume;
+        let click = self.metronome.next_sample();
+        self.effects.process([mix[0] * gain, mix[1] * gain], click)
+    }
+
+    // Fills `buffer` with interleaved left/right frames
+    pub fn render(&mut self, buffer: &mut [f32]) {
+        for frame in buffer.chunks_mut(2) {
+            let [left, right] = self.next_frame();
+            frame[0] = left;
+            if let Some(sample) = frame.get_mut(1) {
+                *sample = right;
+            }
+        }
+    }
+}
+
+impl Iterator for Synth {
+    type Item = f32;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Some(right) = self.pending_right.take() {
+            return Some(right);
+        }
+        let [left, right] = self.next_frame();
+        self.pending_right = Some(right);
+        Some(left)
+    }
+}
+
+impl Source for Synth {
+    fn current_frame_len(&self) -> Option<usize> {
+        None
+    }
+
+    fn channels(&self) -> u16 {
+        2
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TEST_ENVELOPE: EnvelopeConfig = EnvelopeConfig {
+        attack: 0.01,
+        decay: 0.05,
+        sustain: 0.5,
+        release: 0.1,
+    };
+
+    fn peak(buffer: &[f32]) -> f32 {
+        buffer.iter().fold(0.0, |acc, s| acc.max(s.abs()))
+    }
+
+    // Renders frames averaged down to one channel
+    fn render_mono(synth: &mut Synth, buffer: &mut [f32]) {
+        for sample in buffer.iter_mut() {
+            let [left, right] = synth.next_frame();
+            *sample = (left + right) / 2.0;
+        }
+    }
+
+    fn test_synth() -> (Synth, Sender<SynthCommand>) {
+        let (synth, commands) = Synth::new(44100);
+        commands.send(SynthCommand::SetEnvelope(TEST_ENVELOPE)).unwrap();
+        (synth, commands)
+    }
+
+    #[test]
+    fn test_silent_without_notes() {
+        let (mut synth, _commands) = test_synth();
+        let mut buffer = vec![0.0; 1024];
+        render_mono(&mut synth, &mut buffer);
+        assert_eq!(peak(&buffer), 0.0);
+    }
+
+    #[test]
+    fn test_note_on_and_off() {
+        let (mut synth, commands) = test_synth();
+        commands.send(SynthCommand::NoteOn { channel: 0, midi_note: 69, velocity: 127 }).unwrap();
+
+        let mut buffer = vec![0.0; 8820];
+        render_mono(&mut synth, &mut buffer);
+        assert!(peak(&buffer[4410..]) > 0.05);
+        assert_eq!(synth.active_voices(), 1);
+
+        commands.send(SynthCommand::NoteOff { channel: 0, midi_note: 69 }).unwrap();
+        render_mono(&mut synth, &mut buffer);
+        assert_eq!(synth.active_voices(), 0);
+        assert_eq!(peak(&buffer[4410..]), 0.0);
+    }
+
+    #[test]
+    fn test_held_note_sustains() {
+        let (mut synth, commands) = test_synth();
+        commands.send(SynthCommand::NoteOn { channel: 0, midi_note: 69, velocity: 127 }).unwrap();
+
+        // Well past the old fixed one-second note length
+        let mut buffer = vec![0.0; 44100 * 3];
+        render_mono(&mut synth, &mut buffer);
+        assert_eq!(synth.active_voices(), 1);
+        assert!(peak(&buffer[44100 * 2..]) > 0.05);
+    }
+
+    #[test]
+    fn test_rendering_is_deterministic() {
+        let render = || {
+            let (mut synth, commands) = test_synth();
+            commands.send(SynthCommand::NoteOn { channel: 0, midi_note: 60, velocity: 127 }).unwrap();
+            commands.send(SynthCommand::NoteOn { channel: 0, midi_note: 64, velocity: 127 }).unwrap();
+            let mut buffer = vec![0.0; 2048];
+            render_mono(&mut synth, &mut buffer);
+            buffer
+        };
+        assert_eq!(render(), render());
+    }
+
+    #[test]
+    fn test_envelope_stages() {
+        let mut envelope = Envelope::new(&TEST_ENVELOPE, 1000);
+
+        for _ in 0..10 {
+            envelope.next_level();
+        }
+        assert_eq!(envelope.level(), 1.0);
+        assert_eq!(envelope.stage(), EnvelopeStage::Decay);
+
+        for _ in 0..55 {
+            envelope.next_level();
+        }
+        assert_eq!(envelope.stage(), EnvelopeStage::Sustain);
+        assert_eq!(envelope.level(), 0.5);
+
+        envelope.release();
+        for _ in 0..50 {
+            envelope.next_level();
+        }
+        assert!((envelope.level() - 0.25).abs() < 0.01);
+
+        for _ in 0..50 {
+            envelope.next_level();
+        }
+        assert!(envelope.is_finished());
+    }
+
+    #[test]
+    fn test_release_fades_smoothly() {
+        let (mut synth, commands) = test_synth();
+        commands.send(SynthCommand::NoteOn { channel: 0, midi_note: 69, velocity: 127 }).unwrap();
+        let mut buffer = vec![0.0; 4410];
+        render_mono(&mut synth, &mut buffer);
+
+        commands.send(SynthCommand::NoteOff { channel: 0, midi_note: 69 }).unwrap();
+        render_mono(&mut synth, &mut buffer);
+
+        // The first and second halves of the release are both audible,
+        // with the tail quieter than the start
+        let head = peak(&buffer[..1000]);
+        let tail = peak(&buffer[2000..3000]);
+        assert!(head > tail);
+        assert!(tail > 0.0);
+    }
+
+    fn render_note(velocity: u8, curve: VelocityCurve) -> f32 {
+        let (mut synth, commands) = test_synth();
+        commands.send(SynthCommand::SetVelocityCurve { curve, fixed_velocity: 100 }).unwrap();
+        commands.send(SynthCommand::NoteOn { channel: 0, midi_note: 69, velocity }).unwrap();
+        let mut buffer = vec![0.0; 4410];
+        render_mono(&mut synth, &mut buffer);
+        peak(&buffer)
+    }
+
+    #[test]
+    fn test_velocity_scales_amplitude() {
+        let soft = render_note(20, VelocityCurve::Linear);
+        let loud = render_note(120, VelocityCurve::Linear);
+        assert!(soft > 0.0);
+        assert!(loud > soft * 2.0);
+    }
+
+    #[test]
+    fn test_velocity_curves() {
+        assert_eq!(VelocityCurve::Linear.apply(127, 100), 1.0);
+        assert!(VelocityCurve::Soft.apply(64, 100) > VelocityCurve::Linear.apply(64, 100));
+        assert!(VelocityCurve::Hard.apply(64, 100) < VelocityCurve::Linear.apply(64, 100));
+
+        let fixed_soft = render_note(20, VelocityCurve::Fixed);
+        let fixed_loud = render_note(120, VelocityCurve::Fixed);
+        assert_eq!(fixed_soft, fixed_loud);
+    }
+
+    #[test]
+    fn test_sound_font_voices() {
+        let font = crate::soundfont::SoundFont::parse(&crate::soundfont::tests::build_test_sound_font()).unwrap();
+        let (mut synth, commands) = test_synth();
+        commands.send(SynthCommand::SetInstrument(Arc::new(font.patch(0, 0)))).unwrap();
+        commands.send(SynthCommand::NoteOn { channel: 0, midi_note: 69, velocity: 127 }).unwrap();
+
+        let mut buffer = vec![0.0; 44100];
+        render_mono(&mut synth, &mut buffer);
+        assert!(peak(&buffer[22050..]) > 0.05);
+
+        // The test sample is a 441 Hz sine, so one cycle lasts 100 samples
+        let crossings = buffer[22050..22050 + 1000]
+            .windows(2)
+            .filter(|w| w[0] <= 0.0 && w[1] > 0.0)
+            .count();
+        assert_eq!(crossings, 10);
+    }
+
+    #[test]
+    fn test_sustain_pedal_defers_release() {
+        let (mut synth, commands) = test_synth();
+        let mut buffer = vec![0.0; 4410];
+
+        commands.send(SynthCommand::SustainPedal { channel: 0, pressed: true }).unwrap();
+        commands.send(SynthCommand::NoteOn { channel: 0, midi_note: 60, velocity: 100 }).unwrap();
+        commands.send(SynthCommand::NoteOn { channel: 0, midi_note: 64, velocity: 100 }).unwrap();
+        render_mono(&mut synth, &mut buffer);
+        commands.send(SynthCommand::NoteOff { channel: 0, midi_note: 60 }).unwrap();
+        commands.send(SynthCommand::NoteOff { channel: 0, midi_note: 64 }).unwrap();
+
+        render_mono(&mut synth, &mut buffer);
+        assert_eq!(synth.active_voices(), 2);
+        assert_eq!(synth.releasing_voices(), 0);
+        assert!(peak(&buffer) > 0.05);
+
+        commands.send(SynthCommand::SustainPedal { channel: 0, pressed: false }).unwrap();
+        render_mono(&mut synth, &mut buffer);
+        assert_eq!(synth.active_voices(), 0);
+    }
+
+    #[test]
+    fn test_pedal_up_keeps_held_keys() {
+        let (mut synth, commands) = test_synth();
+        let mut buffer = vec![0.0; 4410];
+
+        commands.send(SynthCommand::SustainPedal { channel: 0, pressed: true }).unwrap();
+        commands.send(SynthCommand::NoteOn { channel: 0, midi_note: 60, velocity: 100 }).unwrap();
+        commands.send(SynthCommand::NoteOn { channel: 0, midi_note: 64, velocity: 100 }).unwrap();
+        commands.send(SynthCommand::NoteOff { channel: 0, midi_note: 60 }).unwrap();
+        commands.send(SynthCommand::SustainPedal { channel: 0, pressed: false }).unwrap();
+        render_mono(&mut synth, &mut buffer);
+
+        // Only the note whose key was already up is let go
+        assert_eq!(synth.active_voices(), 1);
+    }
+
+    fn play(commands: &Sender<SynthCommand>, synth: &mut Synth, midi_note: u8, velocity: u8) {
+        commands.send(SynthCommand::NoteOn { channel: 0, midi_note, velocity }).unwrap();
+        let mut buffer = vec![0.0; 441];
+        render_mono(synth, &mut buffer);
+    }
+
+    fn sounding_notes(synth: &Synth) -> Vec<u8> {
+        let mut notes: Vec<u8> = synth.voices.iter().filter(|v| !v.is_fading()).map(|v| v.midi_note).collect();
+        notes.sort();
+        notes
+    }
+
+    #[test]
+    fn test_polyphony_steals_oldest() {
+        let (mut synth, commands) = test_synth();
+        commands.send(SynthCommand::SetPolyphony { max_voices: 3, stealing: VoiceStealing::Oldest }).unwrap();
+        for midi_note in [60, 62, 64, 65] {
+            play(&commands, &mut synth, midi_note, 100);
+        }
+        assert_eq!(sounding_notes(&synth), vec![62, 64, 65]);
+
+        // The stolen voice fades out instead of being cut off
+        let mut buffer = vec![0.0; 4410];
+        render_mono(&mut synth, &mut buffer);
+        assert_eq!(synth.active_voices(), 3);
+    }
+
+    #[test]
+    fn test_polyphony_steals_quietest() {
+        let (mut synth, commands) = test_synth();
+        commands.send(SynthCommand::SetPolyphony { max_voices: 3, stealing: VoiceStealing::Quietest }).unwrap();
+        play(&commands, &mut synth, 60, 120);
+        play(&commands, &mut synth, 62, 10);
+        play(&commands, &mut synth, 64, 120);
+        play(&commands, &mut synth, 65, 120);
+        assert_eq!(sounding_notes(&synth), vec![60, 64, 65]);
+    }
+
+    #[test]
+    fn test_polyphony_steals_released_first() {
+        let (mut synth, commands) = test_synth();
+        commands.send(SynthCommand::SetPolyphony { max_voices: 3, stealing: VoiceStealing::Released }).unwrap();
+        play(&commands, &mut synth, 60, 100);
+        play(&commands, &mut synth, 62, 100);
+        play(&commands, &mut synth, 64, 100);
+        commands.send(SynthCommand::NoteOff { channel: 0, midi_note: 62 }).unwrap();
+        play(&commands, &mut synth, 65, 100);
+        assert_eq!(sounding_notes(&synth), vec![60, 64, 65]);
+    }
+
+    #[test]
+    fn test_retrigger_modes() {
+        let (mut synth, commands) = test_synth();
+        play(&commands, &mut synth, 60, 100);
+        play(&commands, &mut synth, 60, 100);
+        // Overlap: the first strike rings out through its release
+        assert_eq!(synth.active_voices(), 2);
+        assert_eq!(synth.releasing_voices(), 1);
+
+        let (mut synth, commands) = test_synth();
+        commands.send(SynthCommand::SetRetrigger(RetriggerMode::Crossfade)).unwrap();
+        play(&commands, &mut synth, 60, 100);
+        play(&commands, &mut synth, 60, 100);
+        let mut buffer = vec![0.0; 2205];
+        render_mono(&mut synth, &mut buffer);
+        assert_eq!(synth.active_voices(), 1);
+    }
+
+    fn channel_peaks(synth: &mut Synth, frames: usize) -> [f32; 2] {
+        let mut buffer = vec![0.0; frames * 2];
+        synth.render(&mut buffer);
+        buffer.chunks(2).fold([0.0, 0.0], |acc, frame| [acc[0].max(frame[0].abs()), acc[1].max(frame[1].abs())])
+    }
+
+    #[test]
+    fn test_notes_are_panned_by_key_position() {
+        let (mut synth, commands) = test_synth();
+        commands.send(SynthCommand::SetStereoWidth(1.0)).unwrap();
+        commands.send(SynthCommand::NoteOn { channel: 0, midi_note: 36, velocity: 100 }).unwrap();
+        let [left, right] = channel_peaks(&mut synth, 4410);
+        assert!(left > right * 2.0);
+
+        commands.send(SynthCommand::AllNotesOff).unwrap();
+        channel_peaks(&mut synth, 44100);
+        commands.send(SynthCommand::NoteOn { channel: 0, midi_note: 96, velocity: 100 }).unwrap();
+        let [left, right] = channel_peaks(&mut synth, 4410);
+        assert!(right > left * 2.0);
+
+        // With no width everything sits in the middle
+        assert_eq!(key_pan(21, 0.0), 0.0);
+        assert_eq!(key_pan(21, 1.0), -1.0);
+        assert_eq!(key_pan(108, 1.0), 1.0);
+    }
+
+    #[test]
+    fn test_pan_override_replaces_key_position() {
+        let (mut synth, commands) = test_synth();
+        commands.send(SynthCommand::SetStereoWidth(1.0)).unwrap();
+        commands.send(SynthCommand::SetPan { channel: 0, pan: Some(1.0) }).unwrap();
+        commands.send(SynthCommand::NoteOn { channel: 0, midi_note: 36, velocity: 100 }).unwrap();
+        let [left, right] = channel_peaks(&mut synth, 4410);
+        assert!(left < 1e-6);
+        assert!(right > 0.05);
+    }
+
+    #[test]
+    fn test_channels_keep_their_own_notes_and_patches() {
+        let (mut synth, commands) = test_synth();
+        let patch = ChannelPatch {
+            instrument: Arc::new(crate::instruments::Bass),
+            envelope: TEST_ENVELOPE,
+            retrigger: RetriggerMode::Overlap,
+        };
+        commands.send(SynthCommand::SetChannelPatch { channel: 1, patch: Some(patch) }).unwrap();
+        commands.send(SynthCommand::NoteOn { channel: 0, midi_note: 60, velocity: 100 }).unwrap();
+        commands.send(SynthCommand::NoteOn { channel: 1, midi_note: 60, velocity: 100 }).unwrap();
+        let mut buffer = vec![0.0; 441];
+        render_mono(&mut synth, &mut buffer);
+        commands.send(SynthCommand::NoteOff { channel: 1, midi_note: 60 }).unwrap();
+        render_mono(&mut synth, &mut buffer);
+
+        // The same key on another channel is a separate voice
+        assert_eq!(synth.active_voices(), 2);
+        let released: Vec<u8> = synth.voices.iter().filter(|v| !v.key_down).map(|v| v.channel).collect();
+        assert_eq!(released, vec![1]);
+        assert!(synth.channels[1].patch.is_some());
+
+        commands.send(SynthCommand::ResetChannels).unwrap();
+        render_mono(&mut synth, &mut buffer);
+        assert!(synth.channels[1].patch.is_none());
+    }
+
+    fn upward_crossings(buffer: &[f32]) -> usize {
+        buffer.windows(2).filter(|w| w[0] < 0.0 && w[1] >= 0.0).count()
+    }
+
+    #[test]
+    fn test_pitch_bend_follows_channel_range() {
+        let (mut synth, commands) = test_synth();
+        commands.send(SynthCommand::NoteOn { channel: 0, midi_note: 69, velocity: 1 }).unwrap();
+        let mut buffer = vec![0.0; 44100];
+        render_mono(&mut synth, &mut buffer);
+        assert!(upward_crossings(&buffer).abs_diff(440) <= 2);
+
+        // A full bend up at the default two semitones reaches B4
+        commands.send(SynthCommand::PitchBend { channel: 0, bend: 1.0 }).unwrap();
+        render_mono(&mut synth, &mut buffer);
+        assert!(upward_crossings(&buffer).abs_diff(494) <= 2);
+
+        // An octave range doubles the pitch, and other channels are unaffected
+        commands.send(SynthCommand::SetChannelBendRange { channel: 0, semitones: 12.0 }).unwrap();
+        commands.send(SynthCommand::PitchBend { channel: 1, bend: -1.0 }).unwrap();
+        render_mono(&mut synth, &mut buffer);
+        assert!(upward_crossings(&buffer).abs_diff(880) <= 2);
+    }
+
+    #[test]
+    fn test_channel_volume_expression_and_pedals() {
+        let (mut synth, commands) = test_synth();
+        commands.send(SynthCommand::NoteOn { channel: 0, midi_note: 69, velocity: 127 }).unwrap();
+        let mut buffer = vec![0.0; 8820];
+        render_mono(&mut synth, &mut buffer);
+        let full = peak(&buffer[4410..]);
+
+        // Half the default volume is a quarter of the level
+        commands.send(SynthCommand::SetChannelVolume { channel: 0, volume: 50 }).unwrap();
+        render_mono(&mut synth, &mut buffer);
+        assert!((peak(&buffer[4410..]) / full - 0.25).abs() < 0.02);
+        commands.send(SynthCommand::SetExpression { channel: 0, expression: 0 }).unwrap();
+        render_mono(&mut synth, &mut buffer);
+        assert!(peak(&buffer[4410..]) < full * 0.01);
+
+        // The sustain pedal on one channel doesn't hold notes on another
+        commands.send(SynthCommand::SustainPedal { channel: 1, pressed: true }).unwrap();
+        commands.send(SynthCommand::NoteOff { channel: 0, midi_note: 69 }).unwrap();
+        render_mono(&mut synth, &mut buffer);
+        assert_eq!(synth.active_voices(), 0);
+
+        // Notes under the soft pedal start quieter
+        let (mut synth, commands) = test_synth();
+        commands.send(SynthCommand::SoftPedal { channel: 0, pressed: true }).unwrap();
+        commands.send(SynthCommand::NoteOn { channel: 0, midi_note: 69, velocity: 127 }).unwrap();
+        render_mono(&mut synth, &mut buffer);
+        assert!(peak(&buffer[4410..]) < full * 0.8);
+    }
+
+    #[test]
+    fn test_scheduled_commands_land_on_their_frame() {
+        let (mut synth, commands) = test_synth();
+        let clock = synth.clock();
+        let at = |frame: u64, command: SynthCommand| SynthCommand::Scheduled { frame, command: Box::new(command) };
+        // Sent out of order, applied in frame order
+        commands.send(at(3000, SynthCommand::NoteOff { channel: 0, midi_note: 69 })).unwrap();
+        commands.send(at(1000, SynthCommand::NoteOn { channel: 0, midi_note: 69, velocity: 127 })).unwrap();
+
+        let mut buffer = vec![0.0; 2000];
+        render_mono(&mut synth, &mut buffer);
+        assert!(buffer[..1000].iter().all(|s| *s == 0.0));
+        assert!(buffer[1000..1010].iter().any(|s| *s != 0.0));
+        assert_eq!(clock.load(Ordering::Relaxed), 2000);
+        render_mono(&mut synth, &mut buffer);
+        assert_eq!(synth.voices[0].envelope.stage(), EnvelopeStage::Release);
+
+        // Cancelled commands never play; ones already due play at once
+        commands.send(at(5000, SynthCommand::NoteOn { channel: 0, midi_note: 72, velocity: 127 })).unwrap();
+        commands.send(SynthCommand::CancelScheduled).unwrap();
+        commands.send(at(0, SynthCommand::NoteOn { channel: 0, midi_note: 60, velocity: 127 })).unwrap();
+        render_mono(&mut synth, &mut buffer);
+        let notes = sounding_notes(&synth);
+        assert!(notes.contains(&60) && !notes.contains(&72));
+    }
+}
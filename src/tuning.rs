@@ -0,0 +1,327 @@
+use anyhow::{anyhow, Result};
+use std::path::Path;
+
+use crate::config::{TuningConfig, TuningSystem};
+
+// Samples and other fixed-pitch material are assumed to be recorded at
+// standard pitch: 12-TET with A4 = 440 Hz
+pub fn standard_frequency(midi_note: u8) -> f32 {
+    440.0 * 2.0_f32.powf((midi_note as f32 - 69.0) / 12.0)
+}
+
+const A4: u8 = 69;
+const MIDDLE_C: u8 = 60;
+
+// Scale degrees in cents above the tonic, without the tonic itself; the
+// last entry is the octave
+const JUST_INTONATION: [f64; 12] = [
+    111.731, 203.910, 315.641, 386.314, 498.045, 590.224, 701.955, 813.686, 884.359, 1017.596, 1088.269, 1200.0,
+];
+const PYTHAGOREAN: [f64; 12] = [
+    90.225, 203.910, 294.135, 407.820, 498.045, 611.730, 701.955, 792.180, 905.865, 996.090, 1109.775, 1200.0,
+];
+// Quarter-comma meantone with the wolf fifth between G# and Eb
+const MEANTONE: [f64; 12] = [
+    76.049, 193.157, 310.265, 386.314, 503.422, 579.471, 696.578, 772.627, 889.735, 1006.843, 1082.892, 1200.0,
+];
+
+// A frequency for every MIDI note, built once from a scale and a keyboard
+// mapping. Unmapped keys have no frequency and stay silent.
+#[derive(Debug, Clone)]
+pub struct Tuning {
+    name: String,
+    frequencies: [Option<f32>; 128],
+}
+
+// Which scale degree each key plays, in Scala .kbm terms
+#[derive(Debug, Clone, PartialEq)]
+pub struct KeyboardMapping {
+    // Scale degree per key, repeating every `map.len()` keys from
+    // `middle_note`. Empty means every key is the next degree.
+    pub map: Vec<Option<usize>>,
+    pub first_note: u8,
+    pub last_note: u8,
+    // The key that plays the first degree (the tonic)
+    pub middle_note: u8,
+    // The key tuned to exactly `reference_frequency`
+    pub reference_note: u8,
+    pub reference_frequency: f64,
+    // Scale degree reached after one pass through the map
+    pub octave_degree: usize,
+}
+
+impl KeyboardMapping {
+    // One key per degree across the whole keyboard
+    pub fn linear(middle_note: u8, reference_frequency: f64) -> Self {
+        Self {
+            map: Vec::new(),
+            first_note: 0,
+            last_note: 127,
+            middle_note,
+            reference_note: A4,
+            reference_frequency,
+            octave_degree: 0,
+        }
+    }
+
+    pub fn parse(text: &str) -> Result<Self> {
+        let mut lines = scala_lines(text);
+        let mut field = |name: &str| -> Result<String> {
+            lines.next().map(str::to_string).ok_or_else(|| anyhow!("Keyboard mapping is missing its {}", name))
+        };
+        let size: usize = field("map size")?.parse()?;
+        let first_note = parse_note(&field("first note")?)?;
+        let last_note = parse_note(&field("last note")?)?;
+        let middle_note = parse_note(&field("middle note")?)?;
+        let reference_note = parse_note(&field("reference note")?)?;
+        let reference_frequency: f64 = field("reference frequency")?.parse()?;
+        let octave_degree: usize = field("octave degree")?.parse()?;
+
+        let mut map = Vec::with_capacity(size);
+        for _ in 0..size {
+            // Trailing entries may be left out; they count as unmapped
+            let entry = field("map").unwrap_or_else(|_| "x".to_string());
+            map.push(if entry.eq_ignore_ascii_case("x") { None } else { Some(entry.parse()?) });
+        }
+
+        if reference_frequency <= 0.0 {
+            return Err(anyhow!("Keyboard mapping has a non-positive reference frequency"));
+        }
+        Ok(Self { map, first_note, last_note, middle_note, reference_note, reference_frequency, octave_degree })
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
+        Self::parse(&std::fs::read_to_string(path)?)
+    }
+}
+
+// A Scala .scl scale: a description and the pitch of each degree in cents
+#[derive(Debug, Clone, PartialEq)]
+pub struct Scale {
+    pub description: String,
+    // Degrees above the tonic; the last one is the period, usually 1200
+    pub cents: Vec<f64>,
+}
+
+impl Scale {
+    pub fn parse(text: &str) -> Result<Self> {
+        let mut lines = scala_lines(text);
+        // The first line is the description, which may be empty
+        let description = lines.next().unwrap_or_default().to_string();
+        let count: usize = lines
+            .next()
+            .ok_or_else(|| anyhow!("Scale is missing its note count"))?
+            .parse()
+            .map_err(|_| anyhow!("Scale has an invalid note count"))?;
+
+        let cents = lines.take(count).map(parse_pitch).collect::<Result<Vec<f64>>>()?;
+        if cents.len() != count || count == 0 {
+            return Err(anyhow!("Scale lists {} of {} notes", cents.len(), count));
+        }
+        if cents[count - 1] <= 0.0 {
+            return Err(anyhow!("Scale period must be above the tonic"));
+        }
+        Ok(Self { description, cents })
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
+        Self::parse(&std::fs::read_to_string(path)?)
+    }
+
+    fn from_cents(description: &str, cents: &[f64]) -> Self {
+        Self { description: description.to_string(), cents: cents.to_vec() }
+    }
+
+    // Cents of any degree, continuing into further periods
+    fn degree_cents(&self, degree: i64) -> f64 {
+        let len = self.cents.len() as i64;
+        let period = self.cents[self.cents.len() - 1];
+        let octave = degree.div_euclid(len);
+        let step = degree.rem_euclid(len);
+        let within = if step == 0 { 0.0 } else { self.cents[step as usize - 1] };
+        octave as f64 * period + within
+    }
+}
+
+impl Tuning {
+    pub fn equal(reference_frequency: f32) -> Self {
+        let cents: Vec<f64> = (1..=12).map(|step| step as f64 * 100.0).collect();
+        let scale = Scale::from_cents("12-tone equal temperament", &cents);
+        Self::new(&scale, &KeyboardMapping::linear(MIDDLE_C, reference_frequency as f64))
+    }
+
+    pub fn new(scale: &Scale, mapping: &KeyboardMapping) -> Self {
+        let reference_cents = Self::key_cents(scale, mapping, mapping.reference_note);
+        let mut frequencies = [None; 128];
+        for (midi_note, frequency) in frequencies.iter_mut().enumerate() {
+            let midi_note = midi_note as u8;
+            if midi_note < mapping.first_note || midi_note > mapping.last_note {
+                continue;
+            }
+            *frequency = match (Self::key_cents(scale, mapping, midi_note), reference_cents) {
+                (Some(cents), Some(reference)) => {
+                    Some((mapping.reference_frequency * 2f64.powf((cents - reference) / 1200.0)) as f32)
+                }
+                _ => None,
+            };
+        }
+        Self { name: scale.description.clone(), frequencies }
+    }
+
+    // Pitch of a key in cents above the middle note, or None if unmapped
+    fn key_cents(scale: &Scale, mapping: &KeyboardMapping, midi_note: u8) -> Option<f64> {
+        let offset = midi_note as i64 - mapping.middle_note as i64;
+        if mapping.map.is_empty() {
+            return Some(scale.degree_cents(offset));
+        }
+        let size = mapping.map.len() as i64;
+        let repeat = offset.div_euclid(size);
+        let degree = mapping.map[offset.rem_euclid(size) as usize]? as i64;
+        Some(repeat as f64 * scale.degree_cents(mapping.octave_degree as i64) + scale.degree_cents(degree))
+    }
+
+    pub fn from_config(config: &TuningConfig) -> Result<Self> {
+        let reference = config.reference as f64;
+        if reference <= 0.0 {
+            return Err(anyhow!("Reference pitch must be above 0 Hz"));
+        }
+        // Historical temperaments are laid out from the configured tonic
+        let tonic = MIDDLE_C + config.root % 12;
+        let tuning = match config.system {
+            TuningSystem::Equal => Self::equal(config.reference),
+            TuningSystem::Just => Self::new(&Scale::from_cents("Just intonation", &JUST_INTONATION), &KeyboardMapping::linear(tonic, reference)),
+            TuningSystem::Pythagorean => Self::new(&Scale::from_cents("Pythagorean", &PYTHAGOREAN), &KeyboardMapping::linear(tonic, reference)),
+            TuningSystem::Meantone => Self::new(&Scale::from_cents("Quarter-comma meantone", &MEANTONE), &KeyboardMapping::linear(tonic, reference)),
+            TuningSystem::Scala => {
+                let path = config.scala_file.as_ref().ok_or_else(|| anyhow!("No scala_file set for the scala tuning"))?;
+                let scale = Scale::load(crate::audio::expand_home(path))?;
+                let mapping = match &config.keyboard_map {
+                    Some(path) => KeyboardMapping::load(crate::audio::expand_home(path))?,
+                    None => KeyboardMapping::linear(tonic, reference),
+                };
+                Self::new(&scale, &mapping)
+            }
+        };
+        Ok(tuning)
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn frequency(&self, midi_note: u8) -> Option<f32> {
+        self.frequencies.get(midi_note as usize).copied().flatten()
+    }
+
+    // The key whose tuned pitch is closest to `frequency`
+    pub fn nearest_note(&self, frequency: f32) -> Option<u8> {
+        self.frequencies
+            .iter()
+            .enumerate()
+            .filter_map(|(note, f)| f.map(|f| (note as u8, (f / frequency).log2().abs())))
+            .min_by(|a, b| a.1.total_cmp(&b.1))
+            .map(|(note, _)| note)
+    }
+}
+
+impl Default for Tuning {
+    fn default() -> Self {
+        Self::equal(440.0)
+    }
+}
+
+// Non-comment lines, trimmed. Scala files use '!' for comments.
+fn scala_lines(text: &str) -> impl Iterator<Item = &str> {
+    text.lines().filter(|line| !line.starts_with('!')).map(str::trim)
+}
+
+fn parse_note(text: &str) -> Result<u8> {
+    let note: u8 = text.parse().map_err(|_| anyhow!("Invalid MIDI note: {}", text))?;
+    if note > 127 {
+        return Err(anyhow!("Invalid MIDI note: {}", text));
+    }
+    Ok(note)
+}
+
+// A pitch line is cents if it contains a '.', otherwise a ratio like 3/2 or 2.
+// Anything after the value is a comment.
+fn parse_pitch(line: &str) -> Result<f64> {
+    let value = line.split_whitespace().next().ok_or_else(|| anyhow!("Empty pitch line"))?;
+    if value.contains('.') {
+        return value.parse().map_err(|_| anyhow!("Invalid cents value: {}", value));
+    }
+    let (numerator, denominator) = value.split_once('/').unwrap_or((value, "1"));
+    let numerator: f64 = numerator.parse().map_err(|_| anyhow!("Invalid ratio: {}", value))?;
+    let denominator: f64 = denominator.parse().map_err(|_| anyhow!("Invalid ratio: {}", value))?;
+    if numerator <= 0.0 || denominator <= 0.0 {
+        return Err(anyhow!("Invalid ratio: {}", value));
+    }
+    Ok(1200.0 * (numerator / denominator).log2())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn close(a: f32, b: f32) -> bool {
+        (a - b).abs() < 0.01
+    }
+
+    #[test]
+    fn test_equal_temperament_matches_standard_pitch() {
+        let tuning = Tuning::default();
+        for midi_note in 0..128 {
+            assert!(close(tuning.frequency(midi_note).unwrap(), standard_frequency(midi_note)));
+        }
+        assert_eq!(tuning.nearest_note(440.0), Some(69));
+
+        let baroque = Tuning::equal(415.0);
+        assert!(close(baroque.frequency(69).unwrap(), 415.0));
+        assert!(close(baroque.frequency(81).unwrap(), 830.0));
+    }
+
+    #[test]
+    fn test_historical_tunings_keep_the_reference_pitch() {
+        for system in [TuningSystem::Just, TuningSystem::Pythagorean, TuningSystem::Meantone] {
+            let config = TuningConfig { system, ..TuningConfig::default() };
+            let tuning = Tuning::from_config(&config).unwrap();
+            assert!(close(tuning.frequency(69).unwrap(), 440.0));
+            // Octaves stay pure
+            assert!(close(tuning.frequency(81).unwrap(), 880.0));
+        }
+
+        // C to G is a pure 3:2 fifth in just intonation, rooted on C
+        let just = Tuning::from_config(&TuningConfig { system: TuningSystem::Just, ..TuningConfig::default() }).unwrap();
+        let fifth = just.frequency(67).unwrap() / just.frequency(60).unwrap();
+        assert!((fifth - 1.5).abs() < 1e-4);
+    }
+
+    #[test]
+    fn test_scala_scale_and_mapping() {
+        let scl = "! pentatonic.scl\n!\nA pentatonic scale\n 5\n!\n 9/8\n 5/4\n 701.955 pure fifth\n 5/3\n 2\n";
+        let scale = Scale::parse(scl).unwrap();
+        assert_eq!(scale.description, "A pentatonic scale");
+        assert_eq!(scale.cents.len(), 5);
+        assert!((scale.cents[4] - 1200.0).abs() < 1e-9);
+
+        // C D E G A from C, with the other keys left unmapped
+        let kbm = "! white.kbm\n12\n0\n127\n60\n69\n440.0\n5\n0\nx\n1\nx\n2\nx\nx\n3\nx\n4\nx\nx\n";
+        let mapping = KeyboardMapping::parse(kbm).unwrap();
+        let tuning = Tuning::new(&scale, &mapping);
+
+        assert_eq!(tuning.frequency(61), None);
+        assert!(close(tuning.frequency(69).unwrap(), 440.0));
+        // A is degree 5/3 above C
+        assert!(close(tuning.frequency(60).unwrap(), 440.0 * 3.0 / 5.0));
+        // One map repeat up is one period up
+        assert!(close(tuning.frequency(72).unwrap(), 440.0 * 6.0 / 5.0));
+    }
+
+    #[test]
+    fn test_invalid_scala_files_are_rejected() {
+        assert!(Scale::parse("Empty\n0\n").is_err());
+        assert!(Scale::parse("Short\n3\n9/8\n").is_err());
+        assert!(Scale::parse("Bad ratio\n1\n0/2\n").is_err());
+        assert!(KeyboardMapping::parse("0\n0\n127\n60\n").is_err());
+    }
+}
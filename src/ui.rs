@@ -6,35 +6,65 @@ use ratatui::{
 };
 
 use crate::{
+    audio_effects::EffectParam,
+    config::MetronomeConfig,
+    drums::DrumLane,
+    metronome::MetronomeParam,
     piano::{Piano, PianoLayout},
-    effects::VisualEffects,
+    effects::{VisualEffects, DRUM_LANE_WINDOW},
     midi::MidiPlayer,
+    mixer::Mixer,
     audio::AudioEngine,
 };
 
 pub struct UI {
     pub show_help: bool,
+    pub show_effects: bool,
+    pub effects_selection: usize,
+    pub show_metronome: bool,
+    pub metronome_selection: usize,
+    pub show_mixer: bool,
+    pub mixer_selection: usize,
     pub show_info: bool,
     pub current_octave_display: u8,
     pub volume_display: f32,
     pub recording: bool,
     pub metronome: bool,
     pub status_message: Option<String>,
+    // Where the progress bar was last drawn, for mouse clicks
+    pub progress_area: Rect,
 }
 
 impl UI {
     pub fn new() -> Self {
         Self {
             show_help: false,
+            show_effects: false,
+            effects_selection: 0,
+            show_metronome: false,
+            metronome_selection: 0,
+            show_mixer: false,
+            mixer_selection: 0,
             show_info: true,
             current_octave_display: 4,
             volume_display: 0.7,
             recording: false,
             metronome: false,
             status_message: None,
+            progress_area: Rect::default(),
         }
     }
     
+    // The fraction of the song under a click at `column`, `row`, if it is on
+    // the progress bar
+    pub fn progress_position(&self, column: u16, row: u16) -> Option<f32> {
+        let area = self.progress_area;
+        let inside = column >= area.x && column < area.right() && row >= area.y && row < area.bottom();
+        // The borders take a column at each end
+        let width = area.width.checked_sub(2).filter(|w| *w > 0)?;
+        inside.then(|| (column.saturating_sub(area.x + 1) as f32 / width as f32).min(1.0))
+    }
+    
     pub fn render(
         &mut self,
         f: &mut ratatui::Frame,
@@ -42,14 +72,18 @@ impl UI {
         effects: &VisualEffects,
         midi_player: &MidiPlayer,
         audio_engine: &AudioEngine,
+        mixer: &Mixer,
     ) {
         let size = f.area();
+        // Only files with a percussion part make room for the drum lanes
+        let drum_lanes_height = if midi_player.has_drums { DrumLane::ALL.len() as u16 + 2 } else { 0 };
         
         let chunks = Layout::default()
             .direction(Direction::Vertical)
             .constraints([
                 Constraint::Length(3),  // Header
-                Constraint::Length(2),  // MIDI Progress (when playing)
+                Constraint::Length(3),  // MIDI Progress (when playing)
+                Constraint::Length(drum_lanes_height),
                 Constraint::Min(13),    // Piano - still plenty of space
                 Constraint::Length(3),  // Controls
                 Constraint::Length(1),  // Status
@@ -57,11 +91,24 @@ impl UI {
             .split(size);
         
         self.render_header(f, chunks[0], piano, midi_player, audio_engine);
+        self.progress_area = if midi_player.current_file.is_some() { chunks[1] } else { Rect::default() };
         self.render_midi_progress(f, chunks[1], midi_player);
-        self.render_piano(f, chunks[2], piano, effects);
-        self.render_controls(f, chunks[3], piano);
-        self.render_status(f, chunks[4]);
+        if midi_player.has_drums {
+            self.render_drum_lanes(f, chunks[2], effects);
+        }
+        self.render_piano(f, chunks[3], piano, effects);
+        self.render_controls(f, chunks[4], piano);
+        self.render_status(f, chunks[5]);
         
+        if self.show_effects {
+            self.render_effects_panel(f, size, audio_engine);
+        }
+        if self.show_metronome {
+            self.render_metronome_panel(f, size, audio_engine.metronome());
+        }
+        if self.show_mixer {
+            self.render_mixer_panel(f, size, mixer);
+        }
         if self.show_help {
             self.render_help_popup(f, size);
         }
@@ -91,7 +138,11 @@ impl UI {
             .block(Block::default().borders(Borders::ALL));
         f.render_widget(title, header_chunks[0]);
         
-        let octave_text = format!("Octave: {}", piano.current_octave);
+        let octave_text = if audio_engine.is_audio_enabled() {
+            format!("Octave: {}  {}", piano.current_octave, audio_engine.instrument_name())
+        } else {
+            format!("Octave: {}  (no audio)", piano.current_octave)
+        };
         let octave = Paragraph::new(octave_text)
             .style(Style::default().fg(Color::Yellow))
             .alignment(Alignment::Center)
@@ -110,13 +161,17 @@ impl UI {
                 Style::default().fg(Color::Red).add_modifier(Modifier::BOLD),
             ),
             Span::styled(
-                if self.metronome { "♩ " } else { "" },
+                if self.metronome { format!("♩ {:.0} ", audio_engine.metronome().bpm) } else { String::new() },
                 Style::default().fg(Color::Blue),
             ),
             Span::styled(
                 if piano.sustain_pedal { "SUS " } else { "" },
                 Style::default().fg(Color::Magenta),
             ),
+            Span::styled(
+                if piano.soft_pedal { "SOFT " } else { "" },
+                Style::default().fg(Color::LightMagenta),
+            ),
         ];
         
         if let Some(current_file) = &midi_player.current_file {
@@ -142,12 +197,21 @@ impl UI {
             let progress = midi_player.get_progress();
             let (current_time, total_time) = midi_player.get_time_info();
             
+            // Times are in the song's own tempo; the speed is mentioned
+            // next to them when it isn't the file's own
+            let rate = midi_player.rate();
+            let mut speed = if (rate - 1.0).abs() > f64::EPSILON { format!(" at {:.0}%", rate * 100.0) } else { String::new() };
+            if midi_player.loops_between_markers() {
+                speed.push_str(" - Loop A-B");
+            } else if midi_player.is_loop_enabled() {
+                speed.push_str(" - Loop");
+            }
             let title = if midi_player.is_playing {
-                format!("♪ Playing - {:02}:{:02} / {:02}:{:02}", 
+                format!("♪ Playing{} - {:02}:{:02} / {:02}:{:02}", speed,
                     current_time.as_secs() / 60, current_time.as_secs() % 60,
                     total_time.as_secs() / 60, total_time.as_secs() % 60)
             } else {
-                format!("♪ Paused - {:02}:{:02} / {:02}:{:02}", 
+                format!("♪ Paused{} - {:02}:{:02} / {:02}:{:02}", speed,
                     current_time.as_secs() / 60, current_time.as_secs() % 60,
                     total_time.as_secs() / 60, total_time.as_secs() % 60)
             };
@@ -159,6 +223,7 @@ impl UI {
                 .gauge_style(Style::default().fg(gauge_color))
                 .ratio(progress as f64);
             f.render_widget(progress_gauge, area);
+            self.render_loop_markers(f, area, midi_player);
         } else {
             // Show empty space when no MIDI file is loaded
             let empty_block = Block::default()
@@ -169,6 +234,80 @@ impl UI {
         }
     }
     
+    // A and B over the gauge where the loop markers sit, dimmed while the
+    // loop is off
+    fn render_loop_markers(&self, f: &mut ratatui::Frame, area: Rect, midi_player: &MidiPlayer) {
+        if area.width <= 2 || area.height <= 2 || midi_player.total_ticks == 0 {
+            return;
+        }
+        let width = area.width - 2;
+        let row = area.y + 1;
+        let color = if midi_player.loops_between_markers() { Color::Cyan } else { Color::DarkGray };
+        let (a, b) = midi_player.loop_markers;
+        for (tick, label) in [(a, "A"), (b, "B")] {
+            let Some(tick) = tick else { continue };
+            let offset = (tick as f64 / midi_player.total_ticks as f64 * width as f64) as u16;
+            if let Some(cell) = f.buffer_mut().cell_mut((area.x + 1 + offset.min(width - 1), row)) {
+                cell.set_symbol(label).set_style(Style::default().fg(Color::Black).bg(color).add_modifier(Modifier::BOLD));
+            }
+        }
+    }
+    
+    // One row per kind of drum; hits enter on the right and drift left as
+    // they age
+    fn render_drum_lanes(&self, f: &mut ratatui::Frame, area: Rect, effects: &VisualEffects) {
+        let block = Block::default()
+            .title("Drums")
+            .borders(Borders::ALL)
+            .border_style(Style::default().fg(Color::DarkGray));
+        let inner = block.inner(area);
+        f.render_widget(block, area);
+        
+        const LABEL_WIDTH: usize = 8;
+        let cells = (inner.width as usize).saturating_sub(LABEL_WIDTH);
+        if cells == 0 {
+            return;
+        }
+        
+        let lines: Vec<Line> = DrumLane::ALL
+            .iter()
+            .map(|&lane| {
+                let color = match lane {
+                    DrumLane::Kick => Color::Red,
+                    DrumLane::Snare => Color::Yellow,
+                    DrumLane::HiHat => Color::Cyan,
+                    DrumLane::Toms => Color::Green,
+                    DrumLane::Cymbals => Color::Magenta,
+                    DrumLane::Percussion => Color::Blue,
+                };
+                let mut row = vec![(' ', false); cells];
+                for hit in effects.drum_hits.iter().filter(|hit| hit.lane == lane) {
+                    let age = hit.time.elapsed().as_secs_f32() / DRUM_LANE_WINDOW.as_secs_f32();
+                    if age < 1.0 {
+                        let cell = ((1.0 - age) * (cells - 1) as f32).round() as usize;
+                        row[cell] = (if hit.velocity > 100 { '●' } else { '•' }, age < 0.05);
+                    }
+                }
+                
+                let mut spans = vec![Span::styled(
+                    format!("{:<width$}", lane.label(), width = LABEL_WIDTH),
+                    Style::default().fg(color),
+                )];
+                spans.extend(row.into_iter().map(|(symbol, fresh)| {
+                    let style = if fresh {
+                        Style::default().fg(color).add_modifier(Modifier::BOLD)
+                    } else {
+                        Style::default().fg(color)
+                    };
+                    Span::styled(symbol.to_string(), style)
+                }));
+                Line::from(spans)
+            })
+            .collect();
+        
+        f.render_widget(Paragraph::new(lines), inner);
+    }
+    
     fn render_piano(
         &self,
         f: &mut ratatui::Frame,
@@ -340,11 +479,14 @@ impl UI {
                 Span::raw("+ Octave Up "),
                 Span::raw("_ Octave Down "),
                 Span::raw("Space Sustain "),
+                Span::raw("Tab Instrument "),
                 Span::raw("R Record "),
                 Span::raw("P Play "),
                 Span::raw("M Metronome "),
                 Span::raw("L Load "),
                 Span::raw("F1 Help "),
+                Span::raw("F2 Effects "),
+                Span::raw("F3 Metronome "),
                 Span::raw("Q Quit"),
             ]),
             Line::from(vec![
@@ -388,12 +530,22 @@ impl UI {
             Line::from("  [ / ]     - Volume down/up"),
             Line::from("  + / _     - Octave up/down"),
             Line::from("  Space     - Sustain pedal"),
+            Line::from("  Tab       - Next instrument (Shift+Tab: previous)"),
             Line::from("  R         - Start/stop recording"),
             Line::from("  P (upper) - Toggle MIDI playback (with key lighting)"),
-            Line::from("  p (lower) - Playback last recording"),
+            Line::from("  p (lower) - Playback last recording (P pauses, arrows seek)"),
+            Line::from("  ← / →     - Jump 5 seconds back/forward in a MIDI file"),
+            Line::from("  Shift+←/→ - Jump a bar back/forward (or click the progress bar)"),
+            Line::from("  < / >     - Slow down/speed up MIDI playback (25% to 200%)"),
+            Line::from("  { / }     - Set loop marker A/B (or right-click the progress bar)"),
+            Line::from("  | / \\     - Toggle looping / clear the A-B markers"),
             Line::from("  M         - Toggle metronome"),
+            Line::from("  F3        - Metronome settings (tempo, time signature...)"),
+            Line::from("  F4        - Tap tempo"),
+            Line::from("  F5        - Mixer: mute, solo and volume per MIDI track"),
             Line::from("  L         - Load MIDI file"),
             Line::from("  F1        - Toggle this help"),
+            Line::from("  F2        - Effects panel (arrows adjust, Enter toggles)"),
             Line::from("  Q         - Quit"),
             Line::from(""),
             Line::from("Press any key to close this help..."),
@@ -411,6 +563,141 @@ impl UI {
         f.render_widget(help, popup_area);
     }
     
+    fn render_effects_panel(&self, f: &mut ratatui::Frame, area: Rect, audio_engine: &AudioEngine) {
+        let popup_area = centered_rect(50, 80, area);
+        f.render_widget(Clear, popup_area);
+        
+        let effects = audio_engine.effects();
+        let mut lines = Vec::new();
+        let mut current_effect = "";
+        for (i, param) in EffectParam::ALL.iter().enumerate() {
+            if param.effect_name() != current_effect {
+                current_effect = param.effect_name();
+                let (state, color) = if param.is_effect_enabled(effects) {
+                    ("ON", Color::Green)
+                } else {
+                    ("OFF", Color::DarkGray)
+                };
+                lines.push(Line::from(vec![
+                    Span::styled(format!("{:<8}", current_effect), Style::default().fg(Color::Yellow).add_modifier(Modifier::BOLD)),
+                    Span::styled(state, Style::default().fg(color)),
+                ]));
+            }
+            
+            let style = if i == self.effects_selection {
+                Style::default().fg(Color::Black).bg(Color::Cyan)
+            } else {
+                Style::default()
+            };
+            lines.push(Line::from(Span::styled(
+                format!("  {:<12}{:>12}", param.label(), param.value(effects)),
+                style,
+            )));
+        }
+        lines.push(Line::from(""));
+        lines.push(Line::from("Up/Down select  Left/Right adjust  Enter on/off  F2 close"));
+        
+        let panel = Paragraph::new(lines)
+            .block(
+                Block::default()
+                    .title("Effects")
+                    .borders(Borders::ALL)
+                    .border_style(Style::default().fg(Color::Cyan)),
+            );
+        
+        f.render_widget(panel, popup_area);
+    }
+    
+    fn render_metronome_panel(&self, f: &mut ratatui::Frame, area: Rect, config: &MetronomeConfig) {
+        let popup_area = centered_rect(50, 50, area);
+        f.render_widget(Clear, popup_area);
+        
+        let (state, color) = if self.metronome { ("ON", Color::Green) } else { ("OFF", Color::DarkGray) };
+        let mut lines = vec![Line::from(vec![
+            Span::styled("Metronome ", Style::default().fg(Color::Yellow).add_modifier(Modifier::BOLD)),
+            Span::styled(state, Style::default().fg(color)),
+        ])];
+        for (i, param) in MetronomeParam::ALL.iter().enumerate() {
+            let style = if i == self.metronome_selection {
+                Style::default().fg(Color::Black).bg(Color::Cyan)
+            } else {
+                Style::default()
+            };
+            lines.push(Line::from(Span::styled(
+                format!("  {:<14}{:>12}", param.label(), param.value(config)),
+                style,
+            )));
+        }
+        lines.push(Line::from(""));
+        lines.push(Line::from("Up/Down select  Left/Right adjust  Enter on/off"));
+        lines.push(Line::from("F4 tap tempo  F3 close"));
+        
+        let panel = Paragraph::new(lines)
+            .block(
+                Block::default()
+                    .title("Metronome")
+                    .borders(Borders::ALL)
+                    .border_style(Style::default().fg(Color::Cyan)),
+            );
+        
+        f.render_widget(panel, popup_area);
+    }
+    
+    fn render_mixer_panel(&self, f: &mut ratatui::Frame, area: Rect, mixer: &Mixer) {
+        let popup_area = centered_rect(60, 60, area);
+        f.render_widget(Clear, popup_area);
+        
+        let soloing = mixer.strips.iter().any(|s| s.solo);
+        let mut lines = Vec::new();
+        if mixer.strips.is_empty() {
+            lines.push(Line::from("Load a MIDI file to mix its parts"));
+        }
+        for (i, strip) in mixer.strips.iter().enumerate() {
+            let audible = !strip.muted && (strip.solo || !soloing);
+            let style = if i == self.mixer_selection {
+                Style::default().fg(Color::Black).bg(Color::Cyan)
+            } else if audible {
+                Style::default()
+            } else {
+                Style::default().fg(Color::DarkGray)
+            };
+            let filled = (strip.volume * 10.0).round() as usize;
+            lines.push(Line::from(Span::styled(
+                format!(
+                    "  {:<28} {} {} {}{} {:>3.0}%",
+                    strip.label(),
+                    if strip.muted { "M" } else { "·" },
+                    if strip.solo { "S" } else { "·" },
+                    "█".repeat(filled),
+                    "░".repeat(10 - filled),
+                    strip.volume * 100.0,
+                ),
+                style,
+            )));
+        }
+        lines.push(Line::from(""));
+        lines.push(Line::from("Up/Down select  Left/Right volume"));
+        lines.push(Line::from("m/Enter mute  s solo  F5 close"));
+        
+        let panel = Paragraph::new(lines)
+            .block(
+                Block::default()
+                    .title("Mixer")
+                    .borders(Borders::ALL)
+                    .border_style(Style::default().fg(Color::Cyan)),
+            );
+        
+        f.render_widget(panel, popup_area);
+    }
+    
+    pub fn toggle_metronome_panel(&mut self) {
+        self.show_metronome = !self.show_metronome;
+    }
+    
+    pub fn toggle_effects(&mut self) {
+        self.show_effects = !self.show_effects;
+    }
+    
     pub fn set_status_message(&mut self, message: String) {
         self.status_message = Some(message);
     }